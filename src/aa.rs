@@ -0,0 +1,99 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// AA tree balancing shared by the trees whose nodes summarize their
+// subtrees, such as the largest end of an interval or the number of nodes.
+// The operations are those of `TreeMap`, with each node that a rotation moves
+// brought back up to date.
+
+use alloc::boxed::Box;
+use core::mem::swap;
+
+// A node of an AA tree. Nodes keep track of their level in the tree,
+// starting at 1 in the leaves and with a red child sharing the level of the
+// parent.
+pub(crate) trait AaNode: Sized {
+    fn level(&self) -> usize;
+    fn set_level(&mut self, level: usize);
+    fn left(&self) -> &Option<Box<Self>>;
+    fn right(&self) -> &Option<Box<Self>>;
+    fn left_mut(&mut self) -> &mut Option<Box<Self>>;
+    fn right_mut(&mut self) -> &mut Option<Box<Self>>;
+
+    // Recomputes what the node records about its subtree from its children,
+    // which must be up to date.
+    fn update(&mut self);
+}
+
+pub(crate) fn level<N: AaNode>(node: &Option<Box<N>>) -> usize {
+    node.as_ref().map_or(0, |x| x.level())
+}
+
+// Remove left horizontal link by rotating right
+pub(crate) fn skew<N: AaNode>(node: &mut Box<N>) {
+    if node.left().as_ref().is_some_and(|x| x.level() == node.level()) {
+        let mut save = node.left_mut().take().unwrap();
+        swap(node.left_mut(), save.right_mut()); // save.right now None
+        swap(node, &mut save);
+        save.update();
+        *node.right_mut() = Some(save);
+        node.update();
+    }
+}
+
+// Remove dual horizontal link by rotating left and increasing level of
+// the parent
+pub(crate) fn split<N: AaNode>(node: &mut Box<N>) {
+    if node.right().as_ref().is_some_and(|x| x.right().as_ref().is_some_and(|y| y.level() == node.level())) {
+        let mut save = node.right_mut().take().unwrap();
+        swap(node.right_mut(), save.left_mut()); // save.left now None
+        let level = save.level();
+        save.set_level(level + 1);
+        swap(node, &mut save);
+        save.update();
+        *node.left_mut() = Some(save);
+        node.update();
+    }
+}
+
+// Restores the balance of `node` after a node was removed below it, once
+// `node` itself is up to date. Rotations keep the set of nodes in a subtree,
+// so only the nodes that they move need updating, which `skew` and `split`
+// do.
+pub(crate) fn rebalance<N: AaNode>(save: &mut Box<N>) {
+    let left_level = level(save.left());
+    let right_level = level(save.right());
+
+    // re-balance, if necessary
+    if left_level < save.level() - 1 || right_level < save.level() - 1 {
+        let save_level = save.level() - 1;
+        save.set_level(save_level);
+
+        if right_level > save_level {
+            for x in save.right_mut().iter_mut() {
+                x.set_level(save_level)
+            }
+        }
+
+        skew(save);
+
+        for right in save.right_mut().iter_mut() {
+            skew(right);
+            for x in right.right_mut().iter_mut() {
+                skew(x)
+            }
+        }
+
+        split(save);
+        for x in save.right_mut().iter_mut() {
+            split(x)
+        }
+    }
+}
@@ -0,0 +1,494 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! An ordered one-to-one map that can be looked up from either side.
+
+use core::default::Default;
+use core::fmt::{self, Debug};
+use core::iter::{self, FusedIterator};
+
+use compare::{Compare, Natural, natural};
+
+use super::Bound;
+use super::map::{self, Forward, TreeMap};
+
+/// An ordered map between left and right values, in which each left value
+/// is paired with at most one right value and vice versa.
+///
+/// The pairs are kept in two trees, one ordered by the left values according
+/// to one comparator and one ordered by the right values according to
+/// another, so that both sides can be looked up and ranged over in order.
+/// Each tree holds its own copy of both values of every pair, which is why
+/// they must be `Clone`.
+///
+/// # Examples
+///
+/// ```rust
+/// use stable_bst::TreeBiMap;
+/// use stable_bst::Bound::{Included, Unbounded};
+///
+/// let mut ids = TreeBiMap::new();
+/// ids.insert(3, "carol");
+/// ids.insert(1, "alice");
+/// ids.insert(2, "bob");
+///
+/// assert_eq!(ids.get_by_left(&2), Some(&"bob"));
+/// assert_eq!(ids.get_by_right(&"carol"), Some(&3));
+/// assert!(ids.range_by_left(Included(&2), Unbounded).eq(vec![(&2, &"bob"), (&3, &"carol")]));
+/// assert!(ids.range_by_right(Unbounded, Included(&"bob")).eq(vec![(&1, &"alice"), (&2, &"bob")]));
+/// ```
+#[derive(Clone)]
+pub struct TreeBiMap<L, R, CL = Natural<L>, CR = Natural<R>>
+    where CL: Compare<L>,
+          CR: Compare<R>
+{
+    left: TreeMap<L, R, CL>,
+    right: TreeMap<R, L, CR>,
+}
+
+/// What to do when an inserted pair conflicts with pairs already in a
+/// `TreeBiMap`, because its left or right value is already paired.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InsertPolicy {
+    /// Removes the conflicting pairs and inserts the new one.
+    Replace,
+    /// Leaves the map unchanged and hands the new pair back.
+    Reject,
+}
+
+/// The pairs that an insertion into a `TreeBiMap` removed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Overwritten<L, R> {
+    /// Neither value was already paired.
+    Neither,
+    /// The left value was paired with another right value, and this was the
+    /// pair.
+    Left(L, R),
+    /// The right value was paired with another left value, and this was the
+    /// pair.
+    Right(L, R),
+    /// The same pair was already in the map.
+    Pair(L, R),
+    /// Both values were paired with others, and these were the pairs of the
+    /// left and the right value, in that order.
+    Both((L, R), (L, R)),
+}
+
+impl<L: Ord, R: Ord> TreeBiMap<L, R> {
+    /// Creates an empty `TreeBiMap` ordered according to the natural order of
+    /// its left and right values.
+    pub fn new() -> TreeBiMap<L, R> {
+        TreeBiMap::with_comparators(natural(), natural())
+    }
+}
+
+impl<L, R, CL, CR> TreeBiMap<L, R, CL, CR>
+    where CL: Compare<L>,
+          CR: Compare<R>
+{
+    /// Creates an empty `TreeBiMap` ordered according to the given
+    /// comparators for its left and right values.
+    pub fn with_comparators(left: CL, right: CR) -> TreeBiMap<L, R, CL, CR> {
+        TreeBiMap {
+            left: TreeMap::with_comparator(left),
+            right: TreeMap::with_comparator(right),
+        }
+    }
+
+    /// Returns the comparator according to which the left values are
+    /// ordered.
+    pub fn left_comparator(&self) -> &CL {
+        self.left.comparator()
+    }
+
+    /// Returns the comparator according to which the right values are
+    /// ordered.
+    pub fn right_comparator(&self) -> &CR {
+        self.right.comparator()
+    }
+
+    /// Returns the number of pairs in the map.
+    pub fn len(&self) -> usize {
+        self.left.len()
+    }
+
+    /// Returns true if the map contains no pairs.
+    pub fn is_empty(&self) -> bool {
+        self.left.is_empty()
+    }
+
+    /// Clears the map, removing all pairs.
+    pub fn clear(&mut self) {
+        self.left.clear();
+        self.right.clear();
+    }
+
+    /// Returns a reference to the right value paired with `left`.
+    pub fn get_by_left<Q: ?Sized>(&self, left: &Q) -> Option<&R>
+        where CL: Compare<Q, L>
+    {
+        self.left.get(left)
+    }
+
+    /// Returns a reference to the left value paired with `right`.
+    pub fn get_by_right<Q: ?Sized>(&self, right: &Q) -> Option<&L>
+        where CR: Compare<Q, R>
+    {
+        self.right.get(right)
+    }
+
+    /// Returns true if `left` is paired with a right value.
+    pub fn contains_left<Q: ?Sized>(&self, left: &Q) -> bool
+        where CL: Compare<Q, L>
+    {
+        self.left.contains_key(left)
+    }
+
+    /// Returns true if `right` is paired with a left value.
+    pub fn contains_right<Q: ?Sized>(&self, right: &Q) -> bool
+        where CR: Compare<Q, R>
+    {
+        self.right.contains_key(right)
+    }
+
+    /// Removes the pair whose left value is `left`, and returns it.
+    pub fn remove_by_left<Q: ?Sized>(&mut self, left: &Q) -> Option<(L, R)>
+        where CL: Compare<Q, L>
+    {
+        let r = self.left.remove(left)?;
+        let l = self.right.remove(&r).expect("the trees of a TreeBiMap are out of sync");
+        Some((l, r))
+    }
+
+    /// Removes the pair whose right value is `right`, and returns it.
+    pub fn remove_by_right<Q: ?Sized>(&mut self, right: &Q) -> Option<(L, R)>
+        where CR: Compare<Q, R>
+    {
+        let l = self.right.remove(right)?;
+        let r = self.left.remove(&l).expect("the trees of a TreeBiMap are out of sync");
+        Some((l, r))
+    }
+
+    /// Returns an iterator over the pairs in ascending order of their left
+    /// values.
+    pub fn iter(&self) -> map::Iter<'_, L, R, Forward, CL> {
+        self.left.iter()
+    }
+
+    /// Returns an iterator over the pairs in ascending order of their right
+    /// values.
+    pub fn iter_by_right(&self) -> IterByRight<'_, L, R> {
+        IterByRight(self.right.iter().without_comparator())
+    }
+
+    /// Returns a double-ended iterator over the pairs whose left values lie
+    /// between `min` and `max`, in ascending order of their left values.
+    pub fn range_by_left<'a, Min: ?Sized, Max: ?Sized>(&'a self,
+                                                       min: Bound<&Min>,
+                                                       max: Bound<&Max>)
+                                                       -> map::Range<'a, L, R, CL>
+        where CL: Compare<Min, L> + Compare<Max, L>
+    {
+        self.left.range(min, max)
+    }
+
+    /// Returns a double-ended iterator over the pairs whose right values lie
+    /// between `min` and `max`, in ascending order of their right values.
+    pub fn range_by_right<'a, Min: ?Sized, Max: ?Sized>(&'a self,
+                                                        min: Bound<&Min>,
+                                                        max: Bound<&Max>)
+                                                        -> RangeByRight<'a, L, R>
+        where CR: Compare<Min, R> + Compare<Max, R>
+    {
+        RangeByRight(self.right.range(min, max).without_comparator())
+    }
+}
+
+impl<L, R, CL, CR> TreeBiMap<L, R, CL, CR>
+    where L: Clone,
+          R: Clone,
+          CL: Compare<L>,
+          CR: Compare<R>
+{
+    /// Inserts a pair, replacing any pairs that its left or right value is
+    /// already part of, and returns the pairs that were removed.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::TreeBiMap;
+    /// use stable_bst::bimap::Overwritten;
+    ///
+    /// let mut map = TreeBiMap::new();
+    /// assert_eq!(map.insert(1, 'a'), Overwritten::Neither);
+    /// assert_eq!(map.insert(2, 'b'), Overwritten::Neither);
+    /// assert_eq!(map.insert(1, 'b'), Overwritten::Both((1, 'a'), (2, 'b')));
+    /// assert_eq!(map.len(), 1);
+    /// ```
+    pub fn insert(&mut self, left: L, right: R) -> Overwritten<L, R> {
+        match self.insert_with_policy(left, right, InsertPolicy::Replace) {
+            Ok(overwritten) => overwritten,
+            Err(_) => unreachable!(),
+        }
+    }
+
+    /// Inserts a pair if neither of its values is already paired, and hands
+    /// it back otherwise.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::TreeBiMap;
+    ///
+    /// let mut map = TreeBiMap::new();
+    /// assert_eq!(map.try_insert(1, 'a'), Ok(()));
+    /// assert_eq!(map.try_insert(2, 'a'), Err((2, 'a')));
+    /// assert_eq!(map.get_by_right(&'a'), Some(&1));
+    /// ```
+    pub fn try_insert(&mut self, left: L, right: R) -> Result<(), (L, R)> {
+        self.insert_with_policy(left, right, InsertPolicy::Reject).map(|_| ())
+    }
+
+    /// Inserts a pair, resolving conflicts with the pairs already in the map
+    /// according to `policy`.
+    ///
+    /// Returns the pairs that were removed to make room for the new one, or
+    /// the new pair if it was rejected.
+    pub fn insert_with_policy(&mut self,
+                              left: L,
+                              right: R,
+                              policy: InsertPolicy)
+                              -> Result<Overwritten<L, R>, (L, R)> {
+        let overwritten = match policy {
+            InsertPolicy::Reject => {
+                if self.contains_left(&left) || self.contains_right(&right) {
+                    return Err((left, right));
+                }
+                Overwritten::Neither
+            }
+            InsertPolicy::Replace => {
+                match (self.remove_by_left(&left), self.remove_by_right(&right)) {
+                    (None, None) => Overwritten::Neither,
+                    (Some(pair), None) => {
+                        if self.right.comparator().compares_eq(&pair.1, &right) {
+                            Overwritten::Pair(pair.0, pair.1)
+                        } else {
+                            Overwritten::Left(pair.0, pair.1)
+                        }
+                    }
+                    (None, Some(pair)) => Overwritten::Right(pair.0, pair.1),
+                    (Some(l), Some(r)) => Overwritten::Both(l, r),
+                }
+            }
+        };
+        self.left.insert(left.clone(), right.clone());
+        self.right.insert(right, left);
+        Ok(overwritten)
+    }
+}
+
+impl<L, R: PartialEq, CL, CR> PartialEq for TreeBiMap<L, R, CL, CR>
+    where CL: Compare<L>,
+          CR: Compare<R>
+{
+    fn eq(&self, other: &TreeBiMap<L, R, CL, CR>) -> bool {
+        self.left == other.left
+    }
+}
+
+impl<L, R: Eq, CL, CR> Eq for TreeBiMap<L, R, CL, CR>
+    where CL: Compare<L>,
+          CR: Compare<R>
+{
+}
+
+impl<L: Debug, R: Debug, CL, CR> Debug for TreeBiMap<L, R, CL, CR>
+    where CL: Compare<L>,
+          CR: Compare<R>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.left.fmt(f)
+    }
+}
+
+impl<L, R, CL, CR> Default for TreeBiMap<L, R, CL, CR>
+    where CL: Compare<L> + Default,
+          CR: Compare<R> + Default
+{
+    fn default() -> TreeBiMap<L, R, CL, CR> {
+        TreeBiMap::with_comparators(Default::default(), Default::default())
+    }
+}
+
+impl<L, R, CL, CR> iter::FromIterator<(L, R)> for TreeBiMap<L, R, CL, CR>
+    where L: Clone,
+          R: Clone,
+          CL: Compare<L> + Default,
+          CR: Compare<R> + Default
+{
+    fn from_iter<I: IntoIterator<Item = (L, R)>>(iter: I) -> TreeBiMap<L, R, CL, CR> {
+        let mut map: TreeBiMap<L, R, CL, CR> = Default::default();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<L, R, CL, CR> Extend<(L, R)> for TreeBiMap<L, R, CL, CR>
+    where L: Clone,
+          R: Clone,
+          CL: Compare<L>,
+          CR: Compare<R>
+{
+    fn extend<I: IntoIterator<Item = (L, R)>>(&mut self, iter: I) {
+        for (left, right) in iter {
+            self.insert(left, right);
+        }
+    }
+}
+
+impl<'a, L, R, CL, CR> IntoIterator for &'a TreeBiMap<L, R, CL, CR>
+    where CL: Compare<L>,
+          CR: Compare<R>
+{
+    type Item = (&'a L, &'a R);
+    type IntoIter = map::Iter<'a, L, R, Forward, CL>;
+    fn into_iter(self) -> map::Iter<'a, L, R, Forward, CL> {
+        self.iter()
+    }
+}
+
+/// Lazy forward iterator over the pairs of a `TreeBiMap` in the order of
+/// their right values.
+pub struct IterByRight<'a, L: 'a, R: 'a>(map::Iter<'a, R, L, Forward, ()>);
+
+/// Lazy double-ended iterator over a range of the pairs of a `TreeBiMap` in
+/// the order of their right values.
+pub struct RangeByRight<'a, L: 'a, R: 'a>(map::Range<'a, R, L, ()>);
+
+impl<'a, L, R> Iterator for IterByRight<'a, L, R> {
+    type Item = (&'a L, &'a R);
+
+    #[inline]
+    fn next(&mut self) -> Option<(&'a L, &'a R)> {
+        self.0.next().map(|(r, l)| (l, r))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, L, R> ExactSizeIterator for IterByRight<'a, L, R> {}
+impl<'a, L, R> FusedIterator for IterByRight<'a, L, R> {}
+
+impl<'a, L, R> Iterator for RangeByRight<'a, L, R> {
+    type Item = (&'a L, &'a R);
+
+    #[inline]
+    fn next(&mut self) -> Option<(&'a L, &'a R)> {
+        self.0.next().map(|(r, l)| (l, r))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, L, R> DoubleEndedIterator for RangeByRight<'a, L, R> {
+    #[inline]
+    fn next_back(&mut self) -> Option<(&'a L, &'a R)> {
+        self.0.next_back().map(|(r, l)| (l, r))
+    }
+}
+
+impl<'a, L, R> ExactSizeIterator for RangeByRight<'a, L, R> {}
+impl<'a, L, R> FusedIterator for RangeByRight<'a, L, R> {}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use rand::{self, Rng};
+
+    use super::{InsertPolicy, Overwritten, TreeBiMap};
+    use super::super::Bound::{Excluded, Included, Unbounded};
+
+    #[test]
+    fn test_insert_policies() {
+        let mut map = TreeBiMap::new();
+        assert_eq!(map.insert(1, 'a'), Overwritten::Neither);
+        assert_eq!(map.insert(1, 'a'), Overwritten::Pair(1, 'a'));
+        assert_eq!(map.insert(1, 'b'), Overwritten::Left(1, 'a'));
+        assert_eq!(map.insert(2, 'b'), Overwritten::Right(1, 'b'));
+        assert_eq!(map.insert(3, 'c'), Overwritten::Neither);
+        assert_eq!(map.insert_with_policy(3, 'd', InsertPolicy::Reject), Err((3, 'd')));
+        assert_eq!(map.insert_with_policy(4, 'c', InsertPolicy::Reject), Err((4, 'c')));
+        assert_eq!(map.insert_with_policy(4, 'd', InsertPolicy::Reject), Ok(Overwritten::Neither));
+        assert_eq!(map.insert(2, 'c'), Overwritten::Both((2, 'b'), (3, 'c')));
+        assert_eq!(format!("{:?}", map), "{2: 'c', 4: 'd'}");
+        assert_eq!(map.remove_by_right(&'d'), Some((4, 'd')));
+        assert_eq!(map.remove_by_left(&4), None);
+        assert_eq!(map.remove_by_left(&2), Some((2, 'c')));
+        assert!(map.is_empty() && !map.contains_right(&'c'));
+    }
+
+    #[test]
+    fn test_ordered_both_ways() {
+        let map: TreeBiMap<i32, char> = vec![(1, 'd'), (2, 'c'), (3, 'b'), (4, 'a')]
+            .into_iter()
+            .collect();
+        assert!(map.iter().map(|(l, _)| *l).eq(1..5));
+        assert!(map.iter_by_right().map(|(_, r)| *r).eq("abcd".chars()));
+        assert_eq!(map.iter_by_right().len(), 4);
+        assert!(map.range_by_left(Excluded(&1), Included(&3)).eq(vec![(&2, &'c'), (&3, &'b')]));
+        let range = map.range_by_right(Included(&'b'), Unbounded);
+        assert!(range.rev().eq(vec![(&1, &'d'), (&2, &'c'), (&3, &'b')]));
+    }
+
+    #[test]
+    fn test_random_sync() {
+        let mut rng = rand::weak_rng();
+        let mut map = TreeBiMap::new();
+        let mut by_left = BTreeMap::new();
+        let mut by_right = BTreeMap::new();
+
+        for _ in 0..2000 {
+            let (l, r) = (rng.gen_range(0, 50u32), rng.gen_range(0, 50u32));
+            match rng.gen_range(0, 3) {
+                0 => {
+                    let removed = map.remove_by_left(&l);
+                    assert_eq!(removed.map(|p| p.1), by_left.remove(&l));
+                    if let Some((_, r)) = removed {
+                        by_right.remove(&r);
+                    }
+                }
+                _ => {
+                    map.insert(l, r);
+                    if let Some(old) = by_left.insert(l, r) {
+                        by_right.remove(&old);
+                    }
+                    if let Some(old) = by_right.insert(r, l) {
+                        if old != l {
+                            by_left.remove(&old);
+                        }
+                    }
+                }
+            }
+            assert_eq!(map.len(), by_left.len());
+        }
+
+        assert!(map.iter().eq(by_left.iter()));
+        assert!(map.iter_by_right().map(|(l, r)| (r, l)).eq(by_right.iter()));
+        assert_eq!(map.left.check_invariants(), Ok(()));
+        assert_eq!(map.right.check_invariants(), Ok(()));
+    }
+}
@@ -0,0 +1,303 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A comparator that orders records by a key projected out of them.
+//!
+//! Unlike a comparator that implements `Compare<Id, Rec>`, this one cannot
+//! look records up by a bare id: `map.get(&id)` does not compile. Wrap the id
+//! in a `Key` instead, as in `map.get(&Key::new(&id))`, and spell out the key
+//! type of unbounded range ends, as in `Unbounded::<&Key<_, Id>>`.
+//!
+//! That is because the comparator could not implement both `Compare<Rec>`
+//! and `Compare<Id, Rec>` for every `Rec` and `Id`: the two would conflict
+//! whenever `Id` and `Rec` are the same type.
+//!
+//! # Examples
+//!
+//! `by_key(|r: &Rec| &r.id)` orders `Rec`s by their `id` field, so that a
+//! `TreeMap` or `TreeSet` of records can be searched by id alone through
+//! `get`, `remove`, `range` or any other lookup:
+//!
+//! ```rust
+//! use stable_bst::TreeMap;
+//! use stable_bst::Bound::{Included, Unbounded};
+//! use stable_bst::by_key::{by_key, Key};
+//!
+//! struct Rec {
+//!     id: u32,
+//!     name: &'static str,
+//! }
+//!
+//! let mut map = TreeMap::with_comparator(by_key(|r: &Rec| &r.id));
+//! map.insert(Rec { id: 2, name: "b" }, 20);
+//! map.insert(Rec { id: 1, name: "a" }, 10);
+//! map.insert(Rec { id: 3, name: "c" }, 30);
+//!
+//! // Not `map.get(&2)`: the id goes through `Key`.
+//! assert_eq!(map.get(&Key::new(&2)), Some(&20));
+//! assert_eq!(map.remove(&Key::new(&1)), Some(10));
+//!
+//! let names: Vec<_> = map.range(Included(&Key::new(&2)), Unbounded::<&Key<_, u32>>)
+//!                        .map(|(r, _)| r.name)
+//!                        .collect();
+//! assert_eq!(names, ["b", "c"]);
+//! ```
+//!
+//! Looking up a bare id is rejected:
+//!
+//! ```compile_fail
+//! use stable_bst::TreeMap;
+//! use stable_bst::by_key::by_key;
+//!
+//! let mut map = TreeMap::with_comparator(by_key(|r: &(u32, char)| &r.0));
+//! map.insert((2, 'b'), 20);
+//! map.get(&2);
+//! ```
+
+use core::cmp::Ordering;
+use core::fmt::{self, Debug};
+use core::hash::Hasher;
+use core::marker::PhantomData;
+
+use compare::{Compare, Natural, natural};
+
+use super::HashCompare;
+
+/// A comparator that orders values of type `T` by comparing the keys of type
+/// `K` that `f` projects out of them with `cmp`.
+///
+/// It implements `Compare<T>` for storing records, and `Compare<Key<T, Q>, T>`
+/// for looking them up by key.
+pub struct ByKey<T, K: ?Sized, F, C = Natural<K>> {
+    f: F,
+    cmp: C,
+    marker: PhantomData<fn(&T) -> &K>,
+}
+
+/// Creates a comparator that orders values by the key that `f` projects out
+/// of them, in the natural order of the key.
+pub fn by_key<T, K: Ord + ?Sized, F>(f: F) -> ByKey<T, K, F>
+    where F: Fn(&T) -> &K
+{
+    by_key_with(f, natural())
+}
+
+/// Creates a comparator that orders values by the key that `f` projects out
+/// of them, comparing keys with `cmp`.
+///
+/// # Examples
+///
+/// ```rust
+/// extern crate compare;
+/// extern crate stable_bst;
+///
+/// use compare::{Compare, natural};
+/// use stable_bst::TreeSet;
+/// use stable_bst::by_key::{by_key_with, Key};
+///
+/// # fn main() {
+/// let mut set = TreeSet::with_comparator(by_key_with(|p: &(u32, char)| &p.0, natural().rev()));
+/// set.extend(vec![(1, 'a'), (3, 'c'), (2, 'b')]);
+///
+/// assert!(set.iter().cloned().eq(vec![(3, 'c'), (2, 'b'), (1, 'a')]));
+/// assert!(set.contains(&Key::new(&2)));
+/// # }
+/// ```
+#[allow(clippy::redundant_field_names)]
+pub fn by_key_with<T, K: ?Sized, F, C>(f: F, cmp: C) -> ByKey<T, K, F, C>
+    where F: Fn(&T) -> &K,
+          C: Compare<K>
+{
+    ByKey {
+        f: f,
+        cmp: cmp,
+        marker: PhantomData,
+    }
+}
+
+impl<T, K: ?Sized, F, C> ByKey<T, K, F, C> {
+    /// Returns the comparator that the projected keys are compared with.
+    pub fn key_comparator(&self) -> &C {
+        &self.cmp
+    }
+}
+
+impl<T, K: ?Sized, F: Clone, C: Clone> Clone for ByKey<T, K, F, C> {
+    fn clone(&self) -> ByKey<T, K, F, C> {
+        ByKey {
+            f: self.f.clone(),
+            cmp: self.cmp.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, K: ?Sized, F: Copy, C: Copy> Copy for ByKey<T, K, F, C> {}
+
+impl<T, K: ?Sized, F, C: Debug> Debug for ByKey<T, K, F, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ByKey").field("cmp", &self.cmp).finish()
+    }
+}
+
+impl<T, K: ?Sized, F, C> Compare<T> for ByKey<T, K, F, C>
+    where F: Fn(&T) -> &K,
+          C: Compare<K>
+{
+    fn compare(&self, l: &T, r: &T) -> Ordering {
+        self.cmp.compare((self.f)(l), (self.f)(r))
+    }
+}
+
+impl<'a, T, K: ?Sized, Q: ?Sized, F, C> Compare<Key<'a, T, Q>, T> for ByKey<T, K, F, C>
+    where F: Fn(&T) -> &K,
+          C: Compare<Q, K>
+{
+    fn compare(&self, l: &Key<'a, T, Q>, r: &T) -> Ordering {
+        self.cmp.compare(l.key, (self.f)(r))
+    }
+}
+
+impl<T, K: ?Sized, F, C> HashCompare<T> for ByKey<T, K, F, C>
+    where F: Fn(&T) -> &K,
+          C: HashCompare<K>
+{
+    fn hash_key<H: Hasher>(&self, key: &T, state: &mut H) {
+        self.cmp.hash_key((self.f)(key), state);
+    }
+}
+
+/// A key to look up values of type `T` by in a collection ordered by a
+/// `ByKey` comparator.
+///
+/// `Q` is usually the projected key type itself, but may be any type that the
+/// key comparator can compare with it, such as `str` for `String` keys.
+pub struct Key<'a, T, Q: 'a + ?Sized> {
+    key: &'a Q,
+    marker: PhantomData<fn(&T)>,
+}
+
+impl<'a, T, Q: ?Sized> Key<'a, T, Q> {
+    /// Wraps `key` for a lookup.
+    #[allow(clippy::redundant_field_names)]
+    pub fn new(key: &'a Q) -> Key<'a, T, Q> {
+        Key {
+            key: key,
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns the wrapped key.
+    pub fn get(&self) -> &'a Q {
+        self.key
+    }
+}
+
+impl<'a, T, Q: ?Sized> Clone for Key<'a, T, Q> {
+    fn clone(&self) -> Key<'a, T, Q> {
+        *self
+    }
+}
+
+impl<'a, T, Q: ?Sized> Copy for Key<'a, T, Q> {}
+
+impl<'a, T, Q: Debug + ?Sized> Debug for Key<'a, T, Q> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Key").field(&self.key).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use compare::{Compare, natural};
+
+    use super::{by_key, by_key_with, Key};
+    use super::super::Bound::{Excluded, Included, Unbounded};
+    use super::super::{TreeMap, TreeSet};
+
+    #[derive(Debug, PartialEq)]
+    struct Rec {
+        id: u32,
+        name: String,
+    }
+
+    #[allow(clippy::redundant_field_names)]
+    fn rec(id: u32, name: &str) -> Rec {
+        Rec {
+            id: id,
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let cmp = by_key(|r: &Rec| &r.id);
+        assert!(cmp.compares_lt(&rec(1, "z"), &rec(2, "a")));
+        assert!(cmp.compares_eq(&rec(1, "z"), &rec(1, "a")));
+        assert!(cmp.compares_gt(&Key::new(&3), &rec(2, "a")));
+        assert!(cmp.compares_eq(&Key::new(&2), &rec(2, "a")));
+    }
+
+    #[test]
+    fn test_map_lookups() {
+        let mut map = TreeMap::with_comparator(by_key(|r: &Rec| &r.id));
+        for i in (0..10).rev() {
+            assert_eq!(map.insert(rec(i * 10, "x"), i), None);
+        }
+        // Records with the same id are the same key.
+        assert_eq!(map.insert(rec(50, "y"), 55), Some(5));
+
+        assert_eq!(map.get(&Key::new(&50)), Some(&55));
+        assert_eq!(map.get(&Key::new(&51)), None);
+        assert!(map.contains_key(&Key::new(&90)));
+        *map.get_mut(&Key::new(&20)).unwrap() = 22;
+        assert_eq!(map[&Key::new(&20)], 22);
+        assert_eq!(map.remove(&Key::new(&0)), Some(0));
+        assert_eq!(map.remove(&Key::new(&0)), None);
+
+        let ids: Vec<u32> = map.range(Excluded(&Key::new(&20)), Included(&Key::new(&50)))
+                               .map(|(r, _)| r.id)
+                               .collect();
+        assert_eq!(ids, [30, 40, 50]);
+        let ids: Vec<u32> = map.range(Unbounded::<&Key<_, u32>>, Excluded(&Key::new(&35)))
+                               .map(|(r, _)| r.id)
+                               .collect();
+        assert_eq!(ids, [10, 20, 30]);
+        for (_, v) in map.range_mut(Included(&Key::new(&80)), Unbounded::<&Key<_, u32>>) {
+            *v = 0;
+        }
+        assert_eq!(map.get(&Key::new(&90)), Some(&0));
+    }
+
+    #[test]
+    fn test_borrowed_key() {
+        // Records keyed by a `String`, looked up by `&str`.
+        let mut set = TreeSet::with_comparator(by_key_with(|r: &Rec| &r.name, natural::<str>().borrowing()));
+        set.insert(rec(1, "b"));
+        set.insert(rec(2, "a"));
+        assert!(set.contains(&Key::new("a")));
+        assert!(!set.contains(&Key::new("c")));
+        assert_eq!(set.iter().map(|r| r.id).collect::<Vec<_>>(), [2, 1]);
+    }
+
+    #[test]
+    fn test_fn_pointer() {
+        // With a function pointer, the comparator type can be named.
+        fn id(r: &Rec) -> &u32 {
+            &r.id
+        }
+        type ById = super::ByKey<Rec, u32, fn(&Rec) -> &u32>;
+
+        let mut map: TreeMap<Rec, (), ById> = TreeMap::with_comparator(by_key(id as fn(&Rec) -> &u32));
+        map.insert(rec(1, "a"), ());
+        let copy = *map.comparator();
+        assert!(copy.compares_lt(&rec(1, "a"), &rec(2, "a")));
+    }
+}
@@ -0,0 +1,106 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Hashing keys consistently with the comparator that orders them.
+
+use core::borrow::Borrow;
+use core::default::Default;
+use core::hash::{Hash, Hasher};
+
+use compare::{Borrowing, Compare, Natural, Rev, Then};
+
+/// A comparator that can hash keys consistently with its own notion of
+/// equality: two keys that compare `Equal` must feed the same data to the
+/// hasher.
+///
+/// `TreeMap` and `TreeSet` consider two keys equal when their comparator says
+/// so, which need not agree with the keys' own `Eq` and `Hash`, so they are
+/// hashed through this trait rather than through `Hash` for the keys.
+///
+/// # Examples
+///
+/// ```rust
+/// extern crate compare;
+/// extern crate stable_bst;
+///
+/// use std::cmp::Ordering;
+/// use std::hash::{Hash, Hasher};
+/// use compare::Compare;
+/// use stable_bst::{HashCompare, TreeSet};
+///
+/// #[derive(Clone, Copy, Default)]
+/// struct CaseInsensitive;
+///
+/// impl Compare<String> for CaseInsensitive {
+///     fn compare(&self, l: &String, r: &String) -> Ordering {
+///         l.to_lowercase().cmp(&r.to_lowercase())
+///     }
+/// }
+///
+/// impl HashCompare<String> for CaseInsensitive {
+///     fn hash_key<H: Hasher>(&self, key: &String, state: &mut H) {
+///         key.to_lowercase().hash(state);
+///     }
+/// }
+///
+/// # fn main() {
+/// let a: TreeSet<String, CaseInsensitive> = vec!["A".to_string()].into_iter().collect();
+/// let b: TreeSet<String, CaseInsensitive> = vec!["a".to_string()].into_iter().collect();
+/// assert!(a == b);
+///
+/// let mut outer = TreeSet::new();
+/// outer.insert(a);
+/// # }
+/// ```
+pub trait HashCompare<K: ?Sized>: Compare<K> {
+    /// Feeds `key` into `state`.
+    fn hash_key<H: Hasher>(&self, key: &K, state: &mut H);
+}
+
+impl<K: Ord + Hash + ?Sized> HashCompare<K> for Natural<K> {
+    fn hash_key<H: Hasher>(&self, key: &K, state: &mut H) {
+        key.hash(state);
+    }
+}
+
+/// Reversing a comparator keeps its notion of equality. `Rev` does not give
+/// access to the comparator it wraps, so that comparator is recreated with
+/// `Default`.
+impl<K: ?Sized, C> HashCompare<K> for Rev<C>
+    where C: HashCompare<K> + Default
+{
+    fn hash_key<H: Hasher>(&self, key: &K, state: &mut H) {
+        C::default().hash_key(key, state);
+    }
+}
+
+/// As for `Rev`, the comparator that `Borrowing` wraps is recreated with
+/// `Default`.
+impl<K: ?Sized, Kb: ?Sized, C> HashCompare<K> for Borrowing<C, Kb>
+    where K: Borrow<Kb>,
+          C: HashCompare<Kb> + Default
+{
+    fn hash_key<H: Hasher>(&self, key: &K, state: &mut H) {
+        C::default().hash_key(key.borrow(), state);
+    }
+}
+
+/// Keys equal under `Then` are equal under both comparators, so both of
+/// their hashes are fed in. The wrapped comparators are recreated with
+/// `Default`.
+impl<K: ?Sized, C, D> HashCompare<K> for Then<C, D>
+    where C: HashCompare<K> + Default,
+          D: HashCompare<K> + Default
+{
+    fn hash_key<H: Hasher>(&self, key: &K, state: &mut H) {
+        C::default().hash_key(key, state);
+        D::default().hash_key(key, state);
+    }
+}
@@ -0,0 +1,317 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A ring for consistent hashing.
+
+use alloc::vec::Vec;
+use core::default::Default;
+use core::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+
+use super::Bound::{Excluded, Included, Unbounded};
+use super::map::TreeMap;
+
+/// A ring for consistent hashing, which assigns each hash to the node whose
+/// point on the ring is the first at or after it, wrapping around to the
+/// first point past the largest.
+///
+/// Each node is placed at several points, its virtual nodes, by hashing it
+/// together with the numbers of its replicas, which spreads the key space
+/// more evenly between the nodes. Adding or removing a node only moves the
+/// keys of the arcs that its points own.
+///
+/// # Examples
+///
+/// ```rust
+/// use stable_bst::HashRing;
+///
+/// let mut ring = HashRing::new(64);
+/// ring.add_node("a");
+/// ring.add_node("b");
+/// ring.add_node("c");
+///
+/// let owner = *ring.lookup(0x1234_5678_9abc_def0).unwrap();
+/// let replicas = ring.lookup_n(0x1234_5678_9abc_def0, 2);
+/// assert_eq!(*replicas[0], owner);
+/// assert!(*replicas[1] != owner);
+///
+/// let total: f64 = ring.ownership().iter().map(|&(_, share)| share).sum();
+/// assert!((total - 1.0).abs() < 1e-9);
+/// ```
+#[derive(Clone)]
+pub struct HashRing<N: Ord, S = BuildHasherDefault<Fnv1aHasher>> {
+    ring: TreeMap<u64, N>,
+    nodes: Vec<N>,
+    // The index of each node in `nodes`.
+    indices: TreeMap<N, usize>,
+    replicas: usize,
+    hasher: S,
+}
+
+impl<N: Hash + Ord + Clone> HashRing<N> {
+    /// Creates an empty `HashRing` that places each node at `replicas`
+    /// points, chosen with a hash function that is the same across runs and
+    /// builds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `replicas` is zero.
+    pub fn new(replicas: usize) -> HashRing<N> {
+        HashRing::with_hasher(replicas, Default::default())
+    }
+}
+
+impl<N, S> HashRing<N, S>
+    where N: Hash + Ord + Clone,
+          S: BuildHasher
+{
+    /// Creates an empty `HashRing` that places each node at `replicas`
+    /// points, chosen with hashers built by `hasher`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `replicas` is zero.
+    #[allow(clippy::redundant_field_names)]
+    pub fn with_hasher(replicas: usize, hasher: S) -> HashRing<N, S> {
+        assert!(replicas > 0, "a node needs at least one point on the ring");
+        HashRing {
+            ring: TreeMap::new(),
+            nodes: vec![],
+            indices: TreeMap::new(),
+            replicas: replicas,
+            hasher: hasher,
+        }
+    }
+
+    /// Returns the number of nodes on the ring.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns true if the ring has no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Returns the nodes on the ring, in the order they were added.
+    pub fn nodes(&self) -> &[N] {
+        &self.nodes
+    }
+
+    /// Returns true if `node` is on the ring.
+    pub fn contains_node(&self, node: &N) -> bool {
+        self.indices.contains_key(node)
+    }
+
+    // The point of the ring at which `node` places its `replica`th virtual
+    // node.
+    fn point(&self, node: &N, replica: usize) -> u64 {
+        let mut state = self.hasher.build_hasher();
+        node.hash(&mut state);
+        (replica as u64).hash(&mut state);
+        state.finish()
+    }
+
+    /// Adds `node` to the ring, and returns true if it was not already on it.
+    ///
+    /// In the unlikely case that one of its points is already taken by
+    /// another node, that node keeps the point.
+    pub fn add_node(&mut self, node: N) -> bool {
+        if self.contains_node(&node) {
+            return false;
+        }
+        for replica in 0..self.replicas {
+            let point = self.point(&node, replica);
+            self.ring.get_or_insert(point, || node.clone());
+        }
+        self.indices.insert(node.clone(), self.nodes.len());
+        self.nodes.push(node);
+        true
+    }
+
+    /// Removes `node` from the ring, and returns true if it was on it.
+    ///
+    /// The nodes added after it move down a place in `nodes`, which takes
+    /// O(n) time for n nodes.
+    pub fn remove_node(&mut self, node: &N) -> bool {
+        let index = match self.indices.remove(node) {
+            Some(index) => index,
+            None => return false,
+        };
+        self.nodes.remove(index);
+        for (_, i) in self.indices.iter_mut() {
+            if *i > index {
+                *i -= 1;
+            }
+        }
+        for replica in 0..self.replicas {
+            let point = self.point(node, replica);
+            if self.ring.get(&point) == Some(node) {
+                self.ring.remove(&point);
+            }
+        }
+        true
+    }
+
+    /// Returns the node that owns `hash`, that of the first point at or after
+    /// it on the ring, or `None` if the ring is empty.
+    pub fn lookup(&self, hash: u64) -> Option<&N> {
+        self.ring
+            .range(Included(&hash), Unbounded)
+            .next()
+            .or_else(|| self.ring.first_key_value())
+            .map(|(_, node)| node)
+    }
+
+    /// Returns up to `n` distinct nodes in the order that their points follow
+    /// `hash` around the ring, starting with the node that owns it. This is
+    /// the usual choice of the nodes to replicate a key to.
+    pub fn lookup_n(&self, hash: u64, n: usize) -> Vec<&N> {
+        let n = n.min(self.nodes.len());
+        let mut found: Vec<&N> = Vec::with_capacity(n);
+        let after = self.ring.range(Included(&hash), Unbounded);
+        let before = self.ring.range(Unbounded, Excluded(&hash));
+        for (_, node) in after.chain(before) {
+            if found.len() == n {
+                break;
+            }
+            if !found.contains(&node) {
+                found.push(node);
+            }
+        }
+        found
+    }
+
+    /// Returns each node together with the fraction of the hash space that
+    /// it owns, in the order the nodes were added. The fractions add up to 1
+    /// unless the ring is empty.
+    pub fn ownership(&self) -> Vec<(&N, f64)> {
+        // The point at `p` owns the hashes after the previous point, up to
+        // and including `p`.
+        let mut owned: Vec<u128> = vec![0; self.nodes.len()];
+        let mut previous = self.ring.last_key_value().map(|(&point, _)| point);
+        for (&point, node) in self.ring.iter() {
+            let arc = match point.wrapping_sub(previous.unwrap()) {
+                0 => 1 << 64,
+                arc => arc as u128,
+            };
+            previous = Some(point);
+            owned[self.indices[node]] += arc;
+        }
+        self.nodes
+            .iter()
+            .zip(owned)
+            .map(|(node, arc)| (node, arc as f64 / (1u128 << 64) as f64))
+            .collect()
+    }
+}
+
+/// The 64-bit FNV-1a hash function, followed by a final mixing step to spread
+/// short inputs over all the bits.
+///
+/// It is the default hasher of a `HashRing`, since unlike the standard
+/// library's hashers it gives the same results across runs and builds on
+/// platforms of the same word size and endianness.
+#[derive(Clone, Copy, Debug)]
+pub struct Fnv1aHasher(u64);
+
+impl Default for Fnv1aHasher {
+    fn default() -> Fnv1aHasher {
+        Fnv1aHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1aHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        // The finalizer of SplitMix64.
+        let mut x = self.0;
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        x ^ (x >> 31)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{self, Rng};
+
+    use super::HashRing;
+
+    #[test]
+    fn test_wraparound() {
+        let mut ring = HashRing::new(1);
+        assert_eq!(ring.lookup(5), None);
+        assert!(ring.lookup_n(5, 3).is_empty());
+        assert_eq!(ring.ownership(), vec![]);
+
+        ring.add_node(1u32);
+        assert_eq!(ring.ownership(), vec![(&1, 1.0)]);
+        assert!(!ring.add_node(1));
+        ring.add_node(2);
+        let mut points: Vec<(u64, u32)> = ring.ring.iter().map(|(&p, &n)| (p, n)).collect();
+        points.sort();
+        let (first, last) = (points[0], points[1]);
+
+        assert_eq!(ring.lookup(0), Some(&first.1));
+        assert_eq!(ring.lookup(first.0), Some(&first.1));
+        assert_eq!(ring.lookup(first.0 + 1), Some(&last.1));
+        assert_eq!(ring.lookup(last.0 + 1), Some(&first.1));
+        assert_eq!(ring.lookup(u64::MAX), Some(&first.1));
+        assert_eq!(ring.lookup_n(last.0 + 1, 5), vec![&first.1, &last.1]);
+
+        let shares = ring.ownership();
+        let first_share = (first.0 as f64 + (u64::MAX - last.0) as f64 + 1.0) / 2f64.powi(64);
+        let index = if first.1 == 1 { 0 } else { 1 };
+        assert!((shares[index].1 - first_share).abs() < 1e-9);
+
+        assert!(ring.remove_node(&1));
+        assert!(!ring.remove_node(&1));
+        assert_eq!(ring.lookup(first.0), Some(&2));
+        assert_eq!(ring.nodes(), &[2]);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // too slow
+    fn test_consistency() {
+        let mut rng = rand::weak_rng();
+        let mut ring = HashRing::new(100);
+        for node in 0..10u32 {
+            ring.add_node(node);
+        }
+        assert_eq!(ring.ring.len(), 1000);
+        for &(_, share) in &ring.ownership() {
+            assert!(share > 0.05 && share < 0.2, "unbalanced share {}", share);
+        }
+
+        let hashes: Vec<u64> = (0..1000).map(|_| rng.gen()).collect();
+        let before: Vec<u32> = hashes.iter().map(|&h| *ring.lookup(h).unwrap()).collect();
+        ring.remove_node(&3);
+        assert!(!ring.contains_node(&3) && ring.contains_node(&9));
+        assert_eq!(ring.nodes(), &[0, 1, 2, 4, 5, 6, 7, 8, 9]);
+        let shares = ring.ownership();
+        assert!(shares.iter().map(|&(&node, _)| node).eq(ring.nodes().iter().cloned()));
+        assert!(shares.iter().all(|&(_, share)| share > 0.05));
+        for (&hash, &owner) in hashes.iter().zip(&before) {
+            let now = *ring.lookup(hash).unwrap();
+            assert!(now == owner || owner == 3);
+            let replicas = ring.lookup_n(hash, 3);
+            assert_eq!(*replicas[0], now);
+            assert!(replicas[0] != replicas[1] && replicas[1] != replicas[2] && replicas[0] != replicas[2]);
+        }
+        ring.add_node(3);
+        assert!(hashes.iter().zip(&before).all(|(&h, &owner)| *ring.lookup(h).unwrap() == owner));
+    }
+}
@@ -0,0 +1,569 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A map from possibly overlapping half-open intervals to values, with
+//! queries for the intervals that overlap a range or contain a point.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cmp::Ordering::{self, Equal, Greater, Less};
+use core::default::Default;
+use core::fmt::{self, Debug};
+use core::iter::{self, FusedIterator};
+use core::mem::{replace, swap};
+use core::ops;
+
+use super::Bound::{self, Excluded, Included};
+use super::aa::{self, AaNode};
+
+/// A map from half-open intervals to values, in which intervals may overlap.
+///
+/// The intervals are kept in an AA tree ordered by their starts and then by
+/// their ends, whose nodes also record the largest end in their subtree. That
+/// lets `overlapping` and `containing` skip every subtree that holds no
+/// interval reaching their range or point. Every subtree they do enter holds
+/// a result, so each result costs at most one path from the root, and they
+/// take O(min(n, k log n)) time for k results.
+///
+/// This falls short of the O(log n + k) of a dedicated structure such as a
+/// priority search tree. They only take O(log n + k) when the results sit
+/// next to each other in the order of the starts, that is when few of the
+/// intervals that start before the query's end are interleaved with them
+/// while ending before the query's start. Empty intervals may be stored, but
+/// overlap nothing.
+///
+/// # Examples
+///
+/// ```rust
+/// use stable_bst::IntervalTreeMap;
+///
+/// let mut meetings = IntervalTreeMap::new();
+/// meetings.insert(9..11, "standup");
+/// meetings.insert(10..12, "review");
+/// meetings.insert(13..14, "lunch");
+///
+/// assert!(meetings.containing(&10).map(|(_, v)| *v).eq(vec!["standup", "review"]));
+/// assert!(meetings.overlapping(11..14).eq(vec![(&10..&12, &"review"), (&13..&14, &"lunch")]));
+/// assert_eq!(meetings.remove_interval(&(9..11)), Some("standup"));
+/// assert!(meetings.containing(&9).next().is_none());
+/// ```
+#[derive(Clone)]
+pub struct IntervalTreeMap<K, V> {
+    root: Option<Box<Node<K, V>>>,
+    length: usize,
+}
+
+// Nodes keep track of their level in the tree as in `TreeMap`, and of the
+// largest end of the intervals in their subtree.
+#[derive(Clone)]
+struct Node<K, V> {
+    start: K,
+    end: K,
+    value: V,
+    max_end: K,
+    left: Option<Box<Node<K, V>>>,
+    right: Option<Box<Node<K, V>>>,
+    level: usize,
+}
+
+impl<K: Ord + Clone, V> Node<K, V> {
+    #[allow(clippy::redundant_field_names)]
+    fn new(start: K, end: K, value: V) -> Node<K, V> {
+        Node {
+            max_end: end.clone(),
+            start: start,
+            end: end,
+            value: value,
+            left: None,
+            right: None,
+            level: 1,
+        }
+    }
+
+    fn compare(&self, start: &K, end: &K) -> Ordering {
+        (start, end).cmp(&(&self.start, &self.end))
+    }
+}
+
+impl<K: Ord + Clone, V> AaNode for Node<K, V> {
+    fn level(&self) -> usize {
+        self.level
+    }
+
+    fn set_level(&mut self, level: usize) {
+        self.level = level;
+    }
+
+    fn left(&self) -> &Option<Box<Node<K, V>>> {
+        &self.left
+    }
+
+    fn right(&self) -> &Option<Box<Node<K, V>>> {
+        &self.right
+    }
+
+    fn left_mut(&mut self) -> &mut Option<Box<Node<K, V>>> {
+        &mut self.left
+    }
+
+    fn right_mut(&mut self) -> &mut Option<Box<Node<K, V>>> {
+        &mut self.right
+    }
+
+    // Recomputes `max_end` from the node's own end and its children's.
+    fn update(&mut self) {
+        let mut max_end = &self.end;
+        for child in self.left.iter().chain(self.right.iter()) {
+            if child.max_end > *max_end {
+                max_end = &child.max_end;
+            }
+        }
+        if *max_end != self.max_end {
+            self.max_end = max_end.clone();
+        }
+    }
+}
+
+impl<K: Ord + Clone, V> IntervalTreeMap<K, V> {
+    /// Creates an empty `IntervalTreeMap`.
+    pub fn new() -> IntervalTreeMap<K, V> {
+        IntervalTreeMap {
+            root: None,
+            length: 0,
+        }
+    }
+
+    /// Returns the number of intervals in the map.
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    /// Returns true if the map contains no intervals.
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Clears the map, removing all intervals.
+    pub fn clear(&mut self) {
+        self.root = None;
+        self.length = 0;
+    }
+
+    /// Returns a reference to the value of exactly the interval `range`.
+    pub fn get(&self, range: &ops::Range<K>) -> Option<&V> {
+        let mut current = &self.root;
+        while let Some(ref node) = *current {
+            match node.compare(&range.start, &range.end) {
+                Less => current = &node.left,
+                Greater => current = &node.right,
+                Equal => return Some(&node.value),
+            }
+        }
+        None
+    }
+
+    /// Maps the interval `range` to `value`, and returns the value it was
+    /// mapped to before. Other intervals that overlap it are left alone.
+    pub fn insert(&mut self, range: ops::Range<K>, value: V) -> Option<V> {
+        let old = insert(&mut self.root, range.start, range.end, value);
+        if old.is_none() {
+            self.length += 1;
+        }
+        old
+    }
+
+    /// Removes exactly the interval `range`, and returns its value.
+    pub fn remove_interval(&mut self, range: &ops::Range<K>) -> Option<V> {
+        let ret = remove(&mut self.root, &range.start, &range.end);
+        if ret.is_some() {
+            self.length -= 1;
+        }
+        ret
+    }
+
+    /// Returns an iterator over the intervals that share at least one key
+    /// with `range`, in ascending order of their starts and then their ends.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::IntervalTreeMap;
+    ///
+    /// let map: IntervalTreeMap<u32, char> = vec![(0..5, 'a'), (3..4, 'b'), (5..9, 'c')]
+    ///     .into_iter()
+    ///     .collect();
+    /// assert!(map.overlapping(4..6).map(|(_, v)| *v).eq("ac".chars()));
+    /// assert!(map.overlapping(3..3).next().is_none());
+    /// ```
+    pub fn overlapping(&self, range: ops::Range<K>) -> Overlapping<'_, K, V> {
+        let root = if range.start < range.end { &self.root } else { &None };
+        Overlapping::new(root, range.start, Excluded(range.end))
+    }
+
+    /// Returns an iterator over the intervals that contain `point`, in
+    /// ascending order of their starts and then their ends.
+    pub fn containing(&self, point: &K) -> Overlapping<'_, K, V> {
+        Overlapping::new(&self.root, point.clone(), Included(point.clone()))
+    }
+
+    /// Returns an iterator over all the intervals and their values, in
+    /// ascending order of their starts and then their ends.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter {
+            stack: vec![],
+            remaining: self.length,
+        };
+        iter.descend(&self.root);
+        iter
+    }
+}
+
+fn insert<K: Ord + Clone, V>(node: &mut Option<Box<Node<K, V>>>, start: K, end: K, value: V) -> Option<V> {
+    match *node {
+        Some(ref mut save) => {
+            let ret = match save.compare(&start, &end) {
+                Less => insert(&mut save.left, start, end, value),
+                Greater => insert(&mut save.right, start, end, value),
+                Equal => return Some(replace(&mut save.value, value)),
+            };
+            save.update();
+            aa::skew(save);
+            aa::split(save);
+            ret
+        }
+        None => {
+            *node = Some(Box::new(Node::new(start, end, value)));
+            None
+        }
+    }
+}
+
+fn remove<K: Ord + Clone, V>(node: &mut Option<Box<Node<K, V>>>, start: &K, end: &K) -> Option<V> {
+
+    fn heir_swap<K, V>(node: &mut Box<Node<K, V>>, child: &mut Option<Box<Node<K, V>>>) {
+        for x in child.iter_mut() {
+            if x.right.is_some() {
+                heir_swap(node, &mut x.right);
+            } else {
+                swap(&mut node.start, &mut x.start);
+                swap(&mut node.end, &mut x.end);
+                swap(&mut node.value, &mut x.value);
+            }
+        }
+    }
+
+    match *node {
+        None => {
+            return None; // bottom of tree
+        }
+        Some(ref mut save) => {
+            let (ret, rebalance) = match save.compare(start, end) {
+                Less => (remove(&mut save.left, start, end), true),
+                Greater => (remove(&mut save.right, start, end), true),
+                Equal => {
+                    if save.left.is_some() {
+                        if save.right.is_some() {
+                            // Swaps in the predecessor, and removes the
+                            // interval from its old place, which brings the
+                            // ends below back up to date on the way.
+                            let mut left = save.left.take().unwrap();
+                            if left.right.is_some() {
+                                heir_swap(save, &mut left.right);
+                            } else {
+                                swap(&mut save.start, &mut left.start);
+                                swap(&mut save.end, &mut left.end);
+                                swap(&mut save.value, &mut left.value);
+                            }
+                            save.left = Some(left);
+                            (remove(&mut save.left, start, end), true)
+                        } else {
+                            let new = save.left.take().unwrap();
+                            let Node { value, .. } = *replace(save, new);
+                            *save = save.left.take().unwrap();
+                            (Some(value), true)
+                        }
+                    } else if save.right.is_some() {
+                        let new = save.right.take().unwrap();
+                        let Node { value, .. } = *replace(save, new);
+                        (Some(value), true)
+                    } else {
+                        (None, false)
+                    }
+                }
+            };
+
+            if rebalance {
+                save.update();
+                aa::rebalance(save);
+                return ret;
+            }
+        }
+    }
+    match node.take() {
+        Some(b) => {
+            let Node { value, .. } = *b;
+            Some(value)
+        }
+        None => panic!(),
+    }
+}
+
+impl<K: Ord + Clone, V: PartialEq> PartialEq for IntervalTreeMap<K, V> {
+    fn eq(&self, other: &IntervalTreeMap<K, V>) -> bool {
+        self.length == other.length && self.iter().eq(other.iter())
+    }
+}
+
+impl<K: Ord + Clone, V: Eq> Eq for IntervalTreeMap<K, V> {}
+
+impl<K: Ord + Clone + Debug, V: Debug> Debug for IntervalTreeMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{")?;
+
+        for (i, (range, value)) in self.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{:?}..{:?}: {:?}", range.start, range.end, *value)?;
+        }
+
+        write!(f, "}}")
+    }
+}
+
+impl<K: Ord + Clone, V> Default for IntervalTreeMap<K, V> {
+    fn default() -> IntervalTreeMap<K, V> {
+        IntervalTreeMap::new()
+    }
+}
+
+impl<K: Ord + Clone, V> iter::FromIterator<(ops::Range<K>, V)> for IntervalTreeMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (ops::Range<K>, V)>>(iter: I) -> IntervalTreeMap<K, V> {
+        let mut map = IntervalTreeMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K: Ord + Clone, V> Extend<(ops::Range<K>, V)> for IntervalTreeMap<K, V> {
+    fn extend<I: IntoIterator<Item = (ops::Range<K>, V)>>(&mut self, iter: I) {
+        for (range, value) in iter {
+            self.insert(range, value);
+        }
+    }
+}
+
+impl<'a, K: Ord + Clone, V> IntoIterator for &'a IntervalTreeMap<K, V> {
+    type Item = (ops::Range<&'a K>, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+/// Lazy forward iterator over the intervals of an `IntervalTreeMap` and
+/// their values.
+pub struct Iter<'a, K: 'a, V: 'a> {
+    stack: Vec<&'a Node<K, V>>,
+    remaining: usize,
+}
+
+/// Lazy forward iterator over the intervals of an `IntervalTreeMap` that
+/// overlap a range or contain a point.
+pub struct Overlapping<'a, K: 'a, V: 'a> {
+    // The nodes whose intervals and right subtrees are still to be visited.
+    stack: Vec<&'a Node<K, V>>,
+    // Intervals must end after `lo` and start before `hi`.
+    lo: K,
+    hi: Bound<K>,
+}
+
+impl<'a, K, V> Iter<'a, K, V> {
+    fn descend(&mut self, mut node: &'a Option<Box<Node<K, V>>>) {
+        while let Some(ref n) = *node {
+            self.stack.push(n);
+            node = &n.left;
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (ops::Range<&'a K>, &'a V);
+
+    fn next(&mut self) -> Option<(ops::Range<&'a K>, &'a V)> {
+        let node = self.stack.pop()?;
+        self.descend(&node.right);
+        self.remaining -= 1;
+        Some((&node.start..&node.end, &node.value))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V> {}
+impl<'a, K, V> FusedIterator for Iter<'a, K, V> {}
+
+impl<'a, K: Ord, V> Overlapping<'a, K, V> {
+    #[allow(clippy::redundant_field_names)]
+    fn new(root: &'a Option<Box<Node<K, V>>>, lo: K, hi: Bound<K>) -> Overlapping<'a, K, V> {
+        let mut iter = Overlapping {
+            stack: vec![],
+            lo: lo,
+            hi: hi,
+        };
+        iter.descend(root);
+        iter
+    }
+
+    // Pushes the leftmost path of `node`, stopping at the first subtree
+    // whose intervals all end too early.
+    fn descend(&mut self, mut node: &'a Option<Box<Node<K, V>>>) {
+        while let Some(ref n) = *node {
+            if n.max_end <= self.lo {
+                break;
+            }
+            self.stack.push(n);
+            node = &n.left;
+        }
+    }
+
+    fn starts_in_time(&self, start: &K) -> bool {
+        match self.hi {
+            Included(ref hi) => start <= hi,
+            Excluded(ref hi) => start < hi,
+            Bound::Unbounded => true,
+        }
+    }
+}
+
+impl<'a, K: Ord, V> Iterator for Overlapping<'a, K, V> {
+    type Item = (ops::Range<&'a K>, &'a V);
+
+    fn next(&mut self) -> Option<(ops::Range<&'a K>, &'a V)> {
+        while let Some(node) = self.stack.pop() {
+            if !self.starts_in_time(&node.start) {
+                // Every interval still to come starts at least as late.
+                self.stack.clear();
+                return None;
+            }
+            self.descend(&node.right);
+            if node.end > self.lo && node.start < node.end {
+                return Some((&node.start..&node.end, &node.value));
+            }
+        }
+        None
+    }
+}
+
+impl<'a, K: Ord, V> FusedIterator for Overlapping<'a, K, V> {}
+
+#[cfg(test)]
+mod test {
+    use std::ops::Range;
+
+    use rand::{self, Rng};
+
+    use super::{IntervalTreeMap, Node};
+
+    // Checks the order, levels and recorded ends of a subtree, returning its
+    // largest end.
+    fn check<V>(node: &Node<u32, V>, lo: Option<(u32, u32)>, hi: Option<(u32, u32)>) -> u32 {
+        let key = (node.start, node.end);
+        assert!(lo.is_none_or(|lo| lo < key) && hi.is_none_or(|hi| key < hi));
+        let mut max_end = node.end;
+        match node.left {
+            Some(ref left) => {
+                assert_eq!(left.level + 1, node.level);
+                max_end = max_end.max(check(left, lo, Some(key)));
+            }
+            None => assert_eq!(node.level, 1),
+        }
+        match node.right {
+            Some(ref right) => {
+                assert!(right.level == node.level || right.level + 1 == node.level);
+                assert!(right.right.as_ref().is_none_or(|x| x.level < node.level));
+                max_end = max_end.max(check(right, Some(key), hi));
+            }
+            None => assert_eq!(node.level, 1),
+        }
+        assert_eq!(node.max_end, max_end);
+        max_end
+    }
+
+    #[test]
+    fn test_queries() {
+        let mut map = IntervalTreeMap::new();
+        assert_eq!(map.insert(0..10, 'a'), None);
+        assert_eq!(map.insert(2..4, 'b'), None);
+        assert_eq!(map.insert(4..4, 'e'), None);
+        assert_eq!(map.insert(8..12, 'c'), None);
+        assert_eq!(map.insert(2..4, 'd'), Some('b'));
+        assert_eq!(map.len(), 4);
+        assert_eq!(map.get(&(2..4)), Some(&'d'));
+        assert_eq!(format!("{:?}", map), "{0..10: 'a', 2..4: 'd', 4..4: 'e', 8..12: 'c'}");
+
+        let values = |iter: super::Overlapping<u32, char>| iter.map(|(_, v)| *v).collect::<String>();
+        assert_eq!(values(map.containing(&3)), "ad");
+        assert_eq!(values(map.containing(&4)), "a");
+        assert_eq!(values(map.containing(&10)), "c");
+        assert_eq!(values(map.containing(&12)), "");
+        assert_eq!(values(map.overlapping(4..8)), "a");
+        assert_eq!(values(map.overlapping(3..9)), "adc");
+        assert_eq!(values(map.overlapping(12..20)), "");
+
+        assert_eq!(map.remove_interval(&(0..10)), Some('a'));
+        assert_eq!(map.remove_interval(&(0..10)), None);
+        assert_eq!(values(map.overlapping(0..20)), "dc");
+        assert!(map.iter().map(|(r, _)| *r.start..*r.end).eq(vec![2..4, 4..4, 8..12]));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // too slow
+    fn test_random() {
+        let mut rng = rand::weak_rng();
+        let mut map = IntervalTreeMap::new();
+        let mut model: Vec<(Range<u32>, u32)> = vec![];
+
+        for i in 0..3000 {
+            let start = rng.gen_range(0, 100);
+            let range = start..start + rng.gen_range(0, 20);
+            if rng.gen_weighted_bool(3) && !model.is_empty() {
+                let index = rng.gen_range(0, model.len());
+                let (range, value) = model.remove(index);
+                assert_eq!(map.remove_interval(&range), Some(value));
+            } else if !model.iter().any(|e| e.0 == range) {
+                assert_eq!(map.insert(range.clone(), i), None);
+                model.push((range, i));
+            }
+            if let Some(ref root) = map.root {
+                check(root, None, None);
+            }
+
+            model.sort_by_key(|e| (e.0.start, e.0.end));
+            assert_eq!(map.len(), model.len());
+            let point = rng.gen_range(0, 120);
+            let expected: Vec<u32> = model.iter()
+                .filter(|e| e.0.contains(&point))
+                .map(|e| e.1)
+                .collect();
+            assert_eq!(map.containing(&point).map(|(_, v)| *v).collect::<Vec<_>>(), expected);
+            let query = point..point + rng.gen_range(0, 10);
+            let expected: Vec<u32> = model.iter()
+                .filter(|e| e.0.start < query.end && query.start < e.0.end && !e.0.is_empty())
+                .filter(|_| !query.is_empty())
+                .map(|e| e.1)
+                .collect();
+            assert_eq!(map.overlapping(query).map(|(_, v)| *v).collect::<Vec<_>>(), expected);
+        }
+    }
+}
@@ -31,19 +31,60 @@
 //! }
 //! ```
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+#[cfg(any(feature = "std", test))]
+extern crate core;
+#[macro_use]
+extern crate alloc;
+
 extern crate compare;
 
 #[cfg(feature = "ordered_iter")]
 extern crate ordered_iter;
 
+#[cfg(feature = "rayon")]
+extern crate rayon;
+
 #[cfg(test)]
 extern crate rand;
 
+pub use bimap::TreeBiMap;
+pub use hash::HashCompare;
+pub use hash_ring::HashRing;
+pub use interval_tree::IntervalTreeMap;
+pub use list::TreeList;
 pub use map::TreeMap;
+pub use multimap::TreeMultiMap;
+pub use multiset::TreeMultiSet;
+pub use priority_queue::IndexedPriorityQueue;
+pub use range_map::RangeMap;
+pub use range_set::RangeSet;
 pub use set::TreeSet;
+pub use sliding_window::SlidingWindow;
+pub use sorted_map::SortedMap;
+#[cfg(feature = "std")]
+pub use sharded::ShardedTreeMap;
 
+use core::ops;
+
+mod aa;
+pub mod bimap;
+pub mod by_key;
+mod hash;
+pub mod hash_ring;
+pub mod interval_tree;
+pub mod list;
 pub mod map;
+pub mod multimap;
+pub mod multiset;
+pub mod priority_queue;
+pub mod range_map;
+pub mod range_set;
 pub mod set;
+pub mod sliding_window;
+pub mod sorted_map;
+#[cfg(feature = "std")]
+pub mod sharded;
 
 /// An endpoint of a range of keys.
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
@@ -55,3 +96,25 @@ pub enum Bound<T> {
     /// An infinite endpoint. Indicates that there is no bound in this direction.
     Unbounded,
 }
+
+/// Converts to the standard library's equivalent, for use with its
+/// collections and with `RangeBounds`.
+impl<T> From<Bound<T>> for ops::Bound<T> {
+    fn from(bound: Bound<T>) -> ops::Bound<T> {
+        match bound {
+            Bound::Included(x) => ops::Bound::Included(x),
+            Bound::Excluded(x) => ops::Bound::Excluded(x),
+            Bound::Unbounded => ops::Bound::Unbounded,
+        }
+    }
+}
+
+impl<T> From<ops::Bound<T>> for Bound<T> {
+    fn from(bound: ops::Bound<T>) -> Bound<T> {
+        match bound {
+            ops::Bound::Included(x) => Bound::Included(x),
+            ops::Bound::Excluded(x) => Bound::Excluded(x),
+            ops::Bound::Unbounded => Bound::Unbounded,
+        }
+    }
+}
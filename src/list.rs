@@ -0,0 +1,663 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A sequence that can be inserted into and removed from at any position in
+//! logarithmic time.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cmp::Ordering::{Equal, Greater, Less};
+use core::default::Default;
+use core::fmt::{self, Debug};
+use core::iter::{self, FusedIterator};
+use core::mem::{replace, swap};
+use core::ops::{self, RangeBounds};
+
+use super::aa::{self, AaNode};
+
+/// A sequence stored in an AA tree ordered by position, whose nodes record
+/// the size of their subtrees in place of keys.
+///
+/// Inserting, removing and looking up the element at any index take
+/// O(log n), as do `split_off` and `append`, which makes the list suited to
+/// buffers that are edited in the middle.
+///
+/// # Examples
+///
+/// ```rust
+/// use stable_bst::TreeList;
+///
+/// let mut text: TreeList<char> = "helo".chars().collect();
+/// text.insert(3, 'l');
+/// text.push('!');
+/// assert_eq!(text.remove(0), 'h');
+///
+/// assert_eq!(text.get(1), Some(&'l'));
+/// assert!(text.iter().eq("ello!".chars().collect::<Vec<_>>().iter()));
+/// assert!(text.range(1..3).rev().eq(vec![&'l', &'l']));
+/// ```
+#[derive(Clone)]
+pub struct TreeList<T> {
+    root: Option<Box<Node<T>>>,
+}
+
+#[derive(Clone)]
+struct Node<T> {
+    value: T,
+    size: usize,
+    left: Option<Box<Node<T>>>,
+    right: Option<Box<Node<T>>>,
+    level: usize,
+}
+
+impl<T> AaNode for Node<T> {
+    fn level(&self) -> usize {
+        self.level
+    }
+
+    fn set_level(&mut self, level: usize) {
+        self.level = level;
+    }
+
+    fn left(&self) -> &Option<Box<Node<T>>> {
+        &self.left
+    }
+
+    fn right(&self) -> &Option<Box<Node<T>>> {
+        &self.right
+    }
+
+    fn left_mut(&mut self) -> &mut Option<Box<Node<T>>> {
+        &mut self.left
+    }
+
+    fn right_mut(&mut self) -> &mut Option<Box<Node<T>>> {
+        &mut self.right
+    }
+
+    fn update(&mut self) {
+        self.size = size(&self.left) + 1 + size(&self.right);
+    }
+}
+
+fn size<T>(node: &Option<Box<Node<T>>>) -> usize {
+    node.as_ref().map_or(0, |x| x.size)
+}
+
+impl<T> TreeList<T> {
+    /// Creates an empty `TreeList`.
+    pub fn new() -> TreeList<T> {
+        TreeList { root: None }
+    }
+
+    /// Returns the number of elements in the list.
+    pub fn len(&self) -> usize {
+        size(&self.root)
+    }
+
+    /// Returns true if the list contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Clears the list, removing all elements.
+    pub fn clear(&mut self) {
+        self.root = None;
+    }
+
+    /// Returns a reference to the element at `index`, or `None` if it is out
+    /// of bounds.
+    pub fn get(&self, mut index: usize) -> Option<&T> {
+        let mut current = &self.root;
+        while let Some(ref node) = *current {
+            let left_size = size(&node.left);
+            match index.cmp(&left_size) {
+                Less => current = &node.left,
+                Equal => return Some(&node.value),
+                Greater => {
+                    index -= left_size + 1;
+                    current = &node.right;
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns a mutable reference to the element at `index`, or `None` if it
+    /// is out of bounds.
+    pub fn get_mut(&mut self, mut index: usize) -> Option<&mut T> {
+        let mut current = &mut self.root;
+        while let Some(ref mut node) = *current {
+            let left_size = size(&node.left);
+            match index.cmp(&left_size) {
+                Less => current = &mut node.left,
+                Equal => return Some(&mut node.value),
+                Greater => {
+                    index -= left_size + 1;
+                    current = &mut node.right;
+                }
+            }
+        }
+        None
+    }
+
+    /// Inserts `value` at `index`, shifting the elements after it along.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is greater than the list's length.
+    pub fn insert(&mut self, index: usize, value: T) {
+        assert!(index <= self.len(), "insertion index (is {}) should be <= len (is {})", index, self.len());
+        insert(&mut self.root, index, value);
+    }
+
+    /// Appends `value` to the back of the list.
+    pub fn push(&mut self, value: T) {
+        let len = self.len();
+        insert(&mut self.root, len, value);
+    }
+
+    /// Removes the last element of the list and returns it, or `None` if the
+    /// list is empty.
+    pub fn pop(&mut self) -> Option<T> {
+        match self.len() {
+            0 => None,
+            len => Some(remove(&mut self.root, len - 1)),
+        }
+    }
+
+    /// Removes the element at `index` and returns it, shifting the elements
+    /// after it back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn remove(&mut self, index: usize) -> T {
+        assert!(index < self.len(), "removal index (is {}) should be < len (is {})", index, self.len());
+        remove(&mut self.root, index)
+    }
+
+    /// Splits the list in two at `index`, leaving the elements before it and
+    /// returning the rest.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is greater than the list's length.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::TreeList;
+    ///
+    /// let mut a: TreeList<u32> = (0..6).collect();
+    /// let mut b = a.split_off(4);
+    /// assert!(a.iter().cloned().eq(0..4));
+    /// assert!(b.iter().cloned().eq(4..6));
+    ///
+    /// b.append(&mut a);
+    /// assert!(a.is_empty());
+    /// assert!(b.iter().cloned().eq(vec![4, 5, 0, 1, 2, 3]));
+    /// ```
+    pub fn split_off(&mut self, index: usize) -> TreeList<T> {
+        assert!(index <= self.len(), "`at` split index (is {}) should be <= len (is {})", index, self.len());
+        let (left, right) = split_at(self.root.take(), index);
+        self.root = left;
+        TreeList { root: right }
+    }
+
+    /// Moves all the elements of `other` to the back of this list, leaving
+    /// `other` empty.
+    pub fn append(&mut self, other: &mut TreeList<T>) {
+        let right = match other.root.take() {
+            Some(right) => right,
+            None => return,
+        };
+        self.root = match self.pop() {
+            Some(middle) => Some(join(self.root.take(), middle, Some(right))),
+            None => Some(right),
+        };
+    }
+
+    /// Returns a double-ended iterator over the elements of the list.
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.range(..)
+    }
+
+    /// Returns a double-ended iterator over the elements at the indices in
+    /// `range`, which is written like a slice index, such as `a..b` or `a..`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is decreasing or reaches past the end of the list.
+    pub fn range<R: RangeBounds<usize>>(&self, range: R) -> Iter<'_, T> {
+        let start = match range.start_bound() {
+            ops::Bound::Included(&start) => start,
+            ops::Bound::Excluded(&start) => start + 1,
+            ops::Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            ops::Bound::Included(&end) => end + 1,
+            ops::Bound::Excluded(&end) => end,
+            ops::Bound::Unbounded => self.len(),
+        };
+        assert!(start <= end, "slice index starts at {} but ends at {}", start, end);
+        assert!(end <= self.len(), "range end index {} out of range for list of length {}", end, self.len());
+
+        let mut iter = Iter {
+            front: vec![],
+            back: vec![],
+            remaining: end - start,
+        };
+        if start < end {
+            iter.seek_front(&self.root, start);
+            iter.seek_back(&self.root, end - 1);
+        }
+        iter
+    }
+}
+
+#[allow(clippy::redundant_field_names)]
+fn insert<T>(node: &mut Option<Box<Node<T>>>, index: usize, value: T) {
+    match *node {
+        Some(ref mut save) => {
+            let left_size = size(&save.left);
+            if index <= left_size {
+                insert(&mut save.left, index, value);
+            } else {
+                insert(&mut save.right, index - left_size - 1, value);
+            }
+            save.update();
+            aa::skew(save);
+            aa::split(save);
+        }
+        None => {
+            *node = Some(Box::new(Node {
+                value: value,
+                size: 1,
+                left: None,
+                right: None,
+                level: 1,
+            }));
+        }
+    }
+}
+
+fn remove<T>(node: &mut Option<Box<Node<T>>>, index: usize) -> T {
+    if let Some(ref mut save) = *node {
+        let left_size = size(&save.left);
+        if index != left_size || save.left.is_some() {
+            let value = match index.cmp(&left_size) {
+                Less => remove(&mut save.left, index),
+                Greater => remove(&mut save.right, index - left_size - 1),
+                Equal => {
+                    // Takes the place of the element before it.
+                    let heir = remove(&mut save.left, left_size - 1);
+                    replace(&mut save.value, heir)
+                }
+            };
+            save.update();
+            aa::rebalance(save);
+            return value;
+        }
+    }
+
+    // The node has no left child, so it is a leaf or has a single leaf to its
+    // right, which takes its place.
+    let mut removed = node.take().unwrap();
+    swap(node, &mut removed.right);
+    removed.value
+}
+
+// Joins two trees with a node holding `value` between them, in time
+// proportional to the difference of their levels. The shorter tree takes the
+// place of a subtree of the same level on the near edge of the taller one,
+// which then rebalances as if a node had been inserted there.
+#[allow(clippy::redundant_field_names)]
+fn join<T>(left: Option<Box<Node<T>>>, value: T, right: Option<Box<Node<T>>>) -> Box<Node<T>> {
+    let (left_level, right_level) = (aa::level(&left), aa::level(&right));
+    if left_level > right_level {
+        let mut save = left.unwrap();
+        let inner = save.right.take();
+        save.right = Some(join(inner, value, right));
+        save.update();
+        aa::skew(&mut save);
+        aa::split(&mut save);
+        save
+    } else if left_level < right_level {
+        let mut save = right.unwrap();
+        let inner = save.left.take();
+        save.left = Some(join(left, value, inner));
+        save.update();
+        aa::skew(&mut save);
+        aa::split(&mut save);
+        save
+    } else {
+        let mut node = Box::new(Node {
+            value: value,
+            size: 0,
+            left: left,
+            right: right,
+            level: left_level + 1,
+        });
+        node.update();
+        node
+    }
+}
+
+// Splits a tree into one of its first `index` elements and one of the rest.
+#[allow(clippy::type_complexity)]
+fn split_at<T>(node: Option<Box<Node<T>>>, index: usize) -> (Option<Box<Node<T>>>, Option<Box<Node<T>>>) {
+    let node = match node {
+        Some(node) => *node,
+        None => return (None, None),
+    };
+    let Node { value, left, right, .. } = node;
+    let left_size = size(&left);
+    if index <= left_size {
+        let (before, after) = split_at(left, index);
+        (before, Some(join(after, value, right)))
+    } else {
+        let (before, after) = split_at(right, index - left_size - 1);
+        (Some(join(left, value, before)), after)
+    }
+}
+
+// Builds a tree out of the first `len` elements of `iter` in O(len), as
+// `TreeMap` does.
+#[allow(clippy::redundant_field_names)]
+fn build<T, I>(len: usize, iter: &mut I) -> Option<Box<Node<T>>>
+    where I: Iterator<Item = T>
+{
+    if len == 0 {
+        return None;
+    }
+    let left = build((len - 1) / 2, iter);
+    let value = iter.next().expect("too few elements");
+    let right = build(len / 2, iter);
+    Some(Box::new(Node {
+        level: aa::level(&left) + 1,
+        value: value,
+        size: len,
+        left: left,
+        right: right,
+    }))
+}
+
+impl<T: PartialEq> PartialEq for TreeList<T> {
+    fn eq(&self, other: &TreeList<T>) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Eq> Eq for TreeList<T> {}
+
+impl<T: Debug> Debug for TreeList<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T> Default for TreeList<T> {
+    fn default() -> TreeList<T> {
+        TreeList::new()
+    }
+}
+
+impl<T> ops::Index<usize> for TreeList<T> {
+    type Output = T;
+
+    #[inline]
+    fn index(&self, index: usize) -> &T {
+        self.get(index).expect("index out of bounds")
+    }
+}
+
+impl<T> ops::IndexMut<usize> for TreeList<T> {
+    #[inline]
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        self.get_mut(index).expect("index out of bounds")
+    }
+}
+
+impl<T> iter::FromIterator<T> for TreeList<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> TreeList<T> {
+        let values: Vec<T> = iter.into_iter().collect();
+        TreeList { root: build(values.len(), &mut values.into_iter()) }
+    }
+}
+
+impl<T> Extend<T> for TreeList<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let mut other: TreeList<T> = iter.into_iter().collect();
+        self.append(&mut other);
+    }
+}
+
+impl<'a, T> IntoIterator for &'a TreeList<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+/// Lazy double-ended iterator over the elements of a `TreeList`.
+pub struct Iter<'a, T: 'a> {
+    // The nodes still to be yielded from the front, each before its right
+    // subtree, and likewise from the back, each before its left subtree.
+    front: Vec<&'a Node<T>>,
+    back: Vec<&'a Node<T>>,
+    remaining: usize,
+}
+
+impl<'a, T> Iter<'a, T> {
+    fn seek_front(&mut self, mut node: &'a Option<Box<Node<T>>>, mut index: usize) {
+        while let Some(ref n) = *node {
+            let left_size = size(&n.left);
+            if index <= left_size {
+                self.front.push(n);
+                if index == left_size {
+                    return;
+                }
+                node = &n.left;
+            } else {
+                index -= left_size + 1;
+                node = &n.right;
+            }
+        }
+    }
+
+    fn seek_back(&mut self, mut node: &'a Option<Box<Node<T>>>, mut index: usize) {
+        while let Some(ref n) = *node {
+            let left_size = size(&n.left);
+            if index >= left_size {
+                self.back.push(n);
+                if index == left_size {
+                    return;
+                }
+                index -= left_size + 1;
+                node = &n.right;
+            } else {
+                node = &n.left;
+            }
+        }
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let node = self.front.pop().unwrap();
+        let mut child = &node.right;
+        while let Some(ref n) = *child {
+            self.front.push(n);
+            child = &n.left;
+        }
+        Some(&node.value)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for Iter<'a, T> {
+    fn next_back(&mut self) -> Option<&'a T> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let node = self.back.pop().unwrap();
+        let mut child = &node.left;
+        while let Some(ref n) = *child {
+            self.back.push(n);
+            child = &n.right;
+        }
+        Some(&node.value)
+    }
+}
+
+impl<'a, T> ExactSizeIterator for Iter<'a, T> {}
+impl<'a, T> FusedIterator for Iter<'a, T> {}
+
+#[cfg(test)]
+mod test {
+    use rand::{self, Rng};
+
+    use super::{Node, TreeList};
+
+    // Checks the levels and sizes of a subtree, returning its size.
+    fn check<T>(node: &Node<T>) -> usize {
+        let mut size = 1;
+        match node.left {
+            Some(ref left) => {
+                assert_eq!(left.level + 1, node.level);
+                size += check(left);
+            }
+            None => assert_eq!(node.level, 1),
+        }
+        match node.right {
+            Some(ref right) => {
+                assert!(right.level == node.level || right.level + 1 == node.level);
+                assert!(right.right.as_ref().is_none_or(|x| x.level < node.level));
+                size += check(right);
+            }
+            None => assert_eq!(node.level, 1),
+        }
+        assert_eq!(node.size, size);
+        size
+    }
+
+    fn check_list<T: PartialEq + ::std::fmt::Debug>(list: &TreeList<T>, model: &[T]) {
+        if let Some(ref root) = list.root {
+            check(root);
+        }
+        assert_eq!(list.len(), model.len());
+        assert!(list.iter().eq(model.iter()));
+        assert!(list.iter().rev().eq(model.iter().rev()));
+    }
+
+    #[test]
+    fn test_positions() {
+        let mut list = TreeList::new();
+        for i in 0..10 {
+            list.insert(i / 2, i);
+        }
+        assert_eq!(format!("{:?}", list), "[1, 3, 5, 7, 9, 8, 6, 4, 2, 0]");
+        assert_eq!(list.remove(4), 9);
+        assert_eq!(list.pop(), Some(0));
+        list[0] = 10;
+        *list.get_mut(1).unwrap() += 10;
+        assert_eq!(list.get(1), Some(&13));
+        assert_eq!(list.get(8), None);
+        check_list(&list, &[10, 13, 5, 7, 8, 6, 4, 2]);
+
+        let mut range = list.range(2..=5);
+        assert_eq!(range.len(), 4);
+        assert_eq!(range.next(), Some(&5));
+        assert_eq!(range.next_back(), Some(&6));
+        assert_eq!(range.next_back(), Some(&8));
+        assert_eq!(range.next(), Some(&7));
+        assert_eq!(range.next(), None);
+        assert_eq!(range.next_back(), None);
+        assert!(list.range(3..3).next().is_none());
+        assert!(list.range(6..).eq(vec![&4, &2]));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_insert_out_of_bounds() {
+        let mut list: TreeList<u32> = (0..3).collect();
+        list.insert(4, 0);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // too slow
+    fn test_split_and_append() {
+        for len in 0..40 {
+            let list: TreeList<usize> = (0..len).collect();
+            for at in 0..len + 1 {
+                let mut before = list.clone();
+                let mut after = before.split_off(at);
+                check_list(&before, &(0..at).collect::<Vec<_>>());
+                check_list(&after, &(at..len).collect::<Vec<_>>());
+                after.append(&mut before);
+                let rotated: Vec<usize> = (at..len).chain(0..at).collect();
+                check_list(&after, &rotated);
+                check_list(&before, &[]);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // too slow
+    fn test_random() {
+        let mut rng = rand::weak_rng();
+        let mut list = TreeList::new();
+        let mut model = vec![];
+
+        for i in 0..3000 {
+            match rng.gen_range(0, 8) {
+                0 if !model.is_empty() => {
+                    let at = rng.gen_range(0, model.len() + 1);
+                    let mut tail = list.split_off(at);
+                    let tail_model = model.split_off(at);
+                    check_list(&tail, &tail_model);
+                    let mut extra: TreeList<u32> = (0..rng.gen_range(0, 5)).collect();
+                    model.extend(0..extra.len() as u32);
+                    list.append(&mut extra);
+                    list.append(&mut tail);
+                    model.extend(tail_model);
+                }
+                1..=3 if !model.is_empty() => {
+                    let at = rng.gen_range(0, model.len());
+                    assert_eq!(list.remove(at), model.remove(at));
+                }
+                _ => {
+                    let at = rng.gen_range(0, model.len() + 1);
+                    list.insert(at, i);
+                    model.insert(at, i);
+                }
+            }
+            check_list(&list, &model);
+
+            let a = rng.gen_range(0, model.len() + 1);
+            let b = rng.gen_range(a, model.len() + 1);
+            assert!(list.range(a..b).eq(model[a..b].iter()));
+            assert!(list.range(a..b).rev().eq(model[a..b].iter().rev()));
+        }
+    }
+}
@@ -8,20 +8,31 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::default::Default;
-use std::cmp::Ordering::{self, Less, Equal, Greater};
-use std::fmt::{self, Debug};
-use std::iter::{self, IntoIterator};
-use std::mem::{replace, swap};
-use std::ops;
-use std::ops::Deref;
-use std::ptr;
-use std::hash::{Hash, Hasher};
-use std::marker::PhantomData;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::default::Default;
+use core::cmp::Ordering::{self, Less, Equal, Greater};
+use core::fmt::{self, Debug};
+use core::iter::{self, FusedIterator, IntoIterator};
+use core::mem::{replace, swap};
+use core::ops::{self, RangeBounds};
+use core::ptr;
+use core::hash::{Hash, Hasher};
+use core::marker::PhantomData;
 
 use compare::{Compare, Natural, natural};
 
-use super::Bound;
+use super::{Bound, HashCompare};
+use super::aa::{self, AaNode};
+
+pub use self::display::DisplayTree;
+#[cfg(feature = "rayon")]
+pub use self::par::{IntoParIter, ParIter, ParIterMut, ParRange};
+
+mod display;
+#[cfg(feature = "rayon")]
+mod par;
 
 /// This is implemented as an AA tree, which is a simplified variation of
 /// a red-black tree where red (horizontal) nodes can only be added
@@ -75,7 +86,6 @@ use super::Bound;
 /// A `TreeMap` can also be used with a custom ordering:
 ///
 /// ```rust
-
 /// use stable_bst::TreeMap;
 ///
 /// struct Troll<'a> {
@@ -128,36 +138,55 @@ pub struct TreeMap<K, V, C: Compare<K> = Natural<K>> {
     cmp: C,
 }
 
-// FIXME: determine what `PartialEq` means for comparator-based `TreeMap`s
-impl<K: PartialEq + Ord, V: PartialEq> PartialEq for TreeMap<K, V> {
-    #[inline]
-    fn eq(&self, other: &TreeMap<K, V>) -> bool {
-        self.iter().eq(other)
+// Maps are compared entry by entry, keys with `self`'s comparator, which is
+// assumed to order keys the same way as `other`'s.
+impl<K, V: PartialEq, C> PartialEq for TreeMap<K, V, C>
+    where C: Compare<K>
+{
+    fn eq(&self, other: &TreeMap<K, V, C>) -> bool {
+        self.len() == other.len() &&
+        self.iter().zip(other).all(|((k1, v1), (k2, v2))| self.cmp.compares_eq(k1, k2) && v1 == v2)
     }
 }
 
-// FIXME: determine what `Eq` means for comparator-based `TreeMap`s
-impl<K: Eq + Ord, V: Eq> Eq for TreeMap<K, V> {}
+impl<K, V: Eq, C> Eq for TreeMap<K, V, C> where C: Compare<K> {}
 
-// FIXME: determine what `PartialOrd` means for comparator-based `TreeMap`s
-impl<K: Ord, V: PartialOrd> PartialOrd for TreeMap<K, V> {
-    #[inline]
-    fn partial_cmp(&self, other: &TreeMap<K, V>) -> Option<Ordering> {
-        self.iter().partial_cmp(other)
+impl<K, V: PartialOrd, C> PartialOrd for TreeMap<K, V, C>
+    where C: Compare<K>
+{
+    fn partial_cmp(&self, other: &TreeMap<K, V, C>) -> Option<Ordering> {
+        for ((k1, v1), (k2, v2)) in self.iter().zip(other) {
+            match self.cmp.compare(k1, k2) {
+                Equal => {}
+                non_eq => return Some(non_eq),
+            }
+            match v1.partial_cmp(v2) {
+                Some(Equal) => {}
+                non_eq => return non_eq,
+            }
+        }
+        Some(self.len().cmp(&other.len()))
     }
 }
 
-// FIXME: determine what `Ord` means for comparator-based `TreeMap`s
-impl<K: Ord, V: Ord> Ord for TreeMap<K, V> {
-    #[inline]
-    fn cmp(&self, other: &TreeMap<K, V>) -> Ordering {
-        self.iter().cmp(other)
+impl<K, V: Ord, C> Ord for TreeMap<K, V, C>
+    where C: Compare<K>
+{
+    fn cmp(&self, other: &TreeMap<K, V, C>) -> Ordering {
+        for ((k1, v1), (k2, v2)) in self.iter().zip(other) {
+            match self.cmp.compare(k1, k2).then_with(|| v1.cmp(v2)) {
+                Equal => {}
+                non_eq => return non_eq,
+            }
+        }
+        self.len().cmp(&other.len())
     }
 }
 
 impl<K: Debug, V: Debug, C> Debug for TreeMap<K, V, C>
     where C: Compare<K>
 {
+    #[allow(deprecated)]
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         try!(write!(f, "{{"));
 
@@ -218,6 +247,7 @@ impl<K, V, C> TreeMap<K, V, C>
     where C: Compare<K>
 {
     /// Creates an empty `TreeMap` ordered according to the given comparator.
+    #[allow(clippy::redundant_field_names)]
     pub fn with_comparator(cmp: C) -> TreeMap<K, V, C> {
         TreeMap {
             root: None,
@@ -253,7 +283,7 @@ impl<K, V, C> TreeMap<K, V, C>
         }
         let first: fn((&'a K, &'a V)) -> &'a K = first; // coerce to fn pointer
 
-        Keys(self.iter().map(first))
+        Keys(self.iter().without_comparator().map(first))
     }
 
     /// Gets a lazy iterator over the values in the map, in ascending order
@@ -279,7 +309,7 @@ impl<K, V, C> TreeMap<K, V, C>
         }
         let second: fn((&'a K, &'a V)) -> &'a V = second; // coerce to fn pointer
 
-        Values(self.iter().map(second))
+        Values(self.iter().without_comparator().map(second))
     }
 
     /// Gets a lazy iterator over the values in the map, in ascending order
@@ -329,15 +359,23 @@ impl<K, V, C> TreeMap<K, V, C>
     ///     println!("{}: {}", key, value);
     /// }
     /// ```
-    pub fn iter(&self) -> Iter<K, V, Forward> {
-        Iter { iter_mut: (unsafe { &mut *(self as *const Self as *mut Self) }).iter_mut() }
+    #[allow(clippy::redundant_field_names)]
+    pub fn iter(&self) -> Iter<'_, K, V, Forward, C> {
+        let root = unsafe { Shared::child(&self.root as *const _ as *mut _) };
+        Iter {
+            iter: RawIter::new(root),
+            remaining: self.length,
+            root: root,
+            cmp: &self.cmp,
+            marker: PhantomData,
+        }
     }
 
-    fn iter_mut_dir<D: Direction>(&mut self) -> IterMut<K, V, D> {
+    fn iter_mut_dir<D: Direction>(&mut self) -> IterMut<'_, K, V, D> {
         IterMut {
-            stack: vec![],
-            node: deref_mut(&mut self.root),
-            direction: PhantomData,
+            iter: RawIter::new(unsafe { Exclusive::child(&mut self.root) }),
+            remaining: self.length,
+            marker: PhantomData,
         }
     }
 
@@ -363,7 +401,7 @@ impl<K, V, C> TreeMap<K, V, C>
     /// assert_eq!(map.get(&"b"), Some(&12));
     /// assert_eq!(map.get(&"c"), Some(&3));
     /// ```
-    pub fn iter_mut(&mut self) -> IterMut<K, V, Forward> {
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V, Forward> {
         self.iter_mut_dir()
     }
 
@@ -382,6 +420,7 @@ impl<K, V, C> TreeMap<K, V, C>
     /// let vec: Vec<(&str, i32)> = map.into_iter().collect();
     /// assert_eq!(vec, vec![("a", 1), ("b", 2), ("c", 3)]);
     /// ```
+    #[allow(clippy::should_implement_trait)]
     pub fn into_iter(self) -> IntoIter<K, V> {
         let TreeMap { root, length, .. } = self;
         let stk = match root {
@@ -533,6 +572,105 @@ impl<K, V, C> TreeMap<K, V, C>
         f(&mut self.root, &self.cmp, key)
     }
 
+    /// Returns the entry with the smallest key, or `None` if the map is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::TreeMap;
+    ///
+    /// let map: TreeMap<i32, &str> = vec![(2, "b"), (1, "a")].into_iter().collect();
+    /// assert_eq!(map.first_key_value(), Some((&1, &"a")));
+    /// ```
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        let mut node = self.root.as_deref()?;
+        while let Some(ref left) = node.left {
+            node = left;
+        }
+        Some((&node.key, &node.value))
+    }
+
+    /// Returns the entry with the largest key, or `None` if the map is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::TreeMap;
+    ///
+    /// let map: TreeMap<i32, &str> = vec![(2, "b"), (1, "a")].into_iter().collect();
+    /// assert_eq!(map.last_key_value(), Some((&2, &"b")));
+    /// ```
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        let mut node = self.root.as_deref()?;
+        while let Some(ref right) = node.right {
+            node = right;
+        }
+        Some((&node.key, &node.value))
+    }
+
+    /// Returns the entry at `index` in the map's iteration order, or `None`
+    /// if `index` is out of bounds, in O(log n).
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::TreeMap;
+    ///
+    /// let map: TreeMap<i32, &str> = vec![(5, "b"), (1, "a"), (9, "c")].into_iter().collect();
+    /// assert_eq!(map.select(1), Some((&5, &"b")));
+    /// assert_eq!(map.select(3), None);
+    /// ```
+    pub fn select(&self, mut index: usize) -> Option<(&K, &V)> {
+        let mut current = &self.root;
+        while let Some(ref node) = *current {
+            let left = size(&node.left);
+            if index < left {
+                current = &node.left;
+            } else if index == left {
+                return Some((&node.key, &node.value));
+            } else {
+                index -= left + 1;
+                current = &node.right;
+            }
+        }
+        None
+    }
+
+    /// Returns the number of keys in the map that are less than `key`, which
+    /// is the index `key` has or would have in the map's iteration order, in
+    /// O(log n).
+    ///
+    /// The key may be any borrowed form of the map's key type, but the ordering
+    /// on the borrowed form *must* match the ordering on the key type.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::TreeMap;
+    ///
+    /// let map: TreeMap<i32, ()> = vec![(5, ()), (1, ()), (9, ())].into_iter().collect();
+    /// assert_eq!(map.rank(&5), 1);
+    /// assert_eq!(map.rank(&6), 2);
+    /// assert_eq!(map.rank(&0), 0);
+    /// ```
+    pub fn rank<Q: ?Sized>(&self, key: &Q) -> usize
+        where C: Compare<Q, K>
+    {
+        let mut rank = 0;
+        let mut current = &self.root;
+        while let Some(ref node) = *current {
+            match self.cmp.compare(key, &node.key) {
+                Less => current = &node.left,
+                Equal => return rank + size(&node.left),
+                Greater => {
+                    rank += size(&node.left) + 1;
+                    current = &node.right;
+                }
+            }
+        }
+        rank
+    }
+
     /// Inserts a key-value pair from the map. If the key already had a value
     /// present in the map, that value is returned. Otherwise, `None` is returned.
     ///
@@ -603,9 +741,11 @@ impl<K, V, C> TreeMap<K, V, C>
     pub fn get_or_insert<F>(&mut self, key: K, default: F) -> &mut V
         where F: FnOnce() -> V
     {
-        let (inserted, ret) = insert(&mut self.root, key, default, &self.cmp);
+        let (inserted, index) = insert(&mut self.root, key, default, &self.cmp);
         self.length += inserted;
-        unsafe { &mut *ret }
+        // Rebalancing moves the nodes around, so look the value up again by
+        // its index rather than holding on to a pointer into the tree.
+        tree_select_mut(&mut self.root, index).unwrap()
     }
 
     /// Returns the value for which `f(key)` returns `Equal`. `f` is invoked
@@ -664,6 +804,48 @@ impl<K, V, C> TreeMap<K, V, C>
     {
         tree_find_with_mut(&mut self.root, f)
     }
+
+    /// Splits the map in two at `key`, returning the entries whose keys are
+    /// greater than or equal to `key` as a new map and keeping the rest.
+    ///
+    /// Both halves are rebuilt from their sorted entries, which takes O(n).
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::TreeMap;
+    ///
+    /// let mut a: TreeMap<u32, char> = (0..5).zip("abcde".chars()).collect();
+    /// let b = a.split_off(&3);
+    ///
+    /// assert_eq!(a.into_iter().collect::<Vec<_>>(), [(0, 'a'), (1, 'b'), (2, 'c')]);
+    /// assert_eq!(b.into_iter().collect::<Vec<_>>(), [(3, 'd'), (4, 'e')]);
+    /// ```
+    #[allow(clippy::redundant_field_names)]
+    pub fn split_off<Q: ?Sized>(&mut self, key: &Q) -> TreeMap<K, V, C>
+        where C: Compare<Q, K> + Clone
+    {
+        let cmp = self.cmp.clone();
+        let mut entries = replace(self, TreeMap::with_comparator(cmp.clone())).into_iter();
+        let mut low = Vec::new();
+        let mut high = None;
+        for (k, v) in entries.by_ref() {
+            if cmp.compares_le(key, &k) {
+                high = Some((k, v));
+                break;
+            }
+            low.push((k, v));
+        }
+        let high: Vec<(K, V)> = high.into_iter().chain(entries).collect();
+
+        self.length = low.len();
+        self.root = build_sorted(low.len(), &mut low.into_iter());
+        TreeMap {
+            length: high.len(),
+            root: build_sorted(high.len(), &mut high.into_iter()),
+            cmp: cmp,
+        }
+    }
 }
 
 // range iterators.
@@ -700,12 +882,13 @@ impl<K, V, C> TreeMap<K, V, C>
         }
     }
 
-    fn bound_setup<'a, D, Q: ?Sized>(&self,
-                                     mut iter: IterMut<'a, K, V, D>,
-                                     bound: Bound<&Q>)
-                                     -> IterMut<'a, K, V, D>
+    fn bound_setup<D, A, Q: ?Sized>(&self,
+                                    mut iter: RawIter<K, V, D, A>,
+                                    bound: Bound<&Q>)
+                                    -> RawIter<K, V, D, A>
         where C: Compare<Q, K>,
-              D: Direction
+              D: Direction,
+              A: Access
     {
         loop {
             if !iter.node.is_null() {
@@ -725,6 +908,57 @@ impl<K, V, C> TreeMap<K, V, C>
         }
     }
 
+    // Descends from `root` to the first node lying between `min` and `max` and
+    // positions a pair of iterators at the two endpoints below it. `root` must
+    // have been obtained through `A`.
+    #[allow(clippy::redundant_field_names)]
+    fn raw_range<A, Min: ?Sized, Max: ?Sized>(&self,
+                                              root: *mut TreeNode<K, V>,
+                                              min: Bound<&Min>,
+                                              max: Bound<&Max>)
+                                              -> RawRange<K, V, A>
+        where C: Compare<Min, K> + Compare<Max, K>,
+              A: Access
+    {
+        let mut node = root;
+        while !node.is_null() {
+            let key = unsafe { &(*node).key };
+            match (self.compare_bound::<Forward, Min>(min, key),
+                   self.compare_bound::<Backward, Max>(max, key)) {
+                // If both endpoints are in the same subtree, descend into that subtree
+                (Less, Less) => node = unsafe { A::child(ptr::addr_of_mut!((*node).left)) },
+                (Greater, Greater) => node = unsafe { A::child(ptr::addr_of_mut!((*node).right)) },
+                // If start endpoint is actually > the end endpoint, return empty iterator
+                (Equal, Less) | (Greater, Less) | (Greater, Equal) => break,
+                (Less, Equal) | (Less, Greater) | (Equal, Equal) | (Equal, Greater) => {
+                    // We now know that the iterator will be non-empty.
+                    // Populate the iterators, and count the nodes of the
+                    // subtree before and up to the end of the range.
+                    let front = unsafe {
+                        count_below::<K, V, A, _>(node, |k| self.compare_bound::<Forward, Min>(min, k) == Greater)
+                    };
+                    let back = unsafe {
+                        count_below::<K, V, A, _>(node, |k| self.compare_bound::<Backward, Max>(max, k) != Less)
+                    };
+                    return RawRange {
+                        start: self.bound_setup(RawIter::new(node), min),
+                        end: self.bound_setup(RawIter::new(node), max),
+                        root: node,
+                        front: front,
+                        remaining: back - front,
+                    };
+                }
+            }
+        }
+        RawRange {
+            start: RawIter::new(ptr::null_mut()),
+            end: RawIter::new(ptr::null_mut()),
+            root: ptr::null_mut(),
+            front: 0,
+            remaining: 0,
+        }
+    }
+
     /// Constructs a mutable double-ended iterator over a sub-range of elements in the map, starting
     /// at min, and ending at max. If min is `Unbounded`, then it will be treated as "negative
     /// infinity", and if max is `Unbounded`, then it will be treated as "positive infinity".
@@ -751,50 +985,14 @@ impl<K, V, C> TreeMap<K, V, C>
     pub fn range_mut<'a, Min: ?Sized, Max: ?Sized>(&'a mut self,
                                                    min: Bound<&Min>,
                                                    max: Bound<&Max>)
-                                                   -> RangeMut<'a, K, V>
+                                                   -> RangeMut<'a, K, V, C>
         where C: Compare<Min, K> + Compare<Max, K>
     {
-        let none = None;
-        let mut node = &self.root;
-        loop {
-            match node {
-                &None => {
-                    return RangeMut {
-                        start: (unsafe { &mut *(self as *const Self as *mut Self) }).iter_mut_dir(),
-                        end: (unsafe { &mut *(self as *const Self as *mut Self) }).iter_mut_dir(),
-                        empty: true,
-                    }
-                }
-                &Some(ref n) => {
-                    match (self.compare_bound::<Forward, Min>(min, &n.key),
-                           self.compare_bound::<Backward, Max>(max, &n.key)) {
-                        // If both endpoints are in the same subtree, descend into that subtree
-                        (Less, Less) => node = &n.left,
-                        (Greater, Greater) => node = &n.right,
-                        // If start endpoint is actually > the end endpoint, return empty iterator
-                        (Equal, Less) | (Greater, Less) | (Greater, Equal) => node = &none,
-                        (Less, Equal) | (Less, Greater) | (Equal, Equal) | (Equal, Greater) => {
-                            // We now know that the iterator will be non-empty.
-                            // Populate the iterators.
-                            let start: IterMut<K, V, Forward> = IterMut {
-                                stack: vec![],
-                                node: n.deref() as *const TreeNode<K, V> as *mut TreeNode<K, V>,
-                                direction: PhantomData,
-                            };
-                            let end: IterMut<K, V, Backward> = IterMut {
-                                stack: vec![],
-                                node: n.deref() as *const TreeNode<K, V> as *mut TreeNode<K, V>,
-                                direction: PhantomData,
-                            };
-                            return RangeMut {
-                                start: self.bound_setup(start, min),
-                                end: self.bound_setup(end, max),
-                                empty: false,
-                            };
-                        }
-                    };
-                }
-            }
+        let root = unsafe { Exclusive::child(&mut self.root) };
+        RangeMut {
+            range: self.raw_range(root, min, max),
+            cmp: &self.cmp,
+            marker: PhantomData,
         }
     }
 
@@ -823,21 +1021,422 @@ impl<K, V, C> TreeMap<K, V, C>
     pub fn range<'a, Min: ?Sized, Max: ?Sized>(&'a self,
                                                min: Bound<&Min>,
                                                max: Bound<&Max>)
-                                               -> Range<'a, K, V>
+                                               -> Range<'a, K, V, C>
+        where C: Compare<Min, K> + Compare<Max, K>
+    {
+        let root = unsafe { Shared::child(&self.root as *const _ as *mut _) };
+        Range {
+            range: self.raw_range(root, min, max),
+            cmp: &self.cmp,
+            marker: PhantomData,
+        }
+    }
+
+    /// Like `range`, but takes the bounds as a standard range expression
+    /// such as `a..b`, `a..=b` or `..b`, or as any other `RangeBounds`.
+    ///
+    /// A full range `..` needs the key type spelled out, as in
+    /// `range_by::<K, _>(..)`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use stable_bst::TreeMap;
+    ///
+    /// let map: TreeMap<i32, char> = vec![(3, 'a'), (5, 'b'), (8, 'c')].into_iter().collect();
+    /// assert!(map.range_by(4..8).eq(vec![(&5, &'b')]));
+    /// assert!(map.range_by(4..=8).map(|(_, v)| *v).eq(vec!['b', 'c']));
+    /// assert_eq!(map.range_by(..5).next_back(), Some((&3, &'a')));
+    /// ```
+    pub fn range_by<Q: ?Sized, R>(&self, range: R) -> Range<'_, K, V, C>
+        where C: Compare<Q, K>,
+              R: RangeBounds<Q>
+    {
+        self.range(range.start_bound().into(), range.end_bound().into())
+    }
+
+    /// Like `range_mut`, but takes the bounds as any `RangeBounds`, as
+    /// `range_by` does.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use stable_bst::TreeMap;
+    ///
+    /// let mut map: TreeMap<i32, i32> = (0..5).map(|i| (i, 0)).collect();
+    /// for (_, v) in map.range_mut_by(3..) {
+    ///     *v = 1;
+    /// }
+    /// assert!(map.values().cloned().eq(vec![0, 0, 0, 1, 1]));
+    /// ```
+    pub fn range_mut_by<Q: ?Sized, R>(&mut self, range: R) -> RangeMut<'_, K, V, C>
+        where C: Compare<Q, K>,
+              R: RangeBounds<Q>
+    {
+        self.range_mut(range.start_bound().into(), range.end_bound().into())
+    }
+
+    /// Returns a double-ended iterator over the keys in a sub-range of the
+    /// map, with the bounds interpreted as by `range`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use stable_bst::TreeMap;
+    /// use stable_bst::Bound::{Excluded, Included};
+    ///
+    /// let map: TreeMap<i32, char> = vec![(1, 'a'), (2, 'b'), (3, 'c')].into_iter().collect();
+    /// assert!(map.keys_range(Excluded(&1), Included(&3)).eq(vec![&2, &3]));
+    /// ```
+    pub fn keys_range<'a, Min: ?Sized, Max: ?Sized>(&'a self,
+                                                    min: Bound<&Min>,
+                                                    max: Bound<&Max>)
+                                                    -> KeysRange<'a, K, V>
+        where C: Compare<Min, K> + Compare<Max, K>
+    {
+        KeysRange(self.range(min, max).without_comparator())
+    }
+
+    /// Returns a double-ended iterator over the values in a sub-range of the
+    /// map, in order of their keys, with the bounds interpreted as by `range`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use stable_bst::TreeMap;
+    /// use stable_bst::Bound::{Included, Unbounded};
+    ///
+    /// let map: TreeMap<i32, char> = vec![(1, 'a'), (2, 'b'), (3, 'c')].into_iter().collect();
+    /// assert!(map.values_range(Included(&2), Unbounded).eq(vec![&'b', &'c']));
+    /// ```
+    pub fn values_range<'a, Min: ?Sized, Max: ?Sized>(&'a self,
+                                                      min: Bound<&Min>,
+                                                      max: Bound<&Max>)
+                                                      -> ValuesRange<'a, K, V>
         where C: Compare<Min, K> + Compare<Max, K>
     {
-        Range { range: (unsafe { &mut *(self as *const Self as *mut Self) }).range_mut(min, max) }
+        ValuesRange(self.range(min, max).without_comparator())
+    }
+
+    /// Returns a double-ended iterator over mutable references to the values
+    /// in a sub-range of the map, in order of their keys, with the bounds
+    /// interpreted as by `range`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use stable_bst::TreeMap;
+    /// use stable_bst::Bound::{Excluded, Unbounded};
+    ///
+    /// let mut map: TreeMap<i32, i32> = (0..5).map(|i| (i, 0)).collect();
+    /// for v in map.values_range_mut(Unbounded, Excluded(&2)) {
+    ///     *v += 1;
+    /// }
+    /// assert!(map.values().cloned().eq(vec![1, 1, 0, 0, 0]));
+    /// ```
+    pub fn values_range_mut<'a, Min: ?Sized, Max: ?Sized>(&'a mut self,
+                                                          min: Bound<&Min>,
+                                                          max: Bound<&Max>)
+                                                          -> ValuesRangeMut<'a, K, V>
+        where C: Compare<Min, K> + Compare<Max, K>
+    {
+        ValuesRangeMut(self.range_mut(min, max).without_comparator())
+    }
+
+    /// Like `keys_range`, but in descending order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use stable_bst::TreeMap;
+    /// use stable_bst::Bound::{Included, Unbounded};
+    ///
+    /// let map: TreeMap<i32, char> = vec![(1, 'a'), (2, 'b'), (3, 'c')].into_iter().collect();
+    /// assert!(map.rev_keys_range(Unbounded, Included(&2)).eq(vec![&2, &1]));
+    /// ```
+    pub fn rev_keys_range<'a, Min: ?Sized, Max: ?Sized>(&'a self,
+                                                        min: Bound<&Min>,
+                                                        max: Bound<&Max>)
+                                                        -> iter::Rev<KeysRange<'a, K, V>>
+        where C: Compare<Min, K> + Compare<Max, K>
+    {
+        self.keys_range(min, max).rev()
+    }
+
+    /// Like `values_range`, but in descending order of the keys.
+    pub fn rev_values_range<'a, Min: ?Sized, Max: ?Sized>(&'a self,
+                                                          min: Bound<&Min>,
+                                                          max: Bound<&Max>)
+                                                          -> iter::Rev<ValuesRange<'a, K, V>>
+        where C: Compare<Min, K> + Compare<Max, K>
+    {
+        self.values_range(min, max).rev()
+    }
+
+    /// Like `values_range_mut`, but in descending order of the keys.
+    pub fn rev_values_range_mut<'a, Min: ?Sized, Max: ?Sized>(&'a mut self,
+                                                              min: Bound<&Min>,
+                                                              max: Bound<&Max>)
+                                                              -> iter::Rev<ValuesRangeMut<'a, K, V>>
+        where C: Compare<Min, K> + Compare<Max, K>
+    {
+        self.values_range_mut(min, max).rev()
+    }
+}
+
+// structural checks.
+
+/// A way in which a `TreeMap` fails to be a valid AA tree, as reported by
+/// `TreeMap::check_invariants`.
+///
+/// Nodes are identified by their position in the map's iteration order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum InvariantViolation {
+    /// The key at `position` does not compare greater than the key before it.
+    Order { position: usize },
+    /// The node at `position` is missing a child but is on `level` rather than
+    /// on level 1.
+    LeafLevel { position: usize, level: usize },
+    /// The node at `position` is a left child but is not exactly one level
+    /// below its parent, for example because it forms a horizontal link.
+    LeftLevel { position: usize },
+    /// The node at `position` is a right child but is neither on its parent's
+    /// level nor one level below it.
+    RightLevel { position: usize },
+    /// The node at `position` is a right child on its parent's level, and so
+    /// is the parent: two horizontal links in a row.
+    DoubleHorizontal { position: usize },
+    /// The node at `position` records `recorded` nodes in its subtree but has
+    /// `actual`.
+    SubtreeSize { position: usize, recorded: usize, actual: usize },
+    /// The map records a length of `recorded` but holds `actual` entries.
+    Length { recorded: usize, actual: usize },
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InvariantViolation::Order { position } => {
+                write!(f, "key at position {} is out of order", position)
+            }
+            InvariantViolation::LeafLevel { position, level } => {
+                write!(f, "node at position {} lacks a child but is on level {}", position, level)
+            }
+            InvariantViolation::LeftLevel { position } => {
+                write!(f, "left child at position {} is not one level below its parent", position)
+            }
+            InvariantViolation::RightLevel { position } => {
+                write!(f, "right child at position {} is not on or just below its parent's level", position)
+            }
+            InvariantViolation::DoubleHorizontal { position } => {
+                write!(f, "right child at position {} forms a second horizontal link", position)
+            }
+            InvariantViolation::SubtreeSize { position, recorded, actual } => {
+                write!(f, "node at position {} records {} nodes in its subtree but has {}", position, recorded, actual)
+            }
+            InvariantViolation::Length { recorded, actual } => {
+                write!(f, "map records {} entries but holds {}", recorded, actual)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl ::std::error::Error for InvariantViolation {}
+
+/// Statistics on the shape of a `TreeMap`, as returned by `TreeMap::stats`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TreeStats {
+    /// The number of nodes on the longest path from the root to a leaf.
+    pub height: usize,
+    /// The number of nodes on each AA level, starting with level 1.
+    pub nodes_per_level: Vec<usize>,
+    /// The average number of nodes a search for a key in the map visits,
+    /// counting the node holding the key. Zero for an empty map.
+    pub average_search_depth: f64,
+}
+
+impl<K, V, C> TreeMap<K, V, C>
+    where C: Compare<K>
+{
+    /// Checks that the map is a valid AA tree: keys in ascending order under
+    /// the map's comparator, every node that lacks a child on level 1, left
+    /// children one level below their parents, right children on their
+    /// parents' level or one below but never two horizontal links in a row,
+    /// subtree sizes that match the number of nodes in each subtree, and a
+    /// recorded length that matches the number of entries.
+    ///
+    /// The first violation found is returned. This never fails for a map that
+    /// has only been changed through its own methods and whose comparator is a
+    /// total order.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::TreeMap;
+    ///
+    /// let map: TreeMap<u32, ()> = (0..100).map(|i| (i * 7 % 100, ())).collect();
+    /// assert_eq!(map.check_invariants(), Ok(()));
+    /// ```
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        let mut checker = Checker {
+            cmp: &self.cmp,
+            prev: None,
+            position: 0,
+        };
+        if let Some(ref root) = self.root {
+            checker.check(root, Link::Root)?;
+        }
+        if checker.position != self.length {
+            return Err(InvariantViolation::Length {
+                recorded: self.length,
+                actual: checker.position,
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns statistics on the shape of the tree.
+    ///
+    /// Like `check_invariants`, this is meant for trees that may be broken,
+    /// so nodes with the invalid level 0 are left out of `nodes_per_level`
+    /// rather than causing a panic, but still count towards the height and
+    /// the search depth.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::TreeMap;
+    ///
+    /// let map: TreeMap<u32, ()> = (0..7).map(|i| (i, ())).collect();
+    /// let stats = map.stats();
+    /// assert_eq!(stats.height, 3);
+    /// assert_eq!(stats.nodes_per_level.iter().sum::<usize>(), 7);
+    /// assert!(stats.average_search_depth <= 3.0);
+    /// ```
+    pub fn stats(&self) -> TreeStats {
+        // Adds up the depths of the nodes, and counts them.
+        fn walk<K, V>(node: &TreeNode<K, V>, depth: usize, stats: &mut TreeStats, totals: &mut (usize, usize)) {
+            if node.level > 0 {
+                if stats.nodes_per_level.len() < node.level {
+                    stats.nodes_per_level.resize(node.level, 0);
+                }
+                stats.nodes_per_level[node.level - 1] += 1;
+            }
+            if depth > stats.height {
+                stats.height = depth;
+            }
+            totals.0 += depth;
+            totals.1 += 1;
+            for child in node.left.iter().chain(node.right.iter()) {
+                walk(child, depth + 1, stats, totals);
+            }
+        }
+
+        let mut stats = TreeStats {
+            height: 0,
+            nodes_per_level: Vec::new(),
+            average_search_depth: 0.0,
+        };
+        if let Some(ref root) = self.root {
+            let mut totals = (0, 0);
+            walk(root, 1, &mut stats, &mut totals);
+            stats.average_search_depth = totals.0 as f64 / totals.1 as f64;
+        }
+        stats
+    }
+}
+
+// Walks a tree in order for `check_invariants`, tracking the previous key and
+// the position of the current node.
+struct Checker<'a, K: 'a, C: 'a> {
+    cmp: &'a C,
+    prev: Option<&'a K>,
+    position: usize,
+}
+
+// How a node hangs off its parent.
+#[derive(Clone, Copy)]
+enum Link {
+    Root,
+    Left { parent_level: usize },
+    Right { parent_level: usize, parent_horizontal: bool },
+}
+
+impl<'a, K, C: Compare<K>> Checker<'a, K, C> {
+    // Returns the number of nodes in the subtree of `node`.
+    #[allow(clippy::redundant_field_names)]
+    fn check<V>(&mut self, node: &'a TreeNode<K, V>, link: Link) -> Result<usize, InvariantViolation> {
+        let mut size = 1;
+        if let Some(ref left) = node.left {
+            size += self.check(left, Link::Left { parent_level: node.level })?;
+        }
+
+        let position = self.position;
+        if let Some(prev) = self.prev {
+            if !self.cmp.compares_lt(prev, &node.key) {
+                return Err(InvariantViolation::Order { position: position });
+            }
+        }
+        self.prev = Some(&node.key);
+        self.position += 1;
+
+        if node.level != 1 && (node.left.is_none() || node.right.is_none()) {
+            return Err(InvariantViolation::LeafLevel {
+                position: position,
+                level: node.level,
+            });
+        }
+        let mut horizontal = false;
+        match link {
+            Link::Root => {}
+            Link::Left { parent_level } => {
+                if node.level + 1 != parent_level {
+                    return Err(InvariantViolation::LeftLevel { position: position });
+                }
+            }
+            Link::Right { parent_level, parent_horizontal } => {
+                horizontal = node.level == parent_level;
+                if !horizontal && node.level + 1 != parent_level {
+                    return Err(InvariantViolation::RightLevel { position: position });
+                }
+                if horizontal && parent_horizontal {
+                    return Err(InvariantViolation::DoubleHorizontal { position: position });
+                }
+            }
+        }
+
+        if let Some(ref right) = node.right {
+            size += self.check(right,
+                               Link::Right {
+                                   parent_level: node.level,
+                                   parent_horizontal: horizontal,
+                               })?;
+        }
+        if node.size != size {
+            return Err(InvariantViolation::SubtreeSize {
+                position: position,
+                recorded: node.size,
+                actual: size,
+            });
+        }
+        Ok(size)
     }
 }
 
-pub struct RangeMut<'a, K: 'a, V: 'a> {
-    start: IterMut<'a, K, V, Forward>,
-    end: IterMut<'a, K, V, Backward>,
-    empty: bool,
+/// Lazy double-ended iterator over a sub-range of a map that allows for the
+/// mutation of the values.
+pub struct RangeMut<'a, K: 'a, V: 'a, C: 'a = Natural<K>> {
+    range: RawRange<K, V, Exclusive>,
+    cmp: &'a C,
+    marker: PhantomData<&'a mut TreeNode<K, V>>,
 }
 
-pub struct Range<'a, K: 'a, V: 'a> {
-    range: RangeMut<'a, K, V>,
+/// Lazy double-ended iterator over a sub-range of a map.
+pub struct Range<'a, K: 'a, V: 'a, C: 'a = Natural<K>> {
+    range: RawRange<K, V, Shared>,
+    cmp: &'a C,
+    marker: PhantomData<&'a TreeNode<K, V>>,
 }
 
 pub trait Direction {
@@ -860,87 +1459,153 @@ impl Direction for Backward {
     }
 }
 
+// How a raw iterator steps from a node to one of its children. Iterators
+// created from `&TreeMap` use `Shared`, which never creates a mutable
+// reference; iterators created from `&mut TreeMap` use `Exclusive`, whose
+// pointers may be used to hand out `&mut V`. Neither creates a reference to a
+// whole node, so the two ends of a `RangeMut` can't invalidate each other's
+// pointers, nor the `&mut V`s that have already been handed out.
+trait Access {
+    // `link` must point to the `left` or `right` field of a live node (or to
+    // the root of a map) that was reached through this same `Access`.
+    unsafe fn child<K, V>(link: *mut Option<Box<TreeNode<K, V>>>) -> *mut TreeNode<K, V>;
+}
+
+enum Shared {}
+
+impl Access for Shared {
+    #[inline]
+    unsafe fn child<K, V>(link: *mut Option<Box<TreeNode<K, V>>>) -> *mut TreeNode<K, V> {
+        match *link {
+            Some(ref n) => ptr::addr_of!(**n) as *mut TreeNode<K, V>,
+            None => ptr::null_mut(),
+        }
+    }
+}
+
+enum Exclusive {}
+
+impl Access for Exclusive {
+    #[inline]
+    unsafe fn child<K, V>(link: *mut Option<Box<TreeNode<K, V>>>) -> *mut TreeNode<K, V> {
+        match *link {
+            Some(ref mut n) => ptr::addr_of_mut!(**n),
+            None => ptr::null_mut(),
+        }
+    }
+}
+
 /// Lazy forward iterator over a map
-pub struct Iter<'a, K: 'a, V: 'a, D: Direction> {
-    iter_mut: IterMut<'a, K, V, D>,
+pub struct Iter<'a, K: 'a, V: 'a, D: Direction, C: 'a = Natural<K>> {
+    iter: RawIter<K, V, D, Shared>,
+    remaining: usize,
+    // The root of the map, to count the entries left after a `seek`.
+    root: *mut TreeNode<K, V>,
+    cmp: &'a C,
+    marker: PhantomData<&'a TreeNode<K, V>>,
 }
 
 /// Lazy forward iterator over a map that allows for the mutation of
 /// the values.
 pub struct IterMut<'a, K: 'a, V: 'a, D: Direction> {
-    stack: Vec<&'a mut TreeNode<K, V>>,
-    // Unfortunately, we require some unsafe-ness to get around the
-    // fact that we would be storing a reference *into* one of the
-    // nodes in the stack.
-    //
-    // As far as the compiler knows, this would let us invalidate the
-    // reference by assigning a new value to this node's position in
-    // its parent, which would cause this current one to be
-    // deallocated so this reference would be invalid. (i.e. the
-    // compilers complaints are 100% correct.)
-    //
-    // However, as far as you humans reading this code know (or are
-    // about to know, if you haven't read far enough down yet), we are
-    // only reading from the TreeNode.{left,right} fields. the only
-    // thing that is ever mutated is the .value field (although any
-    // actual mutation that happens is done externally, by the
-    // iterator consumer). So, don't be so concerned, rustc, we've got
-    // it under control.
-    //
+    iter: RawIter<K, V, D, Exclusive>,
+    remaining: usize,
+    marker: PhantomData<&'a mut TreeNode<K, V>>,
+}
+
+// The in-order traversal shared by all of the borrowing iterators. It only
+// deals in raw pointers; the wrappers above decide what kind of references
+// the nodes are turned into, and tie them to the lifetime of the borrow.
+struct RawIter<K, V, D, A> {
+    stack: Vec<*mut TreeNode<K, V>>,
+    // The root of the subtree still to be visited before the top of `stack`.
     // (This field can legitimately be null.)
     node: *mut TreeNode<K, V>,
-    direction: PhantomData<D>,
+    marker: PhantomData<(D, A)>,
 }
 
+// A pair of raw iterators that walk towards each other from the two ends of
+// a range, stopping when they meet. The range lies in the subtree of `root`,
+// where `front` nodes come before the front of the range and `remaining`
+// nodes are left between its two ends.
+struct RawRange<K, V, A> {
+    start: RawIter<K, V, Forward, A>,
+    end: RawIter<K, V, Backward, A>,
+    root: *mut TreeNode<K, V>,
+    front: usize,
+    remaining: usize,
+}
+
+/// Lazy double-ended iterator over the keys in a sub-range of a map.
+pub struct KeysRange<'a, K: 'a, V: 'a>(Range<'a, K, V, ()>);
+
+/// Lazy double-ended iterator over the values in a sub-range of a map.
+pub struct ValuesRange<'a, K: 'a, V: 'a>(Range<'a, K, V, ()>);
+
+/// Lazy double-ended iterator over the values in a sub-range of a map that
+/// allows for their mutation.
+pub struct ValuesRangeMut<'a, K: 'a, V: 'a>(RangeMut<'a, K, V, ()>);
+
 /// TreeMap keys iterator.
-pub struct Keys<'a, K: 'a, V: 'a>(iter::Map<Iter<'a, K, V, Forward>, fn((&'a K, &'a V)) -> &'a K>);
+#[allow(clippy::type_complexity)]
+pub struct Keys<'a, K: 'a, V: 'a>(iter::Map<Iter<'a, K, V, Forward, ()>, fn((&'a K, &'a V)) -> &'a K>);
 
 /// TreeMap values iterator.
-pub struct Values<'a, K: 'a, V: 'a>(iter::Map<Iter<'a, K, V, Forward>,
+#[allow(clippy::type_complexity)]
+pub struct Values<'a, K: 'a, V: 'a>(iter::Map<Iter<'a, K, V, Forward, ()>,
                                               fn((&'a K, &'a V)) -> &'a V>);
 
 /// TreeMap values iterator.
+#[allow(clippy::type_complexity)]
 pub struct ValuesMut<'a, K: 'a, V: 'a>(iter::Map<IterMut<'a, K, V, Forward>,
                                                  fn((&'a K, &'a mut V)) -> &'a mut V>);
 
-impl<'a, K, V, D: Direction> IterMut<'a, K, V, D> {
+impl<K, V, D: Direction, A: Access> RawIter<K, V, D, A> {
+    #[allow(clippy::redundant_field_names)]
+    fn new(node: *mut TreeNode<K, V>) -> RawIter<K, V, D, A> {
+        RawIter {
+            stack: vec![],
+            node: node,
+            marker: PhantomData,
+        }
+    }
+
     #[inline(always)]
-    fn next_(&mut self) -> Option<(&'a K, &'a mut V)> {
+    fn next_(&mut self) -> Option<*mut TreeNode<K, V>> {
         self.normalize();
         self.next_node()
     }
 
     fn normalize(&mut self) {
         while !self.node.is_null() {
-            let node = unsafe { &mut *self.node };
-            {
-                let next_node = if D::forward() {
-                    &mut node.left
+            let node = self.node;
+            self.node = unsafe {
+                if D::forward() {
+                    A::child(ptr::addr_of_mut!((*node).left))
                 } else {
-                    &mut node.right
-                };
-                self.node = deref_mut(next_node);
-            }
+                    A::child(ptr::addr_of_mut!((*node).right))
+                }
+            };
             self.stack.push(node);
         }
     }
 
-    fn next_node(&mut self) -> Option<(&'a K, &'a mut V)> {
-        return self.stack.pop().map(|node| {
-            let next_node = if D::forward() {
-                &mut node.right
+    fn next_node(&mut self) -> Option<*mut TreeNode<K, V>> {
+        let node = self.stack.pop()?;
+        self.node = unsafe {
+            if D::forward() {
+                A::child(ptr::addr_of_mut!((*node).right))
             } else {
-                &mut node.left
-            };
-            self.node = deref_mut(next_node);
-            (&node.key, &mut node.value)
-        });
+                A::child(ptr::addr_of_mut!((*node).left))
+            }
+        };
+        Some(node)
     }
 
     #[inline]
     fn traverse_before(&mut self) {
-        let node = unsafe { &mut *self.node };
-        self.node = deref_mut(&mut node.left);
+        let node = self.node;
+        self.node = unsafe { A::child(ptr::addr_of_mut!((*node).left)) };
         if D::forward() {
             self.stack.push(node);
         }
@@ -948,8 +1613,8 @@ impl<'a, K, V, D: Direction> IterMut<'a, K, V, D> {
 
     #[inline]
     fn traverse_after(&mut self) {
-        let node = unsafe { &mut *self.node };
-        self.node = deref_mut(&mut node.right);
+        let node = self.node;
+        self.node = unsafe { A::child(ptr::addr_of_mut!((*node).right)) };
         if !D::forward() {
             self.stack.push(node);
         }
@@ -958,97 +1623,387 @@ impl<'a, K, V, D: Direction> IterMut<'a, K, V, D> {
     #[inline]
     fn traverse_complete(&mut self) {
         if !self.node.is_null() {
-            unsafe {
-                self.stack.push(&mut *self.node);
-            }
+            self.stack.push(self.node);
             self.node = ptr::null_mut();
         }
     }
 }
 
+impl<K, V, A: Access> RawIter<K, V, Forward, A> {
+    // Skips the nodes whose keys `f` puts below its target, as in
+    // `find_with`: first by popping the ancestors below the target, each
+    // along with the subtree visited before it, then by descending from the
+    // subtree still to be visited to the first node at or above the target.
+    fn seek<F>(&mut self, f: &mut F)
+        where F: FnMut(&K) -> Ordering
+    {
+        while let Some(&top) = self.stack.last() {
+            if f(unsafe { &(*top).key }) != Greater {
+                break;
+            }
+            self.stack.pop();
+            self.node = unsafe { A::child(ptr::addr_of_mut!((*top).right)) };
+        }
+        let mut node = replace(&mut self.node, ptr::null_mut());
+        while !node.is_null() {
+            node = unsafe {
+                if f(&(*node).key) == Greater {
+                    A::child(ptr::addr_of_mut!((*node).right))
+                } else {
+                    self.stack.push(node);
+                    A::child(ptr::addr_of_mut!((*node).left))
+                }
+            };
+        }
+    }
+}
+
+impl<K, V, A: Access> RawRange<K, V, A> {
+    fn next(&mut self) -> Option<*mut TreeNode<K, V>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        self.front += 1;
+        self.start.next_()
+    }
+
+    fn next_back(&mut self) -> Option<*mut TreeNode<K, V>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        self.end.next_()
+    }
+
+    // Returns the node that `next` would yield.
+    fn peek(&mut self) -> Option<*mut TreeNode<K, V>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.start.normalize();
+        self.start.stack.last().cloned()
+    }
+
+    // Moves the front of the range to the first node that `f` does not put
+    // below its target. If the back of the range is below the target, so is
+    // every node left.
+    fn seek<F>(&mut self, mut f: F)
+        where F: FnMut(&K) -> Ordering
+    {
+        if self.remaining == 0 {
+            return;
+        }
+        self.end.normalize();
+        let last = *self.end.stack.last().expect("range ends out of step");
+        if f(unsafe { &(*last).key }) == Greater {
+            self.remaining = 0;
+            return;
+        }
+        self.start.seek(&mut f);
+        let front = unsafe { count_below::<K, V, A, _>(self.root, |k| f(k) == Greater) };
+        if front > self.front {
+            self.remaining -= front - self.front;
+            self.front = front;
+        }
+    }
+}
+
+// Counts the nodes in the subtree of `node` whose keys satisfy `below`, which
+// must hold for every key before some point in the order and for none after
+// it. `node` must have been obtained through `A`.
+unsafe fn count_below<K, V, A: Access, F>(mut node: *mut TreeNode<K, V>, mut below: F) -> usize
+    where F: FnMut(&K) -> bool
+{
+    let mut count = 0;
+    while !node.is_null() {
+        let left = A::child(ptr::addr_of_mut!((*node).left));
+        if below(&(*node).key) {
+            count += if left.is_null() { 1 } else { (*left).size + 1 };
+            node = A::child(ptr::addr_of_mut!((*node).right));
+        } else {
+            node = left;
+        }
+    }
+    count
+}
+
+// The returned references must not outlive the borrow of the map that `node`
+// was obtained from.
+unsafe fn node_ref<'a, K, V>(node: *mut TreeNode<K, V>) -> (&'a K, &'a V) {
+    (&(*node).key, &(*node).value)
+}
+
+// As `node_ref`, and additionally `node` must have been reached through
+// `Exclusive` and must not be yielded more than once.
+unsafe fn node_mut<'a, K, V>(node: *mut TreeNode<K, V>) -> (&'a K, &'a mut V) {
+    (&(*node).key, &mut (*node).value)
+}
+
 impl<'a, K, V, D: Direction> Iterator for IterMut<'a, K, V, D> {
     type Item = (&'a K, &'a mut V);
     /// Advances the iterator to the next node (in order) and return a
     /// tuple with a reference to the key and value. If there are no
     /// more nodes, return `None`.
     fn next(&mut self) -> Option<(&'a K, &'a mut V)> {
-        self.next_()
+        let node = self.iter.next_()?;
+        self.remaining -= 1;
+        Some(unsafe { node_mut(node) })
     }
 
     #[inline]
     fn size_hint(&self) -> (usize, Option<usize>) {
-        (0, None)
+        (self.remaining, Some(self.remaining))
     }
 }
 
-impl<'a, K, V> Iterator for RangeMut<'a, K, V> {
+impl<'a, K, V, D: Direction> ExactSizeIterator for IterMut<'a, K, V, D> {}
+impl<'a, K, V, D: Direction> FusedIterator for IterMut<'a, K, V, D> {}
+
+impl<'a, K, V, C> Iterator for RangeMut<'a, K, V, C> {
     type Item = (&'a K, &'a mut V);
     fn next(&mut self) -> Option<(&'a K, &'a mut V)> {
-        if self.empty {
-            return None;
+        self.range.next().map(|node| unsafe { node_mut(node) })
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.range.remaining, Some(self.range.remaining))
+    }
+}
+
+impl<'a, K, V, C> DoubleEndedIterator for RangeMut<'a, K, V, C> {
+    fn next_back(&mut self) -> Option<(&'a K, &'a mut V)> {
+        self.range.next_back().map(|node| unsafe { node_mut(node) })
+    }
+}
+
+impl<'a, K, V, C> ExactSizeIterator for RangeMut<'a, K, V, C> {}
+impl<'a, K, V, C> FusedIterator for RangeMut<'a, K, V, C> {}
+
+impl<'a, K, V, C> Iterator for Range<'a, K, V, C> {
+    type Item = (&'a K, &'a V);
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        self.range.next().map(|node| unsafe { node_ref(node) })
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.range.remaining, Some(self.range.remaining))
+    }
+}
+
+impl<'a, K, V, C> DoubleEndedIterator for Range<'a, K, V, C> {
+    fn next_back(&mut self) -> Option<(&'a K, &'a V)> {
+        self.range.next_back().map(|node| unsafe { node_ref(node) })
+    }
+}
+
+impl<'a, K, V, C> ExactSizeIterator for Range<'a, K, V, C> {}
+impl<'a, K, V, C> FusedIterator for Range<'a, K, V, C> {}
+
+impl<'a, K, V, D: Direction, C> Iter<'a, K, V, D, C> {
+    /// Returns the key of the entry that `next` would return, without
+    /// advancing the iterator.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::TreeMap;
+    ///
+    /// let map: TreeMap<i32, char> = vec![(1, 'a'), (2, 'b')].into_iter().collect();
+    /// let mut iter = map.iter();
+    /// assert_eq!(iter.peek_key(), Some(&1));
+    /// assert_eq!(iter.next(), Some((&1, &'a')));
+    /// ```
+    pub fn peek_key(&mut self) -> Option<&'a K> {
+        self.iter.normalize();
+        self.iter.stack.last().map(|&node| unsafe { &(*node).key })
+    }
+
+    // Forgets the comparator, for the iterators that never seek.
+    pub(crate) fn without_comparator(self) -> Iter<'a, K, V, D, ()> {
+        Iter {
+            iter: self.iter,
+            remaining: self.remaining,
+            root: self.root,
+            cmp: &(),
+            marker: PhantomData,
         }
-        self.start.normalize();
-        self.end.normalize();
-        self.empty = match (self.start.stack.last(), self.end.stack.last()) {
-            (None, _) | (_, None) => true,
-            (Some(n1), Some(n2)) => *n1 as *const TreeNode<K, V> == *n2 as *const TreeNode<K, V>,
-        };
-        self.start.next_node()
     }
-}
+}
+
+impl<'a, K, V, C> Iter<'a, K, V, Forward, C> {
+    /// Skips ahead to the first entry whose key is not less than `key` under
+    /// the map's comparator. Seeking backwards does nothing.
+    ///
+    /// This takes O(log n) time, and the iterator still knows exactly how
+    /// many entries are left.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::TreeMap;
+    ///
+    /// let map: TreeMap<i32, ()> = (0..10).map(|k| (k, ())).collect();
+    /// let mut iter = map.iter();
+    /// iter.seek(&6);
+    /// assert_eq!(iter.len(), 4);
+    /// assert_eq!(iter.next(), Some((&6, &())));
+    /// iter.seek(&2);
+    /// assert_eq!(iter.next(), Some((&7, &())));
+    /// ```
+    pub fn seek<Q: ?Sized>(&mut self, key: &Q)
+        where C: Compare<Q, K>
+    {
+        let cmp = self.cmp;
+        self.seek_with(|k| cmp.compare(key, k))
+    }
+
+    /// Like `seek`, but `f` compares the target with the key it is given, as
+    /// in `TreeMap::find_with`.
+    pub fn seek_with<F>(&mut self, mut f: F)
+        where F: FnMut(&K) -> Ordering
+    {
+        self.iter.seek(&mut f);
+        if self.root.is_null() {
+            return;
+        }
+        unsafe {
+            let below = count_below::<K, V, Shared, _>(self.root, |k| f(k) == Greater);
+            self.remaining = self.remaining.min((*self.root).size - below);
+        }
+    }
+}
+
+impl<'a, K, V, C> Range<'a, K, V, C> {
+    /// Returns the key of the entry that `next` would return, without
+    /// advancing the iterator.
+    pub fn peek_key(&mut self) -> Option<&'a K> {
+        self.range.peek().map(|node| unsafe { &(*node).key })
+    }
+
+    /// Skips ahead to the first entry whose key is not less than `key` under
+    /// the map's comparator. Entries that were already returned from the back
+    /// are not returned again, and seeking backwards does nothing.
+    ///
+    /// This takes O(log n) time, which allows for intersecting maps by
+    /// leapfrogging from one to another.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::TreeMap;
+    /// use stable_bst::Bound::Unbounded;
+    ///
+    /// let a: TreeMap<i32, ()> = vec![1, 3, 4, 7, 9].into_iter().map(|k| (k, ())).collect();
+    /// let b: TreeMap<i32, ()> = vec![2, 3, 5, 7, 8].into_iter().map(|k| (k, ())).collect();
+    ///
+    /// // Intersect the keys of `a` and `b`.
+    /// let mut both = vec![];
+    /// let mut iters = [a.range(Unbounded, Unbounded), b.range(Unbounded, Unbounded)];
+    /// let mut i = 0;
+    /// while let Some(&key) = iters[i].peek_key() {
+    ///     match iters[1 - i].peek_key() {
+    ///         Some(&other) if other == key => {
+    ///             both.push(key);
+    ///             iters[0].next();
+    ///             iters[1].next();
+    ///         }
+    ///         Some(_) => iters[1 - i].seek(&key),
+    ///         None => break,
+    ///     }
+    ///     i = 1 - i;
+    /// }
+    /// assert_eq!(both, [3, 7]);
+    /// ```
+    pub fn seek<Q: ?Sized>(&mut self, key: &Q)
+        where C: Compare<Q, K>
+    {
+        let cmp = self.cmp;
+        self.seek_with(|k| cmp.compare(key, k))
+    }
+
+    /// Like `seek`, but `f` compares the target with the key it is given, as
+    /// in `TreeMap::find_with`.
+    pub fn seek_with<F>(&mut self, f: F)
+        where F: FnMut(&K) -> Ordering
+    {
+        self.range.seek(f)
+    }
 
-impl<'a, K, V> DoubleEndedIterator for RangeMut<'a, K, V> {
-    fn next_back(&mut self) -> Option<(&'a K, &'a mut V)> {
-        if self.empty {
-            return None;
+    // Forgets the comparator, for the iterators that never seek.
+    pub(crate) fn without_comparator(self) -> Range<'a, K, V, ()> {
+        Range {
+            range: self.range,
+            cmp: &(),
+            marker: PhantomData,
         }
-        self.start.normalize();
-        self.end.normalize();
-        self.empty = match (self.start.stack.last(), self.end.stack.last()) {
-            (None, _) | (_, None) => true,
-            (Some(n1), Some(n2)) => *n1 as *const TreeNode<K, V> == *n2 as *const TreeNode<K, V>,
-        };
-        self.end.next_node()
     }
 }
 
-impl<'a, K, V> Iterator for Range<'a, K, V> {
-    type Item = (&'a K, &'a V);
-    fn next(&mut self) -> Option<(&'a K, &'a V)> {
-        self.range.next().map(|o| match o {
-            (k, v) => (k, &*v),
-        })
+impl<'a, K, V, C> RangeMut<'a, K, V, C> {
+    /// Returns the key of the entry that `next` would return, without
+    /// advancing the iterator.
+    pub fn peek_key(&mut self) -> Option<&'a K> {
+        self.range.peek().map(|node| unsafe { &(*node).key })
     }
-}
 
-impl<'a, K, V> DoubleEndedIterator for Range<'a, K, V> {
-    fn next_back(&mut self) -> Option<(&'a K, &'a V)> {
-        self.range.next_back().map(|o| match o {
-            (k, v) => (k, &*v),
-        })
+    /// Skips ahead to the first entry whose key is not less than `key` under
+    /// the map's comparator. See `Range::seek`.
+    pub fn seek<Q: ?Sized>(&mut self, key: &Q)
+        where C: Compare<Q, K>
+    {
+        let cmp = self.cmp;
+        self.seek_with(|k| cmp.compare(key, k))
+    }
+
+    /// Like `seek`, but `f` compares the target with the key it is given, as
+    /// in `TreeMap::find_with`.
+    pub fn seek_with<F>(&mut self, f: F)
+        where F: FnMut(&K) -> Ordering
+    {
+        self.range.seek(f)
+    }
+
+    // Forgets the comparator, for the iterators that never seek.
+    pub(crate) fn without_comparator(self) -> RangeMut<'a, K, V, ()> {
+        RangeMut {
+            range: self.range,
+            cmp: &(),
+            marker: PhantomData,
+        }
     }
 }
 
-impl<'a, K, V, D: Direction> Iterator for Iter<'a, K, V, D> {
+impl<'a, K, V, D: Direction, C> Iterator for Iter<'a, K, V, D, C> {
     type Item = (&'a K, &'a V);
     fn next(&mut self) -> Option<(&'a K, &'a V)> {
-        self.iter_mut.next_().map(|o| match o {
-            (k, v) => (k, &*v),
-        })
+        let node = self.iter.next_()?;
+        self.remaining -= 1;
+        Some(unsafe { node_ref(node) })
     }
 
     #[inline]
     fn size_hint(&self) -> (usize, Option<usize>) {
-        (0, None)
+        (self.remaining, Some(self.remaining))
     }
 }
 
-fn deref_mut<K, V>(x: &mut Option<Box<TreeNode<K, V>>>) -> *mut TreeNode<K, V> {
-    match *x {
-        Some(ref mut n) => &mut **n,
-        None => ptr::null_mut(),
-    }
-}
+impl<'a, K, V, D: Direction, C> ExactSizeIterator for Iter<'a, K, V, D, C> {}
+impl<'a, K, V, D: Direction, C> FusedIterator for Iter<'a, K, V, D, C> {}
+
+// The raw pointers in the borrowing iterators stand in for the `&'a` or
+// `&'a mut` borrow of the map that they were created from, so they are `Send`
+// and `Sync` exactly when that borrow would be.
+unsafe impl<'a, K: Sync, V: Sync, D: Direction, C: Sync> Send for Iter<'a, K, V, D, C> {}
+unsafe impl<'a, K: Sync, V: Sync, D: Direction, C: Sync> Sync for Iter<'a, K, V, D, C> {}
+unsafe impl<'a, K: Sync, V: Send, D: Direction> Send for IterMut<'a, K, V, D> {}
+unsafe impl<'a, K: Sync, V: Sync, D: Direction> Sync for IterMut<'a, K, V, D> {}
+unsafe impl<'a, K: Sync, V: Sync, C: Sync> Send for Range<'a, K, V, C> {}
+unsafe impl<'a, K: Sync, V: Sync, C: Sync> Sync for Range<'a, K, V, C> {}
+unsafe impl<'a, K: Sync, V: Send, C: Sync> Send for RangeMut<'a, K, V, C> {}
+unsafe impl<'a, K: Sync, V: Sync, C: Sync> Sync for RangeMut<'a, K, V, C> {}
 
 /// Lazy forward iterator over a map that consumes the map while iterating
 pub struct IntoIter<K, V> {
@@ -1059,9 +2014,9 @@ pub struct IntoIter<K, V> {
 impl<K, V> Iterator for IntoIter<K, V> {
     type Item = (K, V);
     #[inline]
+    #[allow(clippy::redundant_field_names, clippy::single_match)]
     fn next(&mut self) -> Option<(K, V)> {
-        while !self.stack.is_empty() {
-            let TreeNode { key, value, left, right, level } = self.stack.pop().unwrap();
+        while let Some(TreeNode { key, value, left, right, level, size }) = self.stack.pop() {
 
             match left {
                 Some(b_left) => {
@@ -1071,6 +2026,7 @@ impl<K, V> Iterator for IntoIter<K, V> {
                         left: None,
                         right: right,
                         level: level,
+                        size: size,
                     };
                     self.stack.push(n);
                     self.stack.push(*b_left);
@@ -1094,6 +2050,9 @@ impl<K, V> Iterator for IntoIter<K, V> {
     }
 }
 
+impl<K, V> ExactSizeIterator for IntoIter<K, V> {}
+impl<K, V> FusedIterator for IntoIter<K, V> {}
+
 impl<'a, K, V> Iterator for Keys<'a, K, V> {
     type Item = &'a K;
     #[inline]
@@ -1106,6 +2065,9 @@ impl<'a, K, V> Iterator for Keys<'a, K, V> {
     }
 }
 
+impl<'a, K, V> ExactSizeIterator for Keys<'a, K, V> {}
+impl<'a, K, V> FusedIterator for Keys<'a, K, V> {}
+
 impl<'a, K, V> Iterator for Values<'a, K, V> {
     type Item = &'a V;
     #[inline]
@@ -1118,6 +2080,9 @@ impl<'a, K, V> Iterator for Values<'a, K, V> {
     }
 }
 
+impl<'a, K, V> ExactSizeIterator for Values<'a, K, V> {}
+impl<'a, K, V> FusedIterator for Values<'a, K, V> {}
+
 impl<'a, K, V> Iterator for ValuesMut<'a, K, V> {
     type Item = &'a mut V;
     #[inline]
@@ -1130,8 +2095,78 @@ impl<'a, K, V> Iterator for ValuesMut<'a, K, V> {
     }
 }
 
+impl<'a, K, V> ExactSizeIterator for ValuesMut<'a, K, V> {}
+impl<'a, K, V> FusedIterator for ValuesMut<'a, K, V> {}
+
+impl<'a, K, V> Iterator for KeysRange<'a, K, V> {
+    type Item = &'a K;
+    #[inline]
+    fn next(&mut self) -> Option<&'a K> {
+        self.0.next().map(|(k, _)| k)
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for KeysRange<'a, K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<&'a K> {
+        self.0.next_back().map(|(k, _)| k)
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for KeysRange<'a, K, V> {}
+impl<'a, K, V> FusedIterator for KeysRange<'a, K, V> {}
+
+impl<'a, K, V> Iterator for ValuesRange<'a, K, V> {
+    type Item = &'a V;
+    #[inline]
+    fn next(&mut self) -> Option<&'a V> {
+        self.0.next().map(|(_, v)| v)
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for ValuesRange<'a, K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<&'a V> {
+        self.0.next_back().map(|(_, v)| v)
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for ValuesRange<'a, K, V> {}
+impl<'a, K, V> FusedIterator for ValuesRange<'a, K, V> {}
+
+impl<'a, K, V> Iterator for ValuesRangeMut<'a, K, V> {
+    type Item = &'a mut V;
+    #[inline]
+    fn next(&mut self) -> Option<&'a mut V> {
+        self.0.next().map(|(_, v)| v)
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for ValuesRangeMut<'a, K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<&'a mut V> {
+        self.0.next_back().map(|(_, v)| v)
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for ValuesRangeMut<'a, K, V> {}
+impl<'a, K, V> FusedIterator for ValuesRangeMut<'a, K, V> {}
+
 // Nodes keep track of their level in the tree, starting at 1 in the
-// leaves and with a red child sharing the level of the parent.
+// leaves and with a red child sharing the level of the parent, and of the
+// number of nodes in their subtree.
 #[derive(Clone)]
 struct TreeNode<K, V> {
     key: K,
@@ -1139,11 +2174,13 @@ struct TreeNode<K, V> {
     left: Option<Box<TreeNode<K, V>>>,
     right: Option<Box<TreeNode<K, V>>>,
     level: usize,
+    size: usize,
 }
 
 impl<K, V> TreeNode<K, V> {
     /// Creates a new tree node.
     #[inline]
+    #[allow(clippy::redundant_field_names)]
     pub fn new(key: K, value: V) -> TreeNode<K, V> {
         TreeNode {
             key: key,
@@ -1151,33 +2188,45 @@ impl<K, V> TreeNode<K, V> {
             left: None,
             right: None,
             level: 1,
+            size: 1,
         }
     }
 }
 
-// Remove left horizontal link by rotating right
-fn skew<K, V>(node: &mut Box<TreeNode<K, V>>) {
-    if node.left.as_ref().map_or(false, |x| x.level == node.level) {
-        let mut save = node.left.take().unwrap();
-        swap(&mut node.left, &mut save.right); // save.right now None
-        swap(node, &mut save);
-        node.right = Some(save);
+impl<K, V> AaNode for TreeNode<K, V> {
+    fn level(&self) -> usize {
+        self.level
+    }
+
+    fn set_level(&mut self, level: usize) {
+        self.level = level;
+    }
+
+    fn left(&self) -> &Option<Box<TreeNode<K, V>>> {
+        &self.left
+    }
+
+    fn right(&self) -> &Option<Box<TreeNode<K, V>>> {
+        &self.right
+    }
+
+    fn left_mut(&mut self) -> &mut Option<Box<TreeNode<K, V>>> {
+        &mut self.left
+    }
+
+    fn right_mut(&mut self) -> &mut Option<Box<TreeNode<K, V>>> {
+        &mut self.right
     }
-}
 
-// Remove dual horizontal link by rotating left and increasing level of
-// the parent
-fn split<K, V>(node: &mut Box<TreeNode<K, V>>) {
-    if node.right.as_ref().map_or(false,
-                                  |x| x.right.as_ref().map_or(false, |y| y.level == node.level)) {
-        let mut save = node.right.take().unwrap();
-        swap(&mut node.right, &mut save.left); // save.left now None
-        save.level += 1;
-        swap(node, &mut save);
-        node.left = Some(save);
+    fn update(&mut self) {
+        self.size = 1 + size(&self.left) + size(&self.right);
     }
 }
 
+fn size<K, V>(node: &Option<Box<TreeNode<K, V>>>) -> usize {
+    node.as_ref().map_or(0, |x| x.size)
+}
+
 // Next 2 functions have the same convention: comparator gets
 // at input current key and returns search_key cmp cur_key
 // (i.e. search_key.cmp(&cur_key))
@@ -1220,14 +2269,14 @@ fn tree_find_with_mut<K, V, F>(node: &mut Option<Box<TreeNode<K, V>>>, mut f: F)
     }
 }
 
-fn insert<'a, K, V, F, C>(node: &'a mut Option<Box<TreeNode<K, V>>>,
-                          key: K,
-                          default: F,
-                          cmp: &C)
-                          -> (usize, *mut V)
+// Returns the number of entries inserted (0 or 1) and the index of the entry
+// for `key`, which stays the same when the tree is rebalanced.
+fn insert<K, V, F, C>(node: &mut Option<Box<TreeNode<K, V>>>,
+                      key: K,
+                      default: F,
+                      cmp: &C)
+                      -> (usize, usize)
     where C: Compare<K>,
-          K: 'a,
-          V: 'a,
           F: FnOnce() -> V
 {
 
@@ -1236,26 +2285,46 @@ fn insert<'a, K, V, F, C>(node: &'a mut Option<Box<TreeNode<K, V>>>,
             match cmp.compare(&key, &save.key) {
                 Less => {
                     let ret = insert(&mut save.left, key, default, cmp);
-                    skew(save);
-                    split(save);
+                    save.size += ret.0;
+                    aa::skew(save);
+                    aa::split(save);
                     ret
                 }
                 Greater => {
-                    let ret = insert(&mut save.right, key, default, cmp);
-                    skew(save);
-                    split(save);
-                    ret
+                    let skipped = size(&save.left) + 1;
+                    let (inserted, index) = insert(&mut save.right, key, default, cmp);
+                    save.size += inserted;
+                    aa::skew(save);
+                    aa::split(save);
+                    (inserted, skipped + index)
                 }
-                Equal => (0, &mut save.value),
+                Equal => (0, size(&save.left)),
             }
         }
         None => {
             *node = Some(Box::new(TreeNode::new(key, default())));
-            (1, &mut node.as_mut().unwrap().value)
+            (1, 0)
         }
     }
 }
 
+fn tree_select_mut<K, V>(node: &mut Option<Box<TreeNode<K, V>>>, mut index: usize) -> Option<&mut V> {
+    let mut current = node;
+    while let Some(ref mut r) = *current {
+        let left = size(&r.left);
+        if index < left {
+            current = &mut r.left;
+        } else if index == left {
+            return Some(&mut r.value);
+        } else {
+            index -= left + 1;
+            current = &mut r.right;
+        }
+    }
+    None
+}
+
+#[allow(clippy::needless_return)]
 fn remove<K, V, C, Q: ?Sized>(node: &mut Option<Box<TreeNode<K, V>>>, key: &Q, cmp: &C) -> Option<V>
     where C: Compare<Q, K>
 {
@@ -1309,35 +2378,8 @@ fn remove<K, V, C, Q: ?Sized>(node: &mut Option<Box<TreeNode<K, V>>>, key: &Q, c
             };
 
             if rebalance {
-                let left_level = save.left.as_ref().map_or(0, |x| x.level);
-                let right_level = save.right.as_ref().map_or(0, |x| x.level);
-
-                // re-balance, if necessary
-                if left_level < save.level - 1 || right_level < save.level - 1 {
-                    save.level -= 1;
-
-                    if right_level > save.level {
-                        let save_level = save.level;
-                        for x in save.right.iter_mut() {
-                            x.level = save_level
-                        }
-                    }
-
-                    skew(save);
-
-                    for right in save.right.iter_mut() {
-                        skew(right);
-                        for x in right.right.iter_mut() {
-                            skew(x)
-                        }
-                    }
-
-                    split(save);
-                    for x in save.right.iter_mut() {
-                        split(x)
-                    }
-                }
-
+                save.update();
+                aa::rebalance(save);
                 return ret;
             }
         }
@@ -1351,6 +2393,48 @@ fn remove<K, V, C, Q: ?Sized>(node: &mut Option<Box<TreeNode<K, V>>>, key: &Q, c
     };
 }
 
+impl<K, V, C> TreeMap<K, V, C>
+    where C: Compare<K>
+{
+    // Builds a map out of `len` entries that are in strictly ascending order
+    // according to `cmp`, in O(len).
+    #[allow(clippy::redundant_field_names)]
+    pub(crate) fn from_sorted<I>(len: usize, mut iter: I, cmp: C) -> TreeMap<K, V, C>
+        where I: Iterator<Item = (K, V)>
+    {
+        TreeMap {
+            root: build_sorted(len, &mut iter),
+            length: len,
+            cmp: cmp,
+        }
+    }
+}
+
+// Builds a tree out of the first `len` entries of `iter`, which must be in
+// strictly ascending order, in O(len). Each subtree is split as evenly as
+// possible with any extra entry going to the right, which makes a subtree of
+// `n` nodes have level floor(log2(n + 1)) and leaves a node's right child on
+// its own level only when that child's subtree is perfect.
+#[allow(clippy::redundant_field_names)]
+fn build_sorted<K, V, I>(len: usize, iter: &mut I) -> Option<Box<TreeNode<K, V>>>
+    where I: Iterator<Item = (K, V)>
+{
+    if len == 0 {
+        return None;
+    }
+    let left = build_sorted((len - 1) / 2, iter);
+    let (key, value) = iter.next().expect("too few entries");
+    let right = build_sorted(len / 2, iter);
+    Some(Box::new(TreeNode {
+        level: left.as_ref().map_or(0, |x| x.level) + 1,
+        size: len,
+        key: key,
+        value: value,
+        left: left,
+        right: right,
+    }))
+}
+
 impl<K, V, C> iter::FromIterator<(K, V)> for TreeMap<K, V, C>
     where C: Compare<K> + Default
 {
@@ -1361,6 +2445,35 @@ impl<K, V, C> iter::FromIterator<(K, V)> for TreeMap<K, V, C>
     }
 }
 
+/// Builds the tree directly from the map's sorted entries, in O(n).
+impl<K: Ord, V> From<BTreeMap<K, V>> for TreeMap<K, V> {
+    fn from(map: BTreeMap<K, V>) -> TreeMap<K, V> {
+        TreeMap::from_sorted(map.len(), map.into_iter(), natural())
+    }
+}
+
+/// `BTreeMap` builds itself from sorted entries in O(n); entries ordered by
+/// some other comparator are sorted first.
+impl<K: Ord, V, C> From<TreeMap<K, V, C>> for BTreeMap<K, V>
+    where C: Compare<K>
+{
+    fn from(map: TreeMap<K, V, C>) -> BTreeMap<K, V> {
+        map.into_iter().collect()
+    }
+}
+
+impl<K: Ord, V: PartialEq> PartialEq<BTreeMap<K, V>> for TreeMap<K, V> {
+    fn eq(&self, other: &BTreeMap<K, V>) -> bool {
+        self.len() == other.len() && self.iter().eq(other)
+    }
+}
+
+impl<K: Ord, V: PartialEq> PartialEq<TreeMap<K, V>> for BTreeMap<K, V> {
+    fn eq(&self, other: &TreeMap<K, V>) -> bool {
+        other == self
+    }
+}
+
 impl<K, V, C> Extend<(K, V)> for TreeMap<K, V, C>
     where C: Compare<K>
 {
@@ -1372,12 +2485,16 @@ impl<K, V, C> Extend<(K, V)> for TreeMap<K, V, C>
     }
 }
 
-impl<K: Hash, V: Hash, C> Hash for TreeMap<K, V, C>
-    where C: Compare<K>
+/// Keys are hashed through the comparator, so that maps that compare equal
+/// hash the same even when the comparator considers distinct keys equal.
+impl<K, V: Hash, C> Hash for TreeMap<K, V, C>
+    where C: HashCompare<K>
 {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        for elt in self.iter() {
-            elt.hash(state);
+        state.write_usize(self.len());
+        for (k, v) in self.iter() {
+            self.cmp.hash_key(k, state);
+            v.hash(state);
         }
     }
 }
@@ -1386,8 +2503,8 @@ impl<'a, K, V, C> IntoIterator for &'a TreeMap<K, V, C>
     where C: Compare<K>
 {
     type Item = (&'a K, &'a V);
-    type IntoIter = Iter<'a, K, V, Forward>;
-    fn into_iter(self) -> Iter<'a, K, V, Forward> {
+    type IntoIter = Iter<'a, K, V, Forward, C>;
+    fn into_iter(self) -> Iter<'a, K, V, Forward, C> {
         self.iter()
     }
 }
@@ -1413,12 +2530,22 @@ impl<K, V, C> IntoIterator for TreeMap<K, V, C>
 }
 
 #[cfg(feature="ordered_iter")]
-impl<'a, K, V> ::ordered_iter::OrderedMapIterator for Iter<'a, K, V, Forward> {
+impl<'a, K, V, C> ::ordered_iter::OrderedMapIterator for Iter<'a, K, V, Forward, C> {
     type Key = &'a K;
     type Val = &'a V;
 }
 
 #[cfg(test)]
+#[allow(noop_method_call,
+        clippy::borrowed_box,
+        clippy::double_comparisons,
+        clippy::map_clone,
+        clippy::match_single_binding,
+        clippy::needless_borrowed_reference,
+        clippy::no_effect,
+        clippy::nonminimal_bool,
+        clippy::partialeq_to_none,
+        clippy::single_match)]
 mod test_treemap {
     use rand::{self, Rng};
 
@@ -1593,7 +2720,8 @@ mod test_treemap {
         }
     }
 
-    fn check_structure<K: Ord, V>(map: &TreeMap<K, V>) {
+    pub fn check_structure<K: Ord, V>(map: &TreeMap<K, V>) {
+        assert_eq!(map.check_invariants(), Ok(()));
         match map.root {
             Some(ref r) => {
                 check_left(&r.left, r);
@@ -1603,7 +2731,109 @@ mod test_treemap {
         }
     }
 
+    // Returns the node at in-order `position`.
+    fn node_at<K: Ord, V>(map: &mut TreeMap<K, V>, position: usize) -> &mut TreeNode<K, V> {
+        fn walk<K, V>(node: &mut TreeNode<K, V>, position: usize) -> &mut TreeNode<K, V> {
+            let left = node.left.as_ref().map_or(0, |l| count(l));
+            if position < left {
+                walk(node.left.as_mut().unwrap(), position)
+            } else if position == left {
+                node
+            } else {
+                walk(node.right.as_mut().unwrap(), position - left - 1)
+            }
+        }
+        fn count<K, V>(node: &TreeNode<K, V>) -> usize {
+            1 + node.left.as_ref().map_or(0, |l| count(l)) + node.right.as_ref().map_or(0, |r| count(r))
+        }
+        walk(map.root.as_mut().unwrap(), position)
+    }
+
+    #[test]
+    fn test_check_invariants() {
+        use super::InvariantViolation::*;
+
+        let empty: TreeMap<i32, ()> = TreeMap::new();
+        assert_eq!(empty.check_invariants(), Ok(()));
+
+        // 0..7 builds a perfect tree: 3 on top, 1 and 5 below, leaves below that.
+        let tree = || (0..7).map(|i| (i, ())).collect::<TreeMap<i32, ()>>();
+        assert_eq!(tree().check_invariants(), Ok(()));
+
+        let mut map = tree();
+        node_at(&mut map, 4).key = 6;
+        assert_eq!(map.check_invariants(), Err(Order { position: 5 }));
+
+        let mut map = tree();
+        node_at(&mut map, 5).right = None;
+        assert_eq!(map.check_invariants(), Err(LeafLevel { position: 5, level: 2 }));
+
+        let mut map = tree();
+        node_at(&mut map, 0).level = 2;
+        assert_eq!(map.check_invariants(), Err(LeafLevel { position: 0, level: 2 }));
+
+        let mut map = tree();
+        node_at(&mut map, 1).level = 3;
+        // Its left child is now two levels below it.
+        assert_eq!(map.check_invariants(), Err(LeftLevel { position: 0 }));
+
+        let mut map = tree();
+        node_at(&mut map, 6).level = 3;
+        assert_eq!(map.check_invariants(), Err(LeafLevel { position: 6, level: 3 }));
+
+        let mut map = tree();
+        map.root.as_mut().unwrap().right = Some(Box::new(TreeNode::new(5, ())));
+        map.length = 5;
+        assert_eq!(map.check_invariants(), Err(RightLevel { position: 4 }));
+
+        // 4 and 5 both horizontal off 3's right spine.
+        let mut map: TreeMap<i32, ()> = TreeMap::new();
+        map.insert(3, ());
+        map.root.as_mut().unwrap().right = Some(Box::new(TreeNode::new(4, ())));
+        assert_eq!(map.check_invariants(),
+                   Err(SubtreeSize {
+                       position: 0,
+                       recorded: 1,
+                       actual: 2,
+                   }));
+        map.root.as_mut().unwrap().size = 2;
+        assert_eq!(map.check_invariants(), Err(Length { recorded: 1, actual: 2 }));
+        map.length = 2;
+        assert_eq!(map.check_invariants(), Ok(()));
+        map.root.as_mut().unwrap().right.as_mut().unwrap().right = Some(Box::new(TreeNode::new(5, ())));
+        map.length = 3;
+        assert_eq!(map.check_invariants(), Err(DoubleHorizontal { position: 2 }));
+    }
+
     #[test]
+    fn test_stats() {
+        let empty: TreeMap<i32, ()> = TreeMap::new();
+        let stats = empty.stats();
+        assert_eq!((stats.height, stats.nodes_per_level, stats.average_search_depth),
+                   (0, vec![], 0.0));
+
+        let map: TreeMap<i32, ()> = (0..7).map(|i| (i, ())).collect();
+        let stats = map.stats();
+        assert_eq!(stats.height, 3);
+        assert_eq!(stats.nodes_per_level, [4, 2, 1]);
+        assert_eq!(stats.average_search_depth, (1.0 + 2.0 * 2.0 + 4.0 * 3.0) / 7.0);
+
+        // A broken tree with a level 0 leaf.
+        let mut broken = map.clone();
+        node_at(&mut broken, 6).level = 0;
+        let stats = broken.stats();
+        assert_eq!(stats.nodes_per_level, [3, 2, 1]);
+        assert_eq!(stats.average_search_depth, (1.0 + 2.0 * 2.0 + 4.0 * 3.0) / 7.0);
+
+        let map: TreeMap<i32, ()> = (0..1000).map(|i| (i, ())).collect();
+        let stats = map.stats();
+        assert_eq!(stats.nodes_per_level.iter().sum::<usize>(), 1000);
+        assert!(stats.height <= 2 * stats.nodes_per_level.len());
+        assert!(stats.average_search_depth <= stats.height as f64);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // too slow
     fn test_rand_int() {
         let mut map: TreeMap<i32, i32> = TreeMap::new();
         let mut ctrl = vec![];
@@ -1746,11 +2976,11 @@ mod test_treemap {
         assert!(m_upper.iter().all(|(_, &x)| x == 0));
     }
 
-    fn to_vec<K, V: Clone>(range: Range<K, V>) -> Vec<V> {
+    fn to_vec<K, V: Clone, C>(range: Range<K, V, C>) -> Vec<V> {
         range.map(|o| o.1.clone()).collect::<Vec<V>>()
     }
 
-    fn skip<K, V>(mut range: Range<K, V>, mut f: u32, mut b: u32) -> Range<K, V> {
+    fn skip<K, V, C>(mut range: Range<K, V, C>, mut f: u32, mut b: u32) -> Range<K, V, C> {
         while f > 0 {
             range.next();
             f -= 1;
@@ -1808,11 +3038,164 @@ mod test_treemap {
                    vec![]);
     }
 
-    fn to_vec_mut<K, V: Clone>(range: RangeMut<K, V>) -> Vec<V> {
+    #[test]
+    fn test_keys_values_range() {
+        let mut m: TreeMap<i32, i32> = (0..10).map(|i| (i * 10, 100 * i)).collect();
+
+        let keys: Vec<_> = m.keys_range(Bound::Included(&20), Bound::Excluded(&50)).cloned().collect();
+        assert_eq!(keys, [20, 30, 40]);
+        let keys: Vec<_> = m.rev_keys_range(Bound::Excluded(&60), Bound::Unbounded).cloned().collect();
+        assert_eq!(keys, [90, 80, 70]);
+        let values: Vec<_> = m.values_range(Bound::Unbounded, Bound::Included(&20)).cloned().collect();
+        assert_eq!(values, [0, 100, 200]);
+        let values: Vec<_> = m.rev_values_range(Bound::Included(&15), Bound::Included(&35))
+                              .cloned()
+                              .collect();
+        assert_eq!(values, [300, 200]);
+        assert_eq!(m.values_range(Bound::Included(&60), Bound::Excluded(&50)).next(), None);
+
+        for v in m.values_range_mut(Bound::Included(&70), Bound::Unbounded) {
+            *v += 1;
+        }
+        for (i, v) in m.rev_values_range_mut(Bound::Unbounded, Bound::Excluded(&30)).enumerate() {
+            *v = i as i32;
+        }
+        assert_eq!(m.values().cloned().collect::<Vec<_>>(),
+                   [2, 1, 0, 300, 400, 500, 600, 701, 801, 901]);
+
+        // Both ends meet in the middle.
+        let mut values = m.values_range_mut(Bound::Included(&30), Bound::Included(&50));
+        *values.next().unwrap() = 3;
+        *values.next_back().unwrap() = 5;
+        *values.next().unwrap() = 4;
+        assert_eq!(values.next(), None);
+        assert_eq!(values.next_back(), None);
+        let mut keys = m.keys_range(Bound::Included(&30), Bound::Included(&50));
+        assert_eq!(keys.len(), 3);
+        assert_eq!((keys.next(), keys.next_back(), keys.next_back()), (Some(&30), Some(&50), Some(&40)));
+        assert_eq!(keys.next(), None);
+        assert_eq!(m[&40], 4);
+    }
+
+    #[test]
+    fn test_seek() {
+        use std::collections::VecDeque;
+        use compare::{Compare, natural};
+
+        let seed: &[_] = &[7];
+        let mut rng: rand::IsaacRng = rand::SeedableRng::from_seed(seed);
+
+        for size in 0..40 {
+            let mut m: TreeMap<i32, i32> = (0..size).map(|i| (i * 2, i)).collect();
+            for _ in 0..20 {
+                let lo = rng.gen_range(-2, size * 2 + 2);
+                let hi = rng.gen_range(-2, size * 2 + 2);
+                let mut ctrl: VecDeque<i32> = (0..size).map(|i| i * 2).filter(|&k| lo <= k && k <= hi).collect();
+                let mut range = m.range_mut(Bound::Included(&lo), Bound::Included(&hi));
+                loop {
+                    assert_eq!(range.peek_key(), ctrl.front());
+                    match rng.gen_range(0, 4) {
+                        0 => assert_eq!(range.next().map(|(k, _)| *k), ctrl.pop_front()),
+                        1 => assert_eq!(range.next_back().map(|(k, _)| *k), ctrl.pop_back()),
+                        _ => {
+                            let target = rng.gen_range(-2, size * 2 + 2);
+                            range.seek(&target);
+                            while ctrl.front().is_some_and(|&k| k < target) {
+                                ctrl.pop_front();
+                            }
+                        }
+                    }
+                    assert_eq!(range.len(), ctrl.len());
+                    if ctrl.is_empty() && rng.gen_range(0, 4) == 0 {
+                        assert_eq!(range.peek_key(), None);
+                        assert_eq!(range.next(), None);
+                        assert_eq!(range.next_back(), None);
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Seeking with the map's comparator.
+        let m: TreeMap<i32, ()> = (0..10).map(|i| (i, ())).collect();
+        let mut range = m.range(Bound::Unbounded, Bound::Unbounded);
+        range.seek_with(|k| 6.cmp(k));
+        assert_eq!(range.next(), Some((&6, &())));
+        range.seek(&3);
+        assert_eq!(range.next(), Some((&7, &())));
+
+        let mut iter = m.iter();
+        assert_eq!(iter.peek_key(), Some(&0));
+        assert_eq!(iter.len(), 10);
+        iter.seek(&6);
+        assert_eq!(iter.len(), 4);
+        assert_eq!(iter.next(), Some((&6, &())));
+        iter.seek(&2);
+        assert_eq!(iter.len(), 3);
+        iter.seek(&20);
+        assert_eq!((iter.len(), iter.next()), (0, None));
+
+        // Seeking with a reversed comparator moves towards smaller keys.
+        let mut m = TreeMap::with_comparator(natural().rev());
+        m.extend((0..10).map(|i| (i, i)));
+        let mut iter = m.iter();
+        iter.seek(&6);
+        assert_eq!(iter.len(), 7);
+        assert_eq!(iter.next(), Some((&6, &6)));
+
+        let mut range = m.range(Bound::Included(&8), Bound::Included(&2));
+        range.seek(&5);
+        assert_eq!(range.len(), 4);
+        assert_eq!(range.next(), Some((&5, &5)));
+        assert_eq!(range.next_back(), Some((&2, &2)));
+
+        let mut range = m.range_mut(Bound::Unbounded, Bound::Excluded(&3));
+        range.seek(&4);
+        assert_eq!(range.len(), 1);
+        assert_eq!(range.next().map(|(k, _)| *k), Some(4));
+        assert_eq!(range.next(), None);
+    }
+
+    #[test]
+    fn test_range_by() {
+        use std::ops;
+        use compare::{Compare, natural};
+
+        let mut m: TreeMap<i32, i32> = (0..10).map(|i| (i * 10, 100 * i)).collect();
+
+        assert_eq!(to_vec(m.range_by(20..50)), vec![200, 300, 400]);
+        assert_eq!(to_vec(m.range_by(20..=50)), vec![200, 300, 400, 500]);
+        assert_eq!(to_vec(m.range_by(75..)), vec![800, 900]);
+        assert_eq!(to_vec(m.range_by(..10)), vec![0]);
+        assert_eq!(to_vec(m.range_by(..=10)), vec![0, 100]);
+        assert_eq!(m.range_by::<i32, _>(..).count(), 10);
+        let (lo, hi) = (60, 50);
+        assert_eq!(to_vec(m.range_by(lo..hi)), vec![]);
+        assert_eq!(to_vec(m.range_by((ops::Bound::Excluded(70), ops::Bound::Unbounded))),
+                   vec![800, 900]);
+
+        for (_, v) in m.range_mut_by(..30) {
+            *v += 1;
+        }
+        assert_eq!(to_vec(m.range_by(..40)), vec![1, 101, 201, 300]);
+
+        // Borrowed keys.
+        let mut m = TreeMap::with_comparator(natural::<str>().borrowing());
+        m.insert("a".to_string(), 1);
+        m.insert("b".to_string(), 2);
+        assert_eq!(m.range_by::<str, _>((ops::Bound::Excluded("a"), ops::Bound::Unbounded)).next(),
+                   Some((&"b".to_string(), &2)));
+
+        assert_eq!(ops::Bound::from(Bound::Included(1)), ops::Bound::Included(1));
+        assert_eq!(Bound::from(ops::Bound::Excluded(1)), Bound::Excluded(1));
+        assert_eq!(Bound::from(ops::Bound::Unbounded::<i32>), Bound::Unbounded);
+    }
+
+    fn to_vec_mut<K, V: Clone, C>(range: RangeMut<K, V, C>) -> Vec<V> {
         range.map(|o| o.1.clone()).collect::<Vec<V>>()
     }
 
-    fn skip_mut<K, V>(mut range: RangeMut<K, V>, mut f: u32, mut b: u32) -> RangeMut<K, V> {
+    fn skip_mut<K, V, C>(mut range: RangeMut<K, V, C>, mut f: u32, mut b: u32) -> RangeMut<K, V, C> {
         while f > 0 {
             range.next();
             f -= 1;
@@ -1875,6 +3258,195 @@ mod test_treemap {
                    vec![]);
     }
 
+    // Checks that `iter` reports a size hint that holds at every step.
+    fn check_size_hints<I: Iterator>(mut iter: I) {
+        let mut hints = vec![iter.size_hint()];
+        let mut count = 0;
+        while iter.next().is_some() {
+            hints.push(iter.size_hint());
+            count += 1;
+        }
+        for (i, &hint) in hints.iter().enumerate() {
+            let left = count - i;
+            assert_eq!(hint, (left, Some(left)), "{} for {}", i, left);
+        }
+        assert_eq!(iter.size_hint(), (0, Some(0)));
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_size_hints() {
+        use super::super::Bound::*;
+
+        let mut m: TreeMap<i32, i32> = (0..50).map(|i| (i * 2, i)).collect();
+        check_size_hints(m.iter());
+        check_size_hints(m.iter_mut());
+        check_size_hints(m.keys());
+        check_size_hints(m.values());
+        check_size_hints(m.values_mut());
+        check_size_hints(m.clone().into_iter());
+        assert_eq!(m.iter().len(), 50);
+
+        let bounds = [Unbounded, Included(&-1), Included(&10), Excluded(&10), Included(&99)];
+        for &min in bounds.iter() {
+            for &max in bounds.iter() {
+                check_size_hints(m.range(min, max));
+                check_size_hints(m.range(min, max).rev());
+                check_size_hints(m.range_mut(min, max));
+            }
+        }
+
+        let mut r = m.range(Included(&10), Included(&20));
+        assert_eq!(r.len(), 6);
+        r.next();
+        r.next_back();
+        assert_eq!(r.len(), 4);
+        assert_eq!(m.range(Excluded(&10), Excluded(&12)).len(), 0);
+        assert_eq!(m.range(Included(&20), Included(&10)).len(), 0);
+
+        let empty: TreeMap<i32, i32> = TreeMap::new();
+        check_size_hints(empty.iter());
+        assert_eq!(empty.range(Unbounded, Unbounded).size_hint(), (0, Some(0)));
+    }
+
+    // The following tests hold on to references handed out by an iterator
+    // while it keeps going, which is what an aliasing checker such as Miri
+    // (`cargo +nightly miri test`) needs to see to catch iterators that
+    // invalidate each other's pointers.
+
+    #[test]
+    fn test_iter_mut_retains_refs() {
+        let mut m: TreeMap<i32, i32> = (0..20).map(|i| (i, i)).collect();
+        let mut refs = vec![];
+        for (_, v) in m.iter_mut() {
+            refs.push(v);
+        }
+        for v in refs {
+            *v *= 2;
+        }
+        assert!(m.iter().all(|(&k, &v)| v == k * 2));
+    }
+
+    #[test]
+    fn test_range_mut_retains_refs() {
+        let mut m: TreeMap<i32, i32> = (0..20).map(|i| (i, 0)).collect();
+        {
+            let mut range = m.range_mut(Bound::Included(&3), Bound::Excluded(&17));
+            let mut refs = vec![];
+            while let Some((_, v)) = range.next() {
+                refs.push(v);
+                if let Some((_, v)) = range.next_back() {
+                    refs.push(v);
+                }
+                for v in refs.iter_mut() {
+                    **v += 1;
+                }
+            }
+            assert_eq!(refs.len(), 14);
+        }
+        assert!(m.iter().all(|(k, &v)| (v > 0) == (3..17).contains(k)));
+    }
+
+    #[test]
+    fn test_range_mut_crosses_split_node() {
+        // Drain the range from the front while the back end still sits below
+        // the node where the two ends diverged, then use the back end.
+        let mut m: TreeMap<i32, i32> = (0..31).map(|i| (i, i)).collect();
+        let mut range = m.range_mut(Bound::Unbounded, Bound::Unbounded);
+        let (_, last) = range.next_back().unwrap();
+        let mut front = vec![];
+        for _ in 0..20 {
+            front.push(range.next().unwrap().1);
+        }
+        let (_, back) = range.next_back().unwrap();
+        *back = -1;
+        *last = -1;
+        for v in front {
+            *v = -1;
+        }
+        assert_eq!(range.count(), 9);
+        assert_eq!(m.values().filter(|&&v| v == -1).count(), 22);
+    }
+
+    #[test]
+    fn test_shared_iterators_interleave() {
+        let m: TreeMap<i32, i32> = (0..20).map(|i| (i, i * 10)).collect();
+        let mut a = m.iter();
+        let mut b = m.range(Bound::Included(&5), Bound::Unbounded);
+        let mut c = m.range(Bound::Unbounded, Bound::Included(&14));
+        let mut seen = vec![];
+        for _ in 0..10 {
+            seen.push(a.next().unwrap());
+            seen.push(b.next().unwrap());
+            seen.push(c.next_back().unwrap());
+        }
+        assert_eq!(seen[27..], [(&9, &90), (&14, &140), (&5, &50)]);
+        assert_eq!(m.get(&7), Some(&70));
+        assert_eq!((a.count(), b.count(), c.count()), (10, 5, 5));
+    }
+
+    #[test]
+    fn test_into_iter_partial() {
+        let m: TreeMap<i32, String> = (0..20).map(|i| (i, i.to_string())).collect();
+        let mut it = m.into_iter();
+        assert_eq!(it.next(), Some((0, "0".to_string())));
+        assert_eq!(it.nth(9), Some((10, "10".to_string())));
+        // Dropping the rest must free the remaining nodes.
+    }
+
+    #[test]
+    fn test_iterators_send_sync() {
+        use std::cell::Cell;
+
+        use super::{Backward, Forward, IntoIter, Iter, IterMut, Keys, KeysRange, Values, ValuesMut,
+                    ValuesRange, ValuesRangeMut};
+
+        fn send_sync<T: Send + Sync>() {}
+        fn send<T: Send>() {}
+
+        send_sync::<Iter<String, Vec<u8>, Forward>>();
+        send_sync::<Iter<String, Vec<u8>, Backward>>();
+        send_sync::<IterMut<String, Vec<u8>, Forward>>();
+        send_sync::<Range<String, Vec<u8>>>();
+        send_sync::<RangeMut<String, Vec<u8>>>();
+        send_sync::<Keys<String, Vec<u8>>>();
+        send_sync::<Values<String, Vec<u8>>>();
+        send_sync::<ValuesMut<String, Vec<u8>>>();
+        send_sync::<KeysRange<String, Vec<u8>>>();
+        send_sync::<ValuesRange<String, Vec<u8>>>();
+        send_sync::<ValuesRangeMut<String, Vec<u8>>>();
+        send_sync::<IntoIter<String, Vec<u8>>>();
+
+        // A mutable borrow only needs `V: Send` to be sent, like `&mut V`.
+        send::<IterMut<i32, Cell<i32>, Forward>>();
+        send::<RangeMut<i32, Cell<i32>>>();
+        send::<ValuesMut<i32, Cell<i32>>>();
+        send::<IntoIter<i32, Cell<i32>>>();
+    }
+
+    #[test]
+    fn test_range_on_scoped_thread() {
+        let mut m: TreeMap<i32, i32> = (0..100).map(|i| (i, i)).collect();
+        let (low, high) = (m.range(Bound::Unbounded, Bound::Excluded(&50)),
+                           m.range(Bound::Included(&50), Bound::Unbounded));
+        let sums = ::std::thread::scope(|s| {
+            let low = s.spawn(move || low.map(|(_, &v)| v).sum::<i32>());
+            let high = s.spawn(move || high.map(|(_, &v)| v).sum::<i32>());
+            (low.join().unwrap(), high.join().unwrap())
+        });
+        assert_eq!(sums, (1225, 3725));
+
+        let values = m.values_mut();
+        ::std::thread::scope(|s| {
+            s.spawn(move || {
+                for v in values {
+                    *v *= 2;
+                }
+            });
+        });
+        assert_eq!(m.iter_mut().map(|(_, v)| *v).sum::<i32>(), 9900);
+    }
+
     #[test]
     fn test_keys() {
         let vec = vec![(1, 'a'), (2, 'b'), (3, 'c')];
@@ -1952,6 +3524,61 @@ mod test_treemap {
         assert!(a < b && a <= b);
     }
 
+    #[test]
+    fn test_comparator_eq_ord_hash() {
+        use std::cmp::Ordering;
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+        use compare::{Compare, natural};
+        use super::super::HashCompare;
+
+        #[derive(Clone, Copy, Default)]
+        struct CaseInsensitive;
+
+        impl Compare<&'static str> for CaseInsensitive {
+            fn compare(&self, l: &&str, r: &&str) -> Ordering {
+                l.to_lowercase().cmp(&r.to_lowercase())
+            }
+        }
+
+        impl HashCompare<&'static str> for CaseInsensitive {
+            fn hash_key<H: Hasher>(&self, key: &&str, state: &mut H) {
+                key.to_lowercase().hash(state);
+            }
+        }
+
+        fn hash<T: Hash>(t: &T) -> u64 {
+            let mut s = DefaultHasher::new();
+            t.hash(&mut s);
+            s.finish()
+        }
+
+        let mut a = TreeMap::with_comparator(CaseInsensitive);
+        let mut b = TreeMap::with_comparator(CaseInsensitive);
+        a.insert("Foo", 1);
+        a.insert("bar", 2);
+        b.insert("foo", 1);
+        b.insert("BAR", 2);
+        assert!(a == b);
+        assert_eq!(hash(&a), hash(&b));
+        b.insert("Bar", 3);
+        assert!(a != b && a < b);
+
+        // Ordering follows the comparator, not the keys' own `Ord`.
+        let mut a = TreeMap::with_comparator(natural().rev());
+        let mut b = TreeMap::with_comparator(natural().rev());
+        a.insert(2, ());
+        b.insert(1, ());
+        assert!(a < b);
+        b.insert(3, ());
+        assert!(a > b);
+        assert_eq!(hash(&a), hash(&{
+            let mut c = TreeMap::with_comparator(natural().rev());
+            c.insert(2, ());
+            c
+        }));
+    }
+
     #[test]
     fn test_debug() {
         let mut map = TreeMap::new();
@@ -2021,6 +3648,45 @@ mod test_treemap {
         }
     }
 
+    #[test]
+    fn test_split_off() {
+        for n in 0..40 {
+            for at in -1..n + 1 {
+                let mut a: TreeMap<i32, i32> = (0..n).map(|i| (i, -i)).collect();
+                let b = a.split_off(&at);
+                check_structure(&a);
+                check_structure(&b);
+                let lo = at.clamp(0, n);
+                assert_eq!(a.len(), lo as usize);
+                assert_eq!(b.len(), (n - lo) as usize);
+                assert!(a.iter().map(|(&k, &v)| (k, v)).eq((0..lo).map(|i| (i, -i))));
+                assert!(b.iter().map(|(&k, &v)| (k, v)).eq((lo..n).map(|i| (i, -i))));
+            }
+        }
+    }
+
+    #[test]
+    fn test_select_rank() {
+        let mut map: TreeMap<i32, i32> = (0..50).map(|i| (i * 2, i)).collect();
+        for i in (0..50).step_by(3) {
+            map.remove(&(i * 2));
+        }
+        for i in 0..20 {
+            map.insert(i * 10 + 1, -i);
+        }
+        check_structure(&map);
+
+        let keys: Vec<i32> = map.keys().cloned().collect();
+        for (index, &key) in keys.iter().enumerate() {
+            assert_eq!(map.select(index), Some((&key, &map[&key])));
+            assert_eq!(map.rank(&key), index);
+        }
+        assert_eq!(map.select(keys.len()), None);
+        for probe in -1..200 {
+            assert_eq!(map.rank(&probe), keys.iter().filter(|&&k| k < probe).count());
+        }
+    }
+
     #[test]
     fn test_index() {
         let mut map: TreeMap<i32, i32> = TreeMap::new();
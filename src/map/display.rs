@@ -0,0 +1,192 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Renderings of the shape of a `TreeMap`, for debugging its balancing.
+
+use alloc::vec::Vec;
+use core::fmt::{self, Debug, Write};
+
+use compare::Compare;
+
+use super::{TreeMap, TreeNode};
+
+impl<K, V, C> TreeMap<K, V, C>
+    where C: Compare<K>
+{
+    /// Writes the tree as a Graphviz digraph, with each node labeled with its
+    /// key and AA level. Horizontal links, from a node to a right child on the
+    /// same level, are drawn in red with both ends on the same rank.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::TreeMap;
+    ///
+    /// let map: TreeMap<u32, ()> = (0..3).map(|i| (i, ())).collect();
+    ///
+    /// let mut dot = String::new();
+    /// map.to_dot(&mut dot).unwrap();
+    /// assert!(dot.starts_with("digraph {"));
+    /// assert!(dot.contains("label=\"1\\nlevel 2\""));
+    /// ```
+    pub fn to_dot<W: Write>(&self, w: &mut W) -> fmt::Result
+        where K: Debug
+    {
+        fn write_node<K: Debug, V, W: Write>(w: &mut W, node: &TreeNode<K, V>, id: &mut usize) -> fmt::Result {
+            let this = *id;
+            *id += 1;
+            write!(w, "    n{} [label=\"", this)?;
+            write!(Escape(w), "{:?}", node.key)?;
+            writeln!(w, "\\nlevel {}\"];", node.level)?;
+            if let Some(ref left) = node.left {
+                writeln!(w, "    n{} -> n{} [label=\"L\"];", this, *id)?;
+                write_node(w, left, id)?;
+            }
+            if let Some(ref right) = node.right {
+                if right.level == node.level {
+                    writeln!(w, "    n{} -> n{} [label=\"R\", color=red, penwidth=2];", this, *id)?;
+                    writeln!(w, "    {{ rank=same; n{}; n{}; }}", this, *id)?;
+                } else {
+                    writeln!(w, "    n{} -> n{} [label=\"R\"];", this, *id)?;
+                }
+                write_node(w, right, id)?;
+            }
+            Ok(())
+        }
+
+        writeln!(w, "digraph {{")?;
+        writeln!(w, "    node [shape=box];")?;
+        if let Some(ref root) = self.root {
+            write_node(w, root, &mut 0)?;
+        }
+        writeln!(w, "}}")
+    }
+
+    /// Returns a value that displays the tree as indented ASCII art, one node
+    /// per line with its key and AA level. Each child is marked `L` or `R`,
+    /// and `R=` marks a horizontal link to a right child on the same level.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::TreeMap;
+    ///
+    /// let map: TreeMap<u32, ()> = (0..4).map(|i| (i, ())).collect();
+    /// assert_eq!(map.display_tree().to_string(),
+    ///            "1 [2]\n\
+    ///             +-- L 0 [1]\n\
+    ///             `-- R 2 [1]\n    \
+    ///                 `-- R= 3 [1]\n");
+    /// ```
+    pub fn display_tree(&self) -> DisplayTree<'_, K, V> {
+        DisplayTree { root: self.root.as_deref() }
+    }
+}
+
+/// Displays the shape of a `TreeMap`. See `TreeMap::display_tree`.
+pub struct DisplayTree<'a, K: 'a, V: 'a> {
+    root: Option<&'a TreeNode<K, V>>,
+}
+
+impl<'a, K: Debug, V> fmt::Display for DisplayTree<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // `prefix` holds one column per ancestor below the root, saying
+        // whether lines for later siblings still pass through it.
+        fn children<K: Debug, V>(f: &mut fmt::Formatter,
+                                 node: &TreeNode<K, V>,
+                                 prefix: &mut Vec<bool>)
+                                 -> fmt::Result {
+            let left = node.left.as_deref().map(|left| ("L", left));
+            let right = node.right.as_deref().map(|right| {
+                (if right.level == node.level { "R=" } else { "R" }, right)
+            });
+            for (i, (side, child)) in left.into_iter().chain(right).enumerate() {
+                for &open in prefix.iter() {
+                    f.write_str(if open { "|   " } else { "    " })?;
+                }
+                let more = i == 0 && left.is_some() && right.is_some();
+                f.write_str(if more { "+-- " } else { "`-- " })?;
+                writeln!(f, "{} {:?} [{}]", side, child.key, child.level)?;
+                prefix.push(more);
+                children(f, child, prefix)?;
+                prefix.pop();
+            }
+            Ok(())
+        }
+
+        match self.root {
+            None => Ok(()),
+            Some(root) => {
+                writeln!(f, "{:?} [{}]", root.key, root.level)?;
+                children(f, root, &mut Vec::new())
+            }
+        }
+    }
+}
+
+// Escapes what it writes for use in a double-quoted Graphviz string.
+struct Escape<'a, W: 'a>(&'a mut W);
+
+impl<'a, W: Write> Write for Escape<'a, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                '"' => self.0.write_str("\\\"")?,
+                '\\' => self.0.write_str("\\\\")?,
+                '\n' => self.0.write_str("\\n")?,
+                c => self.0.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::TreeMap;
+
+    #[test]
+    fn test_to_dot() {
+        let mut map = TreeMap::new();
+        map.insert("a\"b", 1);
+        map.insert("c", 2);
+        let mut dot = String::new();
+        map.to_dot(&mut dot).unwrap();
+        assert_eq!(dot,
+                   "digraph {\n    \
+                        node [shape=box];\n    \
+                        n0 [label=\"\\\"a\\\\\\\"b\\\"\\nlevel 1\"];\n    \
+                        n0 -> n1 [label=\"R\", color=red, penwidth=2];\n    \
+                        { rank=same; n0; n1; }\n    \
+                        n1 [label=\"\\\"c\\\"\\nlevel 1\"];\n\
+                    }\n");
+
+        let empty: TreeMap<i32, i32> = TreeMap::new();
+        let mut dot = String::new();
+        empty.to_dot(&mut dot).unwrap();
+        assert_eq!(dot, "digraph {\n    node [shape=box];\n}\n");
+    }
+
+    #[test]
+    fn test_display_tree() {
+        let map: TreeMap<i32, ()> = (0..7).map(|i| (i, ())).collect();
+        assert_eq!(map.display_tree().to_string(),
+                   "3 [3]\n\
+                    +-- L 1 [2]\n\
+                    |   +-- L 0 [1]\n\
+                    |   `-- R 2 [1]\n\
+                    `-- R 5 [2]\n    \
+                        +-- L 4 [1]\n    \
+                        `-- R 6 [1]\n");
+
+        let empty: TreeMap<i32, ()> = TreeMap::new();
+        assert_eq!(empty.display_tree().to_string(), "");
+    }
+}
@@ -0,0 +1,546 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Parallel iteration over a `TreeMap` with rayon.
+//
+// A parallel iterator describes its part of the map as an in-order list of
+// pieces, each either a whole subtree or a single node. Splitting hands half
+// of the pieces to the other side; once a single subtree is left, it is broken
+// up into its left subtree, its root and its right subtree. A whole map starts
+// out as one piece and a range as the O(log n) pieces that make it up.
+
+use std::cmp::Ordering::{Greater, Less};
+use std::marker::PhantomData;
+use std::mem::replace;
+use std::ptr;
+
+use compare::Compare;
+use rayon::iter::plumbing::{bridge_unindexed, Folder, UnindexedConsumer, UnindexedProducer};
+use rayon::prelude::*;
+
+use super::{Access, Backward, Exclusive, Forward, IntoIter, RawIter, Shared, TreeMap, TreeNode};
+use super::{build_sorted, node_mut, node_ref};
+use super::super::Bound;
+
+enum Piece<K, V> {
+    // A node without its subtrees.
+    Node(*mut TreeNode<K, V>),
+    // A node together with both of its subtrees.
+    Tree(*mut TreeNode<K, V>),
+}
+
+// A node handed out by a `RawParIter`, to be turned into references by one of
+// the public iterators.
+struct NodePtr<K, V>(*mut TreeNode<K, V>);
+
+// The parallel counterpart of `RawIter`.
+struct RawParIter<K, V, A> {
+    pieces: Vec<Piece<K, V>>,
+    marker: PhantomData<A>,
+}
+
+// The public wrappers below carry markers for the borrow of the map, which
+// make them `Send` and `Sync` exactly when that borrow is.
+unsafe impl<K, V> Send for NodePtr<K, V> {}
+unsafe impl<K, V, A> Send for RawParIter<K, V, A> {}
+
+impl<K, V, A: Access> RawParIter<K, V, A> {
+    #[allow(clippy::redundant_field_names)]
+    fn new(pieces: Vec<Piece<K, V>>) -> RawParIter<K, V, A> {
+        RawParIter {
+            pieces: pieces,
+            marker: PhantomData,
+        }
+    }
+
+    fn whole(root: *mut TreeNode<K, V>) -> RawParIter<K, V, A> {
+        if root.is_null() {
+            RawParIter::new(vec![])
+        } else {
+            RawParIter::new(vec![Piece::Tree(root)])
+        }
+    }
+}
+
+impl<K, V, A: Access> UnindexedProducer for RawParIter<K, V, A> {
+    type Item = NodePtr<K, V>;
+
+    fn split(mut self) -> (Self, Option<Self>) {
+        if self.pieces.len() == 1 {
+            if let Piece::Tree(node) = self.pieces[0] {
+                let (left, right) = unsafe {
+                    (A::child(ptr::addr_of_mut!((*node).left)),
+                     A::child(ptr::addr_of_mut!((*node).right)))
+                };
+                self.pieces.clear();
+                if !left.is_null() {
+                    self.pieces.push(Piece::Tree(left));
+                }
+                self.pieces.push(Piece::Node(node));
+                if !right.is_null() {
+                    self.pieces.push(Piece::Tree(right));
+                }
+            }
+        }
+        if self.pieces.len() < 2 {
+            return (self, None);
+        }
+        let mid = self.pieces.len() / 2;
+        let rest = self.pieces.split_off(mid);
+        (self, Some(RawParIter::new(rest)))
+    }
+
+    fn fold_with<F>(self, mut folder: F) -> F
+        where F: Folder<Self::Item>
+    {
+        for piece in self.pieces {
+            match piece {
+                Piece::Node(node) => folder = folder.consume(NodePtr(node)),
+                Piece::Tree(node) => {
+                    let mut iter = RawIter::<K, V, Forward, A>::new(node);
+                    while let Some(node) = iter.next_() {
+                        folder = folder.consume(NodePtr(node));
+                        if folder.full() {
+                            break;
+                        }
+                    }
+                }
+            }
+            if folder.full() {
+                break;
+            }
+        }
+        folder
+    }
+}
+
+impl<K, V, A: Access> ParallelIterator for RawParIter<K, V, A> {
+    type Item = NodePtr<K, V>;
+
+    fn drive_unindexed<Co>(self, consumer: Co) -> Co::Result
+        where Co: UnindexedConsumer<Self::Item>
+    {
+        bridge_unindexed(self, consumer)
+    }
+}
+
+impl<K, V, C> TreeMap<K, V, C>
+    where C: Compare<K>
+{
+    // Appends the parts of the subtree at `node` that lie between `min` and
+    // `max` to `pieces`, in order. `lo_free` and `hi_free` record that every
+    // key in the subtree is already known to be above `min` or below `max`.
+    fn range_pieces<A, Min: ?Sized, Max: ?Sized>(&self,
+                                                 node: *mut TreeNode<K, V>,
+                                                 min: Bound<&Min>,
+                                                 max: Bound<&Max>,
+                                                 lo_free: bool,
+                                                 hi_free: bool,
+                                                 pieces: &mut Vec<Piece<K, V>>)
+        where C: Compare<Min, K> + Compare<Max, K>,
+              A: Access
+    {
+        if node.is_null() {
+            return;
+        }
+        if lo_free && hi_free {
+            pieces.push(Piece::Tree(node));
+            return;
+        }
+        let key = unsafe { &(*node).key };
+        let above_min = lo_free || self.compare_bound::<Forward, Min>(min, key) != Greater;
+        let below_max = hi_free || self.compare_bound::<Backward, Max>(max, key) != Less;
+        if above_min {
+            let left = unsafe { A::child(ptr::addr_of_mut!((*node).left)) };
+            self.range_pieces::<A, Min, Max>(left, min, max, lo_free, below_max, pieces);
+        }
+        if above_min && below_max {
+            pieces.push(Piece::Node(node));
+        }
+        if below_max {
+            let right = unsafe { A::child(ptr::addr_of_mut!((*node).right)) };
+            self.range_pieces::<A, Min, Max>(right, min, max, above_min, hi_free, pieces);
+        }
+    }
+
+    /// Constructs a parallel iterator over a sub-range of elements in the map,
+    /// with the same meaning of `min` and `max` as `range`. The work is split
+    /// along the subtrees that make up the range.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// extern crate rayon;
+    /// extern crate stable_bst;
+    ///
+    /// use rayon::prelude::*;
+    /// use stable_bst::TreeMap;
+    /// use stable_bst::Bound::{Included, Excluded};
+    ///
+    /// # fn main() {
+    /// let map: TreeMap<u64, u64> = (0..1000).map(|i| (i, i * i)).collect();
+    /// let sum: u64 = map.par_range(Included(&10), Excluded(&20)).map(|(_, &v)| v).sum();
+    /// assert_eq!(sum, (10..20).map(|i| i * i).sum());
+    /// # }
+    /// ```
+    pub fn par_range<'a, Min: ?Sized, Max: ?Sized>(&'a self,
+                                                   min: Bound<&Min>,
+                                                   max: Bound<&Max>)
+                                                   -> ParRange<'a, K, V>
+        where C: Compare<Min, K> + Compare<Max, K>
+    {
+        let root = unsafe { Shared::child(&self.root as *const _ as *mut _) };
+        let mut pieces = vec![];
+        self.range_pieces::<Shared, Min, Max>(root, min, max, false, false, &mut pieces);
+        ParRange {
+            inner: RawParIter::new(pieces),
+            marker: PhantomData,
+        }
+    }
+}
+
+/// A parallel iterator over the entries of a `TreeMap`.
+pub struct ParIter<'a, K: 'a, V: 'a> {
+    inner: RawParIter<K, V, Shared>,
+    marker: PhantomData<(&'a K, &'a V)>,
+}
+
+/// A parallel iterator over the entries of a `TreeMap`, with the values being
+/// mutable.
+pub struct ParIterMut<'a, K: 'a, V: 'a> {
+    inner: RawParIter<K, V, Exclusive>,
+    marker: PhantomData<(&'a K, &'a mut V)>,
+}
+
+/// A parallel iterator over a sub-range of a `TreeMap`.
+pub struct ParRange<'a, K: 'a, V: 'a> {
+    inner: RawParIter<K, V, Shared>,
+    marker: PhantomData<(&'a K, &'a V)>,
+}
+
+/// A parallel iterator that consumes a `TreeMap`.
+pub struct IntoParIter<K, V> {
+    pieces: Vec<OwnedPiece<K, V>>,
+}
+
+enum OwnedPiece<K, V> {
+    Node(K, V),
+    Tree(Box<TreeNode<K, V>>),
+}
+
+impl<'a, K: Sync, V: Sync> ParallelIterator for ParIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn drive_unindexed<Co>(self, consumer: Co) -> Co::Result
+        where Co: UnindexedConsumer<Self::Item>
+    {
+        self.inner.map(|node| unsafe { node_ref(node.0) }).drive_unindexed(consumer)
+    }
+}
+
+impl<'a, K: Sync, V: Send> ParallelIterator for ParIterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn drive_unindexed<Co>(self, consumer: Co) -> Co::Result
+        where Co: UnindexedConsumer<Self::Item>
+    {
+        self.inner.map(|node| unsafe { node_mut(node.0) }).drive_unindexed(consumer)
+    }
+}
+
+impl<'a, K: Sync, V: Sync> ParallelIterator for ParRange<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn drive_unindexed<Co>(self, consumer: Co) -> Co::Result
+        where Co: UnindexedConsumer<Self::Item>
+    {
+        self.inner.map(|node| unsafe { node_ref(node.0) }).drive_unindexed(consumer)
+    }
+}
+
+impl<K: Send, V: Send> ParallelIterator for IntoParIter<K, V> {
+    type Item = (K, V);
+
+    fn drive_unindexed<Co>(self, consumer: Co) -> Co::Result
+        where Co: UnindexedConsumer<Self::Item>
+    {
+        bridge_unindexed(self, consumer)
+    }
+}
+
+impl<K: Send, V: Send> UnindexedProducer for IntoParIter<K, V> {
+    type Item = (K, V);
+
+    fn split(mut self) -> (Self, Option<Self>) {
+        if let [OwnedPiece::Tree(_)] = self.pieces[..] {
+            if let Some(OwnedPiece::Tree(node)) = self.pieces.pop() {
+                let TreeNode { key, value, left, right, .. } = *node;
+                if let Some(left) = left {
+                    self.pieces.push(OwnedPiece::Tree(left));
+                }
+                self.pieces.push(OwnedPiece::Node(key, value));
+                if let Some(right) = right {
+                    self.pieces.push(OwnedPiece::Tree(right));
+                }
+            }
+        }
+        if self.pieces.len() < 2 {
+            return (self, None);
+        }
+        let mid = self.pieces.len() / 2;
+        let rest = self.pieces.split_off(mid);
+        (self, Some(IntoParIter { pieces: rest }))
+    }
+
+    fn fold_with<F>(self, mut folder: F) -> F
+        where F: Folder<Self::Item>
+    {
+        for piece in self.pieces {
+            folder = match piece {
+                OwnedPiece::Node(key, value) => folder.consume((key, value)),
+                OwnedPiece::Tree(node) => fold_tree(*node, folder),
+            };
+            if folder.full() {
+                break;
+            }
+        }
+        folder
+    }
+}
+
+// Feeds the entries of the subtree at `node` to `folder` in order, dropping
+// whatever is left once it is full.
+fn fold_tree<K, V, F>(node: TreeNode<K, V>, mut folder: F) -> F
+    where F: Folder<(K, V)>
+{
+    let TreeNode { key, value, left, right, .. } = node;
+    if let Some(left) = left {
+        folder = fold_tree(*left, folder);
+        if folder.full() {
+            return folder;
+        }
+    }
+    folder = folder.consume((key, value));
+    match right {
+        Some(right) if !folder.full() => fold_tree(*right, folder),
+        _ => folder,
+    }
+}
+
+impl<'a, K: Sync, V: Sync, C> IntoParallelIterator for &'a TreeMap<K, V, C>
+    where C: Compare<K>
+{
+    type Item = (&'a K, &'a V);
+    type Iter = ParIter<'a, K, V>;
+
+    fn into_par_iter(self) -> ParIter<'a, K, V> {
+        ParIter {
+            inner: RawParIter::whole(unsafe { Shared::child(&self.root as *const _ as *mut _) }),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, K: Sync, V: Send, C> IntoParallelIterator for &'a mut TreeMap<K, V, C>
+    where C: Compare<K>
+{
+    type Item = (&'a K, &'a mut V);
+    type Iter = ParIterMut<'a, K, V>;
+
+    fn into_par_iter(self) -> ParIterMut<'a, K, V> {
+        ParIterMut {
+            inner: RawParIter::whole(unsafe { Exclusive::child(&mut self.root) }),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<K: Send, V: Send, C> IntoParallelIterator for TreeMap<K, V, C>
+    where C: Compare<K>
+{
+    type Item = (K, V);
+    type Iter = IntoParIter<K, V>;
+
+    fn into_par_iter(self) -> IntoParIter<K, V> {
+        IntoParIter { pieces: self.root.into_iter().map(OwnedPiece::Tree).collect() }
+    }
+}
+
+/// Collects the entries into a vector, sorts it in parallel and builds the
+/// tree from the sorted entries in one pass, rather than inserting them one at
+/// a time. As with `insert`, a later entry replaces the value of an earlier
+/// one with an equal key, but the earlier key is kept.
+impl<K: Send, V: Send, C> ParallelExtend<(K, V)> for TreeMap<K, V, C>
+    where C: Compare<K> + Sync
+{
+    fn par_extend<I>(&mut self, par_iter: I)
+        where I: IntoParallelIterator<Item = (K, V)>
+    {
+        let mut entries: Vec<(K, V)> = par_iter.into_par_iter().collect();
+        if entries.is_empty() {
+            return;
+        }
+        {
+            // The sort is stable, so entries with equal keys stay in the
+            // order in which they were produced.
+            let cmp = &self.cmp;
+            entries.par_sort_by(|a, b| cmp.compare(&a.0, &b.0));
+        }
+
+        let old = IntoIter {
+            stack: self.root.take().into_iter().map(|node| *node).collect(),
+            remaining: replace(&mut self.length, 0),
+        };
+        let mut merged: Vec<(K, V)> = Vec::with_capacity(old.remaining + entries.len());
+        let mut old = old.peekable();
+        let mut new = entries.into_iter().peekable();
+        loop {
+            let take_old = match (old.peek(), new.peek()) {
+                (Some(a), Some(b)) => self.cmp.compares_le(&a.0, &b.0),
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+            let entry = if take_old { old.next() } else { new.next() }.unwrap();
+            match merged.last_mut() {
+                Some(last) if self.cmp.compares_eq(&last.0, &entry.0) => last.1 = entry.1,
+                _ => merged.push(entry),
+            }
+        }
+
+        self.length = merged.len();
+        self.root = build_sorted(self.length, &mut merged.into_iter());
+    }
+}
+
+impl<K: Send, V: Send, C> FromParallelIterator<(K, V)> for TreeMap<K, V, C>
+    where C: Compare<K> + Default + Sync
+{
+    fn from_par_iter<I>(par_iter: I) -> TreeMap<K, V, C>
+        where I: IntoParallelIterator<Item = (K, V)>
+    {
+        let mut map: TreeMap<K, V, C> = Default::default();
+        map.par_extend(par_iter);
+        map
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rayon::prelude::*;
+
+    use super::super::TreeMap;
+    use super::super::test_treemap::check_structure;
+    use super::super::super::Bound;
+
+    #[test]
+    fn test_par_iter() {
+        let m: TreeMap<i32, i32> = (0..10_000).map(|i| (i, i * 2)).collect();
+        let par: Vec<(&i32, &i32)> = m.par_iter().collect();
+        let seq: Vec<(&i32, &i32)> = m.iter().collect();
+        assert_eq!(par, seq);
+        assert_eq!(m.par_iter().map(|(_, &v)| v as i64).sum::<i64>(), 99_990_000);
+
+        let empty: TreeMap<i32, i32> = TreeMap::new();
+        assert_eq!(empty.par_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_par_iter_mut() {
+        let mut m: TreeMap<i32, i32> = (0..10_000).map(|i| (i, i)).collect();
+        m.par_iter_mut().for_each(|(&k, v)| *v += k);
+        assert!(m.iter().all(|(&k, &v)| v == k * 2));
+    }
+
+    #[test]
+    fn test_par_range() {
+        let m: TreeMap<i32, i32> = (0..1000).map(|i| (i * 2, i)).collect();
+        let bounds = [Bound::Unbounded,
+                      Bound::Included(&-1),
+                      Bound::Included(&0),
+                      Bound::Excluded(&0),
+                      Bound::Included(&501),
+                      Bound::Excluded(&502),
+                      Bound::Included(&1998),
+                      Bound::Excluded(&1998),
+                      Bound::Included(&2000)];
+        for &min in bounds.iter() {
+            for &max in bounds.iter() {
+                let par: Vec<_> = m.par_range(min, max).collect();
+                let seq: Vec<_> = m.range(min, max).collect();
+                assert_eq!(par, seq);
+            }
+        }
+    }
+
+    #[test]
+    fn test_into_par_iter() {
+        let m: TreeMap<i32, String> = (0..5000).map(|i| (i, i.to_string())).collect();
+        let v: Vec<(i32, String)> = m.into_par_iter().collect();
+        assert_eq!(v, (0..5000).map(|i| (i, i.to_string())).collect::<Vec<_>>());
+
+        // Stopping early has to drop what's left.
+        let m: TreeMap<i32, String> = (0..5000).map(|i| (i, i.to_string())).collect();
+        assert!(m.into_par_iter().any(|(k, _)| k == 2500));
+    }
+
+    #[test]
+    fn test_from_par_iter() {
+        for n in 0..300 {
+            let m: TreeMap<i32, i32> = (0..n).into_par_iter().rev().map(|i| (i, -i)).collect();
+            check_structure(&m);
+            assert_eq!(m.len(), n as usize);
+            assert!(m.iter().map(|(&k, &v)| (k, v)).eq((0..n).map(|i| (i, -i))));
+        }
+
+        // Later entries win, as with `insert`.
+        let m: TreeMap<i32, i32> = (0..1000).into_par_iter().map(|i| (i % 100, i)).collect();
+        assert_eq!(m.len(), 100);
+        assert!(m.iter().all(|(&k, &v)| v == k + 900));
+    }
+
+    #[test]
+    fn test_par_extend() {
+        let mut m: TreeMap<i32, &str> = (0..100).map(|i| (i * 3, "old")).collect();
+        m.par_extend((0..150).into_par_iter().map(|i| (i * 2, "new")));
+        check_structure(&m);
+
+        let mut expected: TreeMap<i32, &str> = (0..100).map(|i| (i * 3, "old")).collect();
+        expected.extend((0..150).map(|i| (i * 2, "new")));
+        assert_eq!(m, expected);
+
+        m.par_extend(vec![]);
+        assert_eq!(m, expected);
+        m.insert(-1, "after");
+        m.remove(&0);
+        check_structure(&m);
+    }
+
+    #[test]
+    fn test_par_extend_keeps_keys() {
+        use by_key::by_key;
+
+        // Keys that compare equal but are told apart by their second field.
+        let cmp = by_key(|k: &(i32, &'static str)| &k.0);
+        let old: Vec<_> = (0..50).map(|i| ((i * 2, "old"), i)).collect();
+        let new: Vec<_> = (0..50).map(|i| ((i * 3, "new"), -i)).collect();
+
+        let mut par = TreeMap::with_comparator(cmp);
+        par.extend(old.clone());
+        par.par_extend(new.clone().into_par_iter());
+        assert_eq!(par.check_invariants(), Ok(()));
+
+        let mut seq = TreeMap::with_comparator(cmp);
+        seq.extend(old);
+        seq.extend(new);
+        assert!(par.iter().eq(seq.iter()));
+        assert_eq!(par.iter().filter(|&(k, _)| k.1 == "old").count(), 50);
+    }
+}
@@ -0,0 +1,473 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! An ordered map that allows duplicate keys.
+
+use alloc::collections::vec_deque::{self, VecDeque};
+use alloc::vec::Vec;
+use core::default::Default;
+use core::fmt::{self, Debug};
+use core::iter::{self, FusedIterator};
+
+use compare::{Compare, Natural, natural};
+
+use super::Bound;
+use super::map::{self, Forward, TreeMap};
+
+/// An ordered map that can hold any number of values for each key.
+///
+/// Keys are ordered by the comparator, as in a `TreeMap`, and the values for
+/// a key are kept in the order in which they were inserted.
+///
+/// # Examples
+///
+/// ```rust
+/// use stable_bst::TreeMultiMap;
+///
+/// let mut events = TreeMultiMap::new();
+/// events.insert(10, "start");
+/// events.insert(12, "tick");
+/// events.insert(10, "load");
+///
+/// assert_eq!(events.len(), 3);
+/// assert!(events.get_all(&10).eq(&["start", "load"]));
+/// assert!(events.iter().eq(vec![(&10, &"start"), (&10, &"load"), (&12, &"tick")]));
+/// ```
+#[derive(Clone)]
+pub struct TreeMultiMap<K, V, C: Compare<K> = Natural<K>> {
+    map: TreeMap<K, VecDeque<V>, C>,
+    length: usize,
+}
+
+impl<K: Ord, V> TreeMultiMap<K, V> {
+    /// Creates an empty `TreeMultiMap` ordered according to the natural order
+    /// of its keys.
+    pub fn new() -> TreeMultiMap<K, V> {
+        TreeMultiMap::with_comparator(natural())
+    }
+}
+
+impl<K, V, C> TreeMultiMap<K, V, C>
+    where C: Compare<K>
+{
+    /// Creates an empty `TreeMultiMap` ordered according to the given
+    /// comparator.
+    pub fn with_comparator(cmp: C) -> TreeMultiMap<K, V, C> {
+        TreeMultiMap {
+            map: TreeMap::with_comparator(cmp),
+            length: 0,
+        }
+    }
+
+    /// Returns the comparator according to which the `TreeMultiMap` is
+    /// ordered.
+    pub fn comparator(&self) -> &C {
+        self.map.comparator()
+    }
+
+    /// Returns the number of key-value pairs in the map.
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    /// Returns true if the map contains no pairs.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of distinct keys in the map.
+    pub fn keys_len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Clears the map, removing all pairs.
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.length = 0;
+    }
+
+    /// Adds a value for `key`, after any values that the key already has.
+    pub fn insert(&mut self, key: K, value: V) {
+        self.map.get_or_insert(key, VecDeque::new).push_back(value);
+        self.length += 1;
+    }
+
+    /// Returns an iterator over the values for `key`, in the order in which
+    /// they were inserted.
+    pub fn get_all<Q: ?Sized>(&self, key: &Q) -> vec_deque::Iter<'_, V>
+        where C: Compare<Q, K>
+    {
+        self.map.get(key).map(|values| values.iter()).unwrap_or_default()
+    }
+
+    /// Returns the number of values for `key`.
+    pub fn count<Q: ?Sized>(&self, key: &Q) -> usize
+        where C: Compare<Q, K>
+    {
+        self.get_all(key).len()
+    }
+
+    /// Returns true if the map contains any values for `key`.
+    pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool
+        where C: Compare<Q, K>
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Removes the earliest inserted value for `key`, and returns it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::TreeMultiMap;
+    ///
+    /// let mut map = TreeMultiMap::new();
+    /// map.insert(1, 'a');
+    /// map.insert(1, 'b');
+    /// assert_eq!(map.remove_one(&1), Some('a'));
+    /// assert_eq!(map.remove_one(&1), Some('b'));
+    /// assert_eq!(map.remove_one(&1), None);
+    /// assert!(!map.contains_key(&1));
+    /// ```
+    pub fn remove_one<Q: ?Sized>(&mut self, key: &Q) -> Option<V>
+        where C: Compare<Q, K>
+    {
+        let (value, now_empty) = {
+            let values = self.map.get_mut(key)?;
+            (values.pop_front().unwrap(), values.is_empty())
+        };
+        if now_empty {
+            self.map.remove(key);
+        }
+        self.length -= 1;
+        Some(value)
+    }
+
+    /// Removes all of the values for `key`, and returns them in the order in
+    /// which they were inserted.
+    pub fn remove_all<Q: ?Sized>(&mut self, key: &Q) -> Vec<V>
+        where C: Compare<Q, K>
+    {
+        let values = Vec::from(self.map.remove(key).unwrap_or_default());
+        self.length -= values.len();
+        values
+    }
+
+    /// Returns an iterator over every key-value pair, in order of the keys
+    /// and then in the order in which the values were inserted.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            pairs: Pairs::new(self.map.iter().without_comparator()),
+            remaining: self.length,
+        }
+    }
+
+    /// Returns an iterator over the distinct keys, in ascending order.
+    pub fn keys(&self) -> map::Keys<'_, K, VecDeque<V>> {
+        self.map.keys()
+    }
+
+    /// Returns a double-ended iterator over every key-value pair with a key
+    /// between `min` and `max`, with the bounds interpreted as by
+    /// `TreeMap::range`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::TreeMultiMap;
+    /// use stable_bst::Bound::{Excluded, Unbounded};
+    ///
+    /// let map: TreeMultiMap<i32, char> = vec![(1, 'a'), (2, 'b'), (2, 'c'), (3, 'd')]
+    ///                                        .into_iter()
+    ///                                        .collect();
+    /// assert!(map.range(Excluded(&1), Unbounded).map(|(_, v)| *v).eq(vec!['b', 'c', 'd']));
+    /// assert!(map.range(Unbounded, Excluded(&3)).rev().map(|(_, v)| *v).eq(vec!['c', 'b', 'a']));
+    /// ```
+    pub fn range<'a, Min: ?Sized, Max: ?Sized>(&'a self,
+                                               min: Bound<&Min>,
+                                               max: Bound<&Max>)
+                                               -> Range<'a, K, V>
+        where C: Compare<Min, K> + Compare<Max, K>
+    {
+        Range { pairs: Pairs::new(self.map.range(min, max).without_comparator()) }
+    }
+}
+
+impl<K, V: PartialEq, C> PartialEq for TreeMultiMap<K, V, C>
+    where C: Compare<K>
+{
+    fn eq(&self, other: &TreeMultiMap<K, V, C>) -> bool {
+        self.map == other.map
+    }
+}
+
+impl<K, V: Eq, C> Eq for TreeMultiMap<K, V, C> where C: Compare<K> {}
+
+impl<K: Debug, V: Debug, C> Debug for TreeMultiMap<K, V, C>
+    where C: Compare<K>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{")?;
+
+        for (i, (k, v)) in self.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{:?}: {:?}", *k, *v)?;
+        }
+
+        write!(f, "}}")
+    }
+}
+
+impl<K, V, C> Default for TreeMultiMap<K, V, C>
+    where C: Compare<K> + Default
+{
+    fn default() -> TreeMultiMap<K, V, C> {
+        TreeMultiMap::with_comparator(Default::default())
+    }
+}
+
+impl<K, V, C> iter::FromIterator<(K, V)> for TreeMultiMap<K, V, C>
+    where C: Compare<K> + Default
+{
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> TreeMultiMap<K, V, C> {
+        let mut map: TreeMultiMap<K, V, C> = Default::default();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K, V, C> Extend<(K, V)> for TreeMultiMap<K, V, C>
+    where C: Compare<K>
+{
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+impl<'a, K, V, C> IntoIterator for &'a TreeMultiMap<K, V, C>
+    where C: Compare<K>
+{
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+/// Lazy forward iterator over the pairs of a `TreeMultiMap`.
+pub struct Iter<'a, K: 'a, V: 'a> {
+    pairs: Pairs<'a, K, V, map::Iter<'a, K, VecDeque<V>, Forward, ()>>,
+    remaining: usize,
+}
+
+/// Lazy double-ended iterator over the pairs in a sub-range of a
+/// `TreeMultiMap`.
+pub struct Range<'a, K: 'a, V: 'a> {
+    pairs: Pairs<'a, K, V, map::Range<'a, K, VecDeque<V>, ()>>,
+}
+
+// Flattens an iterator over the values for each key into one over pairs,
+// keeping the partly visited groups at both ends.
+struct Pairs<'a, K: 'a, V: 'a, I> {
+    groups: I,
+    front: Option<(&'a K, vec_deque::Iter<'a, V>)>,
+    back: Option<(&'a K, vec_deque::Iter<'a, V>)>,
+}
+
+impl<'a, K, V, I> Pairs<'a, K, V, I>
+    where I: Iterator<Item = (&'a K, &'a VecDeque<V>)>
+{
+    #[allow(clippy::redundant_field_names)]
+    fn new(groups: I) -> Pairs<'a, K, V, I> {
+        Pairs {
+            groups: groups,
+            front: None,
+            back: None,
+        }
+    }
+
+    // The number of pairs left in the partly visited groups.
+    fn started(&self) -> usize {
+        self.front.as_ref().map_or(0, |f| f.1.len()) + self.back.as_ref().map_or(0, |b| b.1.len())
+    }
+}
+
+impl<'a, K, V, I> Iterator for Pairs<'a, K, V, I>
+    where I: Iterator<Item = (&'a K, &'a VecDeque<V>)>
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        loop {
+            if let Some((k, ref mut values)) = self.front {
+                if let Some(v) = values.next() {
+                    return Some((k, v));
+                }
+            }
+            match self.groups.next() {
+                Some((k, values)) => self.front = Some((k, values.iter())),
+                None => {
+                    let (k, values) = self.back.as_mut()?;
+                    return values.next().map(|v| (*k, v));
+                }
+            }
+        }
+    }
+}
+
+impl<'a, K, V, I> DoubleEndedIterator for Pairs<'a, K, V, I>
+    where I: DoubleEndedIterator<Item = (&'a K, &'a VecDeque<V>)>
+{
+    fn next_back(&mut self) -> Option<(&'a K, &'a V)> {
+        loop {
+            if let Some((k, ref mut values)) = self.back {
+                if let Some(v) = values.next_back() {
+                    return Some((k, v));
+                }
+            }
+            match self.groups.next_back() {
+                Some((k, values)) => self.back = Some((k, values.iter())),
+                None => {
+                    let (k, values) = self.front.as_mut()?;
+                    return values.next_back().map(|v| (*k, v));
+                }
+            }
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        let pair = self.pairs.next()?;
+        self.remaining -= 1;
+        Some(pair)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V> {}
+impl<'a, K, V> FusedIterator for Iter<'a, K, V> {}
+
+impl<'a, K, V> Iterator for Range<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    #[inline]
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        self.pairs.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Each group left holds at least one value, but may hold any number.
+        let (groups, _) = self.pairs.groups.size_hint();
+        (self.pairs.started() + groups, None)
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for Range<'a, K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<(&'a K, &'a V)> {
+        self.pairs.next_back()
+    }
+}
+
+impl<'a, K, V> FusedIterator for Range<'a, K, V> {}
+
+#[cfg(test)]
+mod test {
+    use compare::{Compare, natural};
+
+    use super::TreeMultiMap;
+    use super::super::Bound::{Excluded, Included, Unbounded};
+
+    #[test]
+    fn test_insert_and_remove() {
+        let mut m = TreeMultiMap::new();
+        assert!(m.is_empty());
+        for (i, k) in [3, 1, 3, 2, 3, 1].iter().enumerate() {
+            m.insert(*k, i);
+        }
+        assert_eq!((m.len(), m.keys_len()), (6, 3));
+        assert!(m.get_all(&3).eq(&[0, 2, 4]));
+        assert_eq!(m.get_all(&4).next(), None);
+        assert_eq!((m.count(&1), m.count(&2), m.count(&4)), (2, 1, 0));
+
+        assert_eq!(m.remove_one(&3), Some(0));
+        assert!(m.get_all(&3).eq(&[2, 4]));
+        assert_eq!(m.remove_one(&2), Some(3));
+        assert!(!m.contains_key(&2));
+        assert_eq!(m.remove_one(&2), None);
+        assert_eq!(m.len(), 4);
+
+        assert_eq!(m.remove_all(&1), [1, 5]);
+        assert_eq!(m.remove_all(&1), []);
+        assert_eq!((m.len(), m.keys_len()), (2, 1));
+        assert_eq!(format!("{:?}", m), "{3: 2, 3: 4}");
+
+        m.clear();
+        assert!(m.is_empty() && m.iter().next().is_none());
+    }
+
+    #[test]
+    fn test_iterators() {
+        let m: TreeMultiMap<i32, char> = vec![(2, 'c'), (1, 'a'), (2, 'd'), (1, 'b'), (4, 'e')]
+                                             .into_iter()
+                                             .collect();
+        let mut iter = m.iter();
+        assert_eq!(iter.len(), 5);
+        assert_eq!(iter.next(), Some((&1, &'a')));
+        assert_eq!(iter.len(), 4);
+        assert!(iter.map(|(_, v)| *v).eq("bcde".chars()));
+        assert!(m.keys().eq(vec![&1, &2, &4]));
+
+        assert!(m.range(Included(&2), Unbounded).map(|(_, v)| *v).eq("cde".chars()));
+        assert!(m.range(Excluded(&1), Excluded(&4)).rev().map(|(_, v)| *v).eq("dc".chars()));
+        assert_eq!(m.range(Included(&3), Excluded(&4)).next(), None);
+
+        // Both ends within the same group.
+        let mut range = m.range(Included(&2), Included(&2));
+        assert_eq!(range.size_hint(), (1, None));
+        assert_eq!(range.next_back(), Some((&2, &'d')));
+        assert_eq!(range.next(), Some((&2, &'c')));
+        assert_eq!(range.size_hint(), (0, None));
+        assert_eq!((range.next(), range.next_back()), (None, None));
+
+        let mut range = m.range(Unbounded, Unbounded);
+        assert_eq!(range.next(), Some((&1, &'a')));
+        assert_eq!(range.next_back(), Some((&4, &'e')));
+        assert_eq!(range.next_back(), Some((&2, &'d')));
+        assert_eq!(range.size_hint(), (2, None));
+        assert!(range.map(|(_, v)| *v).eq("bc".chars()));
+    }
+
+    #[test]
+    fn test_comparator() {
+        let mut m = TreeMultiMap::with_comparator(natural::<i32>().rev());
+        m.extend(vec![(1, 'a'), (3, 'b'), (1, 'c')]);
+        assert!(m.iter().map(|(_, v)| *v).eq("bac".chars()));
+        assert!(m.comparator().compares_lt(&3, &1));
+
+        let mut other = TreeMultiMap::with_comparator(natural::<i32>().rev());
+        other.extend(vec![(3, 'b'), (1, 'a'), (1, 'c')]);
+        assert!(m == other);
+        other.remove_one(&1);
+        other.insert(1, 'a');
+        assert!(m != other);
+    }
+}
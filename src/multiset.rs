@@ -0,0 +1,595 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! An ordered multiset, or bag, that counts how many times each value occurs.
+
+use alloc::vec::{self, Vec};
+use core::cmp::{self, Ordering};
+use core::default::Default;
+use core::fmt::{self, Debug};
+use core::iter::{self, FusedIterator, Peekable};
+
+use compare::{Compare, Natural, natural};
+
+use super::map::{self, Forward, TreeMap};
+use super::set::CompatibleWith;
+
+/// An ordered multiset that stores each distinct value once, along with the
+/// number of times it occurs.
+///
+/// Values are ordered by the comparator, as in a `TreeSet`, and values that
+/// the comparator considers equal are counted together. A value whose count
+/// drops to zero is removed.
+///
+/// # Examples
+///
+/// ```rust
+/// use stable_bst::TreeMultiSet;
+///
+/// let mut bag: TreeMultiSet<&str> = vec!["b", "a", "b"].into_iter().collect();
+/// bag.insert_n("c", 3);
+///
+/// assert_eq!(bag.count(&"b"), 2);
+/// assert_eq!((bag.total_len(), bag.distinct_len()), (6, 3));
+/// assert!(bag.iter().eq(vec![&"a", &"b", &"b", &"c", &"c", &"c"]));
+/// assert!(bag.counts().eq(vec![(&"a", 1), (&"b", 2), (&"c", 3)]));
+/// ```
+#[derive(Clone)]
+pub struct TreeMultiSet<T, C: Compare<T> = Natural<T>> {
+    map: TreeMap<T, usize, C>,
+    total: usize,
+}
+
+impl<T: Ord> TreeMultiSet<T> {
+    /// Creates an empty `TreeMultiSet` ordered according to the natural order
+    /// of its values.
+    pub fn new() -> TreeMultiSet<T> {
+        TreeMultiSet::with_comparator(natural())
+    }
+}
+
+impl<T, C> TreeMultiSet<T, C>
+    where C: Compare<T>
+{
+    /// Creates an empty `TreeMultiSet` ordered according to the given
+    /// comparator.
+    pub fn with_comparator(cmp: C) -> TreeMultiSet<T, C> {
+        TreeMultiSet {
+            map: TreeMap::with_comparator(cmp),
+            total: 0,
+        }
+    }
+
+    /// Returns the comparator according to which the `TreeMultiSet` is
+    /// ordered.
+    pub fn comparator(&self) -> &C {
+        self.map.comparator()
+    }
+
+    /// Returns the number of values in the multiset, counting each as many
+    /// times as it occurs.
+    pub fn total_len(&self) -> usize {
+        self.total
+    }
+
+    /// Returns the number of distinct values in the multiset.
+    pub fn distinct_len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns true if the multiset contains no values.
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    /// Clears the multiset, removing all values.
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.total = 0;
+    }
+
+    /// Returns the number of times `value` occurs in the multiset.
+    pub fn count<Q: ?Sized>(&self, value: &Q) -> usize
+        where C: Compare<Q, T>
+    {
+        self.map.get(value).cloned().unwrap_or(0)
+    }
+
+    /// Returns true if `value` occurs in the multiset.
+    pub fn contains<Q: ?Sized>(&self, value: &Q) -> bool
+        where C: Compare<Q, T>
+    {
+        self.map.contains_key(value)
+    }
+
+    /// Adds one occurrence of `value`, and returns the number of times it
+    /// occurred before.
+    pub fn insert(&mut self, value: T) -> usize {
+        self.insert_n(value, 1)
+    }
+
+    /// Adds `n` occurrences of `value`, and returns the number of times it
+    /// occurred before. Adding no occurrences leaves the multiset unchanged.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the total number of occurrences in the multiset would
+    /// overflow a `usize`, in which case the multiset is left unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::TreeMultiSet;
+    ///
+    /// let mut bag = TreeMultiSet::new();
+    /// assert_eq!(bag.insert_n('a', 2), 0);
+    /// assert_eq!(bag.insert_n('a', 3), 2);
+    /// assert_eq!(bag.insert_n('b', 0), 0);
+    /// assert!(!bag.contains(&'b'));
+    /// ```
+    pub fn insert_n(&mut self, value: T, n: usize) -> usize {
+        if n == 0 {
+            return self.count(&value);
+        }
+        // No count exceeds the total, so checking the total is enough.
+        self.total = self.total.checked_add(n).expect("occurrence count overflowed");
+        let count = self.map.get_or_insert(value, || 0);
+        let old = *count;
+        *count += n;
+        old
+    }
+
+    /// Removes one occurrence of `value`, and returns true if there was one.
+    pub fn remove<Q: ?Sized>(&mut self, value: &Q) -> bool
+        where C: Compare<Q, T>
+    {
+        self.remove_n(value, 1) == 1
+    }
+
+    /// Removes up to `n` occurrences of `value`, and returns the number that
+    /// were removed.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::TreeMultiSet;
+    ///
+    /// let mut bag = TreeMultiSet::new();
+    /// bag.insert_n('a', 3);
+    /// assert_eq!(bag.remove_n(&'a', 2), 2);
+    /// assert_eq!(bag.remove_n(&'a', 2), 1);
+    /// assert!(!bag.contains(&'a'));
+    /// ```
+    pub fn remove_n<Q: ?Sized>(&mut self, value: &Q, n: usize) -> usize
+        where C: Compare<Q, T>
+    {
+        let (removed, left) = match self.map.get_mut(value) {
+            None => return 0,
+            Some(count) => {
+                let removed = cmp::min(*count, n);
+                *count -= removed;
+                (removed, *count)
+            }
+        };
+        if left == 0 {
+            self.map.remove(value);
+        }
+        self.total -= removed;
+        removed
+    }
+
+    /// Returns an iterator over the values in ascending order, repeating each
+    /// as many times as it occurs.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            counts: self.map.iter().without_comparator(),
+            current: None,
+            remaining: self.total,
+        }
+    }
+
+    /// Returns an iterator over the distinct values in ascending order, each
+    /// with the number of times it occurs.
+    pub fn counts(&self) -> Counts<'_, T> {
+        Counts(self.map.iter().without_comparator())
+    }
+
+    /// Returns the union of the two multisets, in which each value occurs as
+    /// many times as it does in whichever of them has more of it.
+    ///
+    /// The result is ordered by this multiset's comparator. If `other`'s is
+    /// not compatible with it, `other` is sorted first, as by the set
+    /// operations of `TreeSet`. As with those, `C` must implement
+    /// `CompatibleWith<D>`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the total number of occurrences in the result would
+    /// overflow a `usize`. The same goes for `sum`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::TreeMultiSet;
+    ///
+    /// let a: TreeMultiSet<char> = "aab".chars().collect();
+    /// let b: TreeMultiSet<char> = "abbc".chars().collect();
+    /// assert!(a.union(&b).iter().cloned().eq("aabbc".chars()));
+    /// assert!(a.sum(&b).iter().cloned().eq("aaabbbc".chars()));
+    /// assert!(a.intersection(&b).iter().cloned().eq("ab".chars()));
+    /// assert!(a.difference(&b).iter().cloned().eq("a".chars()));
+    /// ```
+    pub fn union<D>(&self, other: &TreeMultiSet<T, D>) -> TreeMultiSet<T, C>
+        where T: Clone,
+              C: CompatibleWith<D> + Clone,
+              D: Compare<T>
+    {
+        self.merge(other, cmp::max)
+    }
+
+    /// Returns the sum of the two multisets, in which each value occurs as
+    /// many times as it does in both of them together.
+    ///
+    /// As with `union`, `C` must implement `CompatibleWith<D>`.
+    pub fn sum<D>(&self, other: &TreeMultiSet<T, D>) -> TreeMultiSet<T, C>
+        where T: Clone,
+              C: CompatibleWith<D> + Clone,
+              D: Compare<T>
+    {
+        self.merge(other, |a, b| a.checked_add(b).expect("occurrence count overflowed"))
+    }
+
+    /// Returns the intersection of the two multisets, in which each value
+    /// occurs as many times as it does in whichever of them has less of it.
+    ///
+    /// As with `union`, `C` must implement `CompatibleWith<D>`.
+    pub fn intersection<D>(&self, other: &TreeMultiSet<T, D>) -> TreeMultiSet<T, C>
+        where T: Clone,
+              C: CompatibleWith<D> + Clone,
+              D: Compare<T>
+    {
+        self.merge(other, cmp::min)
+    }
+
+    /// Returns the difference of the two multisets, in which each value
+    /// occurs as many more times as it does in `self` than in `other`.
+    ///
+    /// As with `union`, `C` must implement `CompatibleWith<D>`.
+    pub fn difference<D>(&self, other: &TreeMultiSet<T, D>) -> TreeMultiSet<T, C>
+        where T: Clone,
+              C: CompatibleWith<D> + Clone,
+              D: Compare<T>
+    {
+        self.merge(other, |a, b| a.saturating_sub(b))
+    }
+
+    // Walks the distinct values of both multisets in step, and builds a
+    // multiset of the values for which `f` of their two counts is nonzero.
+    #[allow(clippy::redundant_field_names)]
+    fn merge<D, F>(&self, other: &TreeMultiSet<T, D>, f: F) -> TreeMultiSet<T, C>
+        where T: Clone,
+              C: CompatibleWith<D> + Clone,
+              D: Compare<T>,
+              F: Fn(usize, usize) -> usize
+    {
+        let cmp = self.comparator();
+        let mut a = self.counts().peekable();
+        let mut b = self.other_counts(other);
+        let mut merged = vec![];
+        let mut total: usize = 0;
+        loop {
+            let (value, count) = match (a.peek().cloned(), b.peek().cloned()) {
+                (None, None) => break,
+                (Some((x, n)), None) => {
+                    a.next();
+                    (x, f(n, 0))
+                }
+                (None, Some((y, m))) => {
+                    b.next();
+                    (y, f(0, m))
+                }
+                (Some((x, n)), Some((y, m))) => {
+                    match cmp.compare(x, y) {
+                        Ordering::Less => {
+                            a.next();
+                            (x, f(n, 0))
+                        }
+                        Ordering::Greater => {
+                            b.next();
+                            (y, f(0, m))
+                        }
+                        Ordering::Equal => {
+                            a.next();
+                            b.next();
+                            (x, f(n, m))
+                        }
+                    }
+                }
+            };
+            if count > 0 {
+                merged.push((value.clone(), count));
+                total = total.checked_add(count).expect("occurrence count overflowed");
+            }
+        }
+        TreeMultiSet {
+            map: TreeMap::from_sorted(merged.len(), merged.into_iter(), cmp.clone()),
+            total: total,
+        }
+    }
+
+    // Iterates over the counts of `other` in the order of `self`'s
+    // comparator, sorting them and adding up the counts of values that it
+    // considers equal if the comparators are not compatible.
+    fn other_counts<'a, D>(&self, other: &'a TreeMultiSet<T, D>) -> Peekable<OtherCounts<'a, T>>
+        where C: CompatibleWith<D>,
+              D: Compare<T>
+    {
+        let cmp = self.comparator();
+        if cmp.compatible_with(other.comparator()) {
+            return OtherCounts::Tree(other.counts()).peekable();
+        }
+        let mut sorted: Vec<(&T, usize)> = other.counts().collect();
+        sorted.sort_by(|a, b| cmp.compare(a.0, b.0));
+        let mut merged: Vec<(&T, usize)> = Vec::with_capacity(sorted.len());
+        for (value, count) in sorted {
+            match merged.last_mut() {
+                Some(last) if cmp.compares_eq(last.0, value) => last.1 += count,
+                _ => merged.push((value, count)),
+            }
+        }
+        OtherCounts::Sorted(merged.into_iter()).peekable()
+    }
+}
+
+impl<T, C> PartialEq for TreeMultiSet<T, C>
+    where C: Compare<T>
+{
+    fn eq(&self, other: &TreeMultiSet<T, C>) -> bool {
+        self.map == other.map
+    }
+}
+
+impl<T, C> Eq for TreeMultiSet<T, C> where C: Compare<T> {}
+
+impl<T: Debug, C> Debug for TreeMultiSet<T, C>
+    where C: Compare<T>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{")?;
+
+        for (i, x) in self.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{:?}", *x)?;
+        }
+
+        write!(f, "}}")
+    }
+}
+
+impl<T, C> Default for TreeMultiSet<T, C>
+    where C: Compare<T> + Default
+{
+    fn default() -> TreeMultiSet<T, C> {
+        TreeMultiSet::with_comparator(Default::default())
+    }
+}
+
+impl<T, C> iter::FromIterator<T> for TreeMultiSet<T, C>
+    where C: Compare<T> + Default
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> TreeMultiSet<T, C> {
+        let mut set: TreeMultiSet<T, C> = Default::default();
+        set.extend(iter);
+        set
+    }
+}
+
+impl<T, C> Extend<T> for TreeMultiSet<T, C>
+    where C: Compare<T>
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for value in iter {
+            self.insert(value);
+        }
+    }
+}
+
+impl<'a, T, C> IntoIterator for &'a TreeMultiSet<T, C>
+    where C: Compare<T>
+{
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+/// Lazy forward iterator over the values of a `TreeMultiSet`, repeating each
+/// as many times as it occurs.
+pub struct Iter<'a, T: 'a> {
+    counts: map::Iter<'a, T, usize, Forward, ()>,
+    current: Option<(&'a T, usize)>,
+    remaining: usize,
+}
+
+/// Lazy forward iterator over the distinct values of a `TreeMultiSet` and
+/// their counts.
+pub struct Counts<'a, T: 'a>(map::Iter<'a, T, usize, Forward, ()>);
+
+// The counts of the second operand of a multiset operation, in the order of
+// the first operand's comparator.
+enum OtherCounts<'a, T: 'a> {
+    Tree(Counts<'a, T>),
+    Sorted(vec::IntoIter<(&'a T, usize)>),
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            if let Some((value, ref mut n)) = self.current {
+                if *n > 0 {
+                    *n -= 1;
+                    self.remaining -= 1;
+                    return Some(value);
+                }
+            }
+            let (value, &n) = self.counts.next()?;
+            self.current = Some((value, n));
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, T> ExactSizeIterator for Iter<'a, T> {}
+impl<'a, T> FusedIterator for Iter<'a, T> {}
+
+impl<'a, T> Iterator for Counts<'a, T> {
+    type Item = (&'a T, usize);
+
+    #[inline]
+    fn next(&mut self) -> Option<(&'a T, usize)> {
+        self.0.next().map(|(value, &n)| (value, n))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, T> ExactSizeIterator for Counts<'a, T> {}
+impl<'a, T> FusedIterator for Counts<'a, T> {}
+
+impl<'a, T> Iterator for OtherCounts<'a, T> {
+    type Item = (&'a T, usize);
+
+    fn next(&mut self) -> Option<(&'a T, usize)> {
+        match *self {
+            OtherCounts::Tree(ref mut iter) => iter.next(),
+            OtherCounts::Sorted(ref mut iter) => iter.next(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cmp::Ordering;
+
+    use compare::{Compare, Natural, natural};
+
+    use super::TreeMultiSet;
+    use super::super::set::CompatibleWith;
+
+    #[test]
+    fn test_counts() {
+        let mut bag = TreeMultiSet::new();
+        assert!(bag.is_empty());
+        assert_eq!(bag.insert(3), 0);
+        assert_eq!(bag.insert(3), 1);
+        assert_eq!(bag.insert_n(1, 4), 0);
+        assert_eq!(bag.insert_n(2, 0), 0);
+        assert_eq!((bag.total_len(), bag.distinct_len()), (6, 2));
+        assert_eq!((bag.count(&1), bag.count(&2), bag.count(&3)), (4, 0, 2));
+
+        assert!(bag.remove(&3));
+        assert_eq!(bag.remove_n(&1, 3), 3);
+        assert_eq!(bag.remove_n(&2, 3), 0);
+        assert_eq!(format!("{:?}", bag), "{1, 3}");
+        assert!(bag.remove(&3));
+        assert!(!bag.remove(&3));
+        assert!(!bag.contains(&3));
+        assert_eq!((bag.total_len(), bag.distinct_len()), (1, 1));
+
+        bag.clear();
+        assert!(bag.is_empty() && bag.iter().next().is_none());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_insert_overflow() {
+        let mut bag = TreeMultiSet::new();
+        bag.insert_n(1, usize::MAX);
+        bag.insert(2);
+    }
+
+    #[test]
+    fn test_iterators() {
+        let bag: TreeMultiSet<char> = "mississippi".chars().collect();
+        let mut iter = bag.iter();
+        assert_eq!(iter.len(), 11);
+        assert_eq!(iter.next(), Some(&'i'));
+        assert_eq!(iter.len(), 10);
+        assert!(iter.cloned().eq("iiimppssss".chars()));
+        let counts = bag.counts();
+        assert_eq!(counts.len(), 4);
+        assert!(counts.eq(vec![(&'i', 4), (&'m', 1), (&'p', 2), (&'s', 4)]));
+        assert!((&bag).into_iter().count() == 11);
+    }
+
+    #[test]
+    fn test_operations() {
+        let a: TreeMultiSet<i32> = vec![1, 1, 1, 2, 4, 4].into_iter().collect();
+        let b: TreeMultiSet<i32> = vec![1, 2, 2, 3, 4, 4].into_iter().collect();
+
+        let counts = |bag: TreeMultiSet<i32>| bag.counts().map(|(&x, n)| (x, n)).collect::<Vec<_>>();
+        assert_eq!(counts(a.union(&b)), [(1, 3), (2, 2), (3, 1), (4, 2)]);
+        assert_eq!(counts(a.sum(&b)), [(1, 4), (2, 3), (3, 1), (4, 4)]);
+        assert_eq!(counts(a.intersection(&b)), [(1, 1), (2, 1), (4, 2)]);
+        assert_eq!(counts(a.difference(&b)), [(1, 2)]);
+        assert_eq!(counts(b.difference(&a)), [(2, 1), (3, 1)]);
+        assert_eq!(a.sum(&b).total_len(), 12);
+        assert!(a.difference(&a).is_empty());
+
+        let union = a.union(&b);
+        assert_eq!(union.map.check_invariants(), Ok(()));
+        assert!(union == b.union(&a));
+    }
+
+    #[test]
+    fn test_incompatible_comparators() {
+        // Compares by `x / 10`, so that 21 and 25 are the same value to it.
+        #[derive(Clone)]
+        struct Tens;
+
+        impl Compare<i32> for Tens {
+            fn compare(&self, l: &i32, r: &i32) -> Ordering {
+                (l / 10).cmp(&(r / 10))
+            }
+        }
+
+        impl CompatibleWith<Natural<i32>> for Tens {
+            fn compatible_with(&self, _: &Natural<i32>) -> bool {
+                false
+            }
+        }
+
+        let mut tens = TreeMultiSet::with_comparator(Tens);
+        tens.insert_n(20, 1);
+        tens.insert_n(30, 2);
+        let b: TreeMultiSet<i32> = vec![25, 21, 30, 5].into_iter().collect();
+
+        let counts = |bag: TreeMultiSet<i32, Tens>| bag.counts().map(|(&x, n)| (x, n)).collect::<Vec<_>>();
+        assert_eq!(counts(tens.union(&b)), [(5, 1), (20, 2), (30, 2)]);
+        assert_eq!(counts(tens.intersection(&b)), [(20, 1), (30, 1)]);
+
+        let rev: TreeMultiSet<i32, _> = TreeMultiSet::with_comparator(natural::<i32>().rev());
+        let mut other = rev.clone();
+        other.extend(vec![5, 30, 21]);
+        assert!(rev.sum(&other).iter().cloned().eq(vec![30, 21, 5]));
+    }
+}
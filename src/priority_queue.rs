@@ -0,0 +1,345 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A double-ended priority queue whose entries can be found by id to change
+//! their priorities.
+
+use core::default::Default;
+use core::fmt::{self, Debug};
+use core::iter::{self, FusedIterator};
+use core::mem::replace;
+
+use super::map::TreeMap;
+use super::set::{self, TreeSet};
+
+/// A priority queue of ids, each with a priority that can be looked up and
+/// changed by its id.
+///
+/// The entries are kept in a `TreeSet` ordered by priority and then by id,
+/// so entries with equal priorities come out in the order of their ids,
+/// alongside a `TreeMap` from each id to its priority. Every operation takes
+/// O(log n), and the queue can be popped from either end.
+///
+/// # Examples
+///
+/// ```rust
+/// use stable_bst::IndexedPriorityQueue;
+///
+/// let mut distances = IndexedPriorityQueue::new();
+/// distances.push('a', 7);
+/// distances.push('b', 3);
+/// distances.push('c', 5);
+/// distances.change_priority(&'a', 1);
+///
+/// assert_eq!(distances.peek(), Some((&'a', &1)));
+/// assert_eq!(distances.pop_min(), Some(('a', 1)));
+/// assert_eq!(distances.pop_max(), Some(('c', 5)));
+/// assert_eq!(distances.priority(&'b'), Some(&3));
+/// ```
+#[derive(Clone)]
+pub struct IndexedPriorityQueue<Id: Ord, P: Ord> {
+    order: TreeSet<(P, Id)>,
+    priorities: TreeMap<Id, P>,
+}
+
+impl<Id, P> IndexedPriorityQueue<Id, P>
+    where Id: Ord + Clone,
+          P: Ord + Clone
+{
+    /// Creates an empty `IndexedPriorityQueue`.
+    pub fn new() -> IndexedPriorityQueue<Id, P> {
+        IndexedPriorityQueue {
+            order: TreeSet::new(),
+            priorities: TreeMap::new(),
+        }
+    }
+
+    /// Returns the number of entries in the queue.
+    pub fn len(&self) -> usize {
+        self.priorities.len()
+    }
+
+    /// Returns true if the queue contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.priorities.is_empty()
+    }
+
+    /// Clears the queue, removing all entries.
+    pub fn clear(&mut self) {
+        self.order.clear();
+        self.priorities.clear();
+    }
+
+    /// Returns true if the queue has an entry for `id`.
+    pub fn contains(&self, id: &Id) -> bool {
+        self.priorities.contains_key(id)
+    }
+
+    /// Returns the priority of `id`.
+    pub fn priority(&self, id: &Id) -> Option<&P> {
+        self.priorities.get(id)
+    }
+
+    /// Adds `id` with `priority`, or changes its priority if it is already in
+    /// the queue, and returns the priority it had before.
+    pub fn push(&mut self, id: Id, priority: P) -> Option<P> {
+        if self.contains(&id) {
+            return self.change_priority(&id, priority);
+        }
+        self.order.insert((priority.clone(), id.clone()));
+        self.priorities.insert(id, priority);
+        None
+    }
+
+    /// Changes the priority of `id`, and returns the priority it had before,
+    /// or leaves the queue alone and returns `None` if `id` is not in it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::IndexedPriorityQueue;
+    ///
+    /// let mut queue = IndexedPriorityQueue::new();
+    /// queue.push("x", 10);
+    /// assert_eq!(queue.change_priority(&"x", 2), Some(10));
+    /// assert_eq!(queue.change_priority(&"y", 2), None);
+    /// assert_eq!(queue.len(), 1);
+    /// ```
+    pub fn change_priority(&mut self, id: &Id, priority: P) -> Option<P> {
+        let old = match self.priorities.get_mut(id) {
+            Some(current) => replace(current, priority.clone()),
+            None => return None,
+        };
+        let key = (old, id.clone());
+        self.order.remove(&key);
+        self.order.insert((priority, key.1));
+        Some(key.0)
+    }
+
+    /// Removes `id` from the queue, and returns its priority.
+    pub fn remove(&mut self, id: &Id) -> Option<P> {
+        let priority = self.priorities.remove(id)?;
+        let key = (priority, id.clone());
+        self.order.remove(&key);
+        Some(key.0)
+    }
+
+    /// Returns the entry with the lowest priority.
+    pub fn peek(&self) -> Option<(&Id, &P)> {
+        self.iter().next()
+    }
+
+    /// Returns the entry with the highest priority.
+    pub fn peek_max(&self) -> Option<(&Id, &P)> {
+        self.iter().next_back()
+    }
+
+    /// Removes the entry with the lowest priority, and returns it.
+    pub fn pop_min(&mut self) -> Option<(Id, P)> {
+        let id = self.peek()?.0.clone();
+        self.remove(&id).map(|priority| (id, priority))
+    }
+
+    /// Removes the entry with the highest priority, and returns it.
+    pub fn pop_max(&mut self) -> Option<(Id, P)> {
+        let id = self.peek_max()?.0.clone();
+        self.remove(&id).map(|priority| (id, priority))
+    }
+
+    /// Returns a double-ended iterator over the entries in ascending order of
+    /// priority.
+    pub fn iter(&self) -> Iter<'_, Id, P> {
+        Iter(self.order.range_by::<(P, Id), _>(..).without_comparator())
+    }
+}
+
+impl<Id, P> PartialEq for IndexedPriorityQueue<Id, P>
+    where Id: Ord,
+          P: Ord
+{
+    fn eq(&self, other: &IndexedPriorityQueue<Id, P>) -> bool {
+        self.priorities == other.priorities
+    }
+}
+
+impl<Id: Ord, P: Ord> Eq for IndexedPriorityQueue<Id, P> {}
+
+impl<Id, P> Debug for IndexedPriorityQueue<Id, P>
+    where Id: Ord + Clone + Debug,
+          P: Ord + Clone + Debug
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{")?;
+
+        for (i, (id, priority)) in self.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{:?}: {:?}", *id, *priority)?;
+        }
+
+        write!(f, "}}")
+    }
+}
+
+impl<Id, P> Default for IndexedPriorityQueue<Id, P>
+    where Id: Ord + Clone,
+          P: Ord + Clone
+{
+    fn default() -> IndexedPriorityQueue<Id, P> {
+        IndexedPriorityQueue::new()
+    }
+}
+
+impl<Id, P> iter::FromIterator<(Id, P)> for IndexedPriorityQueue<Id, P>
+    where Id: Ord + Clone,
+          P: Ord + Clone
+{
+    fn from_iter<I: IntoIterator<Item = (Id, P)>>(iter: I) -> IndexedPriorityQueue<Id, P> {
+        let mut queue = IndexedPriorityQueue::new();
+        queue.extend(iter);
+        queue
+    }
+}
+
+impl<Id, P> Extend<(Id, P)> for IndexedPriorityQueue<Id, P>
+    where Id: Ord + Clone,
+          P: Ord + Clone
+{
+    fn extend<I: IntoIterator<Item = (Id, P)>>(&mut self, iter: I) {
+        for (id, priority) in iter {
+            self.push(id, priority);
+        }
+    }
+}
+
+impl<'a, Id, P> IntoIterator for &'a IndexedPriorityQueue<Id, P>
+    where Id: Ord + Clone,
+          P: Ord + Clone
+{
+    type Item = (&'a Id, &'a P);
+    type IntoIter = Iter<'a, Id, P>;
+    fn into_iter(self) -> Iter<'a, Id, P> {
+        self.iter()
+    }
+}
+
+/// Lazy double-ended iterator over the entries of an `IndexedPriorityQueue`
+/// in ascending order of priority.
+pub struct Iter<'a, Id: 'a, P: 'a>(set::Range<'a, (P, Id), ()>);
+
+impl<'a, Id, P> Iterator for Iter<'a, Id, P> {
+    type Item = (&'a Id, &'a P);
+
+    #[inline]
+    fn next(&mut self) -> Option<(&'a Id, &'a P)> {
+        self.0.next().map(|(priority, id)| (id, priority))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, Id, P> DoubleEndedIterator for Iter<'a, Id, P> {
+    #[inline]
+    fn next_back(&mut self) -> Option<(&'a Id, &'a P)> {
+        self.0.next_back().map(|(priority, id)| (id, priority))
+    }
+}
+
+impl<'a, Id, P> FusedIterator for Iter<'a, Id, P> {}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use rand::{self, Rng};
+
+    use super::IndexedPriorityQueue;
+
+    #[test]
+    fn test_operations() {
+        let mut queue: IndexedPriorityQueue<u32, i32> = vec![(1, 5), (2, 5), (3, -1)].into_iter().collect();
+        assert_eq!(format!("{:?}", queue), "{3: -1, 1: 5, 2: 5}");
+        assert_eq!(queue.push(3, 9), Some(-1));
+        assert_eq!(queue.peek_max(), Some((&3, &9)));
+        assert_eq!(queue.remove(&1), Some(5));
+        assert_eq!(queue.remove(&1), None);
+        assert!(!queue.contains(&1));
+        assert!(queue.iter().rev().eq(vec![(&3, &9), (&2, &5)]));
+        assert_eq!(queue.pop_min(), Some((2, 5)));
+        assert_eq!(queue.pop_min(), Some((3, 9)));
+        assert_eq!(queue.pop_max(), None);
+        assert!(queue.is_empty() && queue.peek().is_none());
+    }
+
+    #[test]
+    fn test_dijkstra() {
+        // A ring of 20 nodes, each linked to the next two with weights 3 and
+        // 5, starting from node 0.
+        let n = 20;
+        let mut dist = vec![u32::MAX; n];
+        let mut queue = IndexedPriorityQueue::new();
+        queue.push(0, 0);
+        while let Some((node, d)) = queue.pop_min() {
+            dist[node] = d;
+            for &(step, weight) in &[(1, 3), (2, 5)] {
+                let next = (node + step) % n;
+                if dist[next] != u32::MAX {
+                    continue;
+                }
+                match queue.priority(&next).cloned() {
+                    Some(p) if p <= d + weight => {}
+                    Some(_) => {
+                        queue.change_priority(&next, d + weight);
+                    }
+                    None => {
+                        queue.push(next, d + weight);
+                    }
+                }
+            }
+        }
+        let expected: Vec<u32> = (0..n as u32).map(|i| i / 2 * 5 + i % 2 * 3).collect();
+        assert_eq!(dist, expected);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // too slow
+    fn test_random() {
+        let mut rng = rand::weak_rng();
+        let mut queue = IndexedPriorityQueue::new();
+        let mut model = BTreeMap::new();
+
+        for _ in 0..2000 {
+            let id = rng.gen_range(0, 40u32);
+            let priority = rng.gen_range(0, 10u32);
+            match rng.gen_range(0, 5) {
+                0 => assert_eq!(queue.remove(&id), model.remove(&id)),
+                1 => {
+                    let expected = model.get_mut(&id).map(|p| ::std::mem::replace(p, priority));
+                    assert_eq!(queue.change_priority(&id, priority), expected);
+                }
+                2 => {
+                    let min = model.iter().map(|(&i, &p)| (p, i)).min();
+                    if let Some((_, i)) = min {
+                        model.remove(&i);
+                    }
+                    assert_eq!(queue.pop_min(), min.map(|(p, i)| (i, p)));
+                }
+                _ => assert_eq!(queue.push(id, priority), model.insert(id, priority)),
+            }
+            let mut entries: Vec<(u32, u32)> = model.iter().map(|(&i, &p)| (p, i)).collect();
+            entries.sort();
+            assert!(queue.iter().map(|(&i, &p)| (p, i)).eq(entries));
+        }
+    }
+}
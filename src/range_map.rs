@@ -0,0 +1,414 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A map from disjoint half-open ranges of keys to values.
+
+use alloc::vec::Vec;
+use core::cmp;
+use core::default::Default;
+use core::fmt::{self, Debug};
+use core::iter::{self, FusedIterator};
+use core::ops;
+
+use super::Bound::{Excluded, Included, Unbounded};
+use super::map::{self, TreeMap};
+
+/// A map from half-open ranges of keys to values, which keeps its ranges
+/// disjoint.
+///
+/// Inserting a range that overlaps ranges already in the map overwrites the
+/// overlapping parts of them, splitting them where needed. Ranges that touch
+/// and map to equal values are merged into one, so that the map always holds
+/// as few ranges as possible. The ranges are stored in a `TreeMap` keyed by
+/// their starts.
+///
+/// # Examples
+///
+/// ```rust
+/// use stable_bst::RangeMap;
+///
+/// let mut map = RangeMap::new();
+/// map.insert(0..10, "a");
+/// map.insert(4..6, "b");
+/// map.insert(10..12, "a");
+///
+/// assert_eq!(map.get(&5), Some(&"b"));
+/// assert_eq!(map.get(&12), None);
+/// assert!(map.iter().eq(vec![(&0..&4, &"a"), (&4..&6, &"b"), (&6..&12, &"a")]));
+/// ```
+#[derive(Clone)]
+pub struct RangeMap<K: Ord, V> {
+    map: TreeMap<K, (K, V)>,
+}
+
+impl<K: Ord, V> RangeMap<K, V> {
+    /// Creates an empty `RangeMap`.
+    pub fn new() -> RangeMap<K, V> {
+        RangeMap { map: TreeMap::new() }
+    }
+
+    /// Returns the number of disjoint ranges in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns true if the map contains no ranges.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Clears the map, removing all ranges.
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+
+    /// Returns a reference to the value of the range that contains `point`.
+    pub fn get(&self, point: &K) -> Option<&V> {
+        self.get_range_value(point).map(|(_, v)| v)
+    }
+
+    /// Returns the range that contains `point`, along with its value.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::RangeMap;
+    ///
+    /// let mut map = RangeMap::new();
+    /// map.insert(3..7, 'x');
+    /// assert_eq!(map.get_range_value(&5), Some((&3..&7, &'x')));
+    /// assert_eq!(map.get_range_value(&7), None);
+    /// ```
+    pub fn get_range_value(&self, point: &K) -> Option<(ops::Range<&K>, &V)> {
+        match self.map.range(Unbounded, Included(point)).next_back() {
+            Some((start, (end, value))) if point < end => Some((start..end, value)),
+            _ => None,
+        }
+    }
+
+    /// Returns true if some range in the map contains `point`.
+    pub fn contains(&self, point: &K) -> bool {
+        self.get_range_value(point).is_some()
+    }
+
+    /// Returns an iterator over the ranges and their values, in ascending
+    /// order.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter(self.map.range(Unbounded::<&K>, Unbounded::<&K>).without_comparator())
+    }
+
+    /// Returns an iterator over the parts of `range` that no range in the map
+    /// covers, in ascending order.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::RangeMap;
+    ///
+    /// let mut map = RangeMap::new();
+    /// map.insert(2..4, ());
+    /// map.insert(6..8, ());
+    /// assert!(map.gaps(0..7).eq(vec![0..2, 4..6]));
+    /// assert!(map.gaps(3..5).eq(vec![4..5]));
+    /// ```
+    pub fn gaps(&self, range: ops::Range<K>) -> Gaps<'_, K, V>
+        where K: Clone
+    {
+        // A range that starts before `range` and reaches into it hides the
+        // start of the first gap.
+        let cursor = match self.map.range(Unbounded, Excluded(&range.start)).next_back() {
+            Some((_, (end, _))) if *end > range.start => end.clone(),
+            _ => range.start.clone(),
+        };
+        Gaps {
+            ranges: self.map.range(Included(&range.start), Excluded(&range.end)).without_comparator(),
+            cursor: Some(cursor),
+            end: range.end,
+        }
+    }
+}
+
+impl<K, V> RangeMap<K, V>
+    where K: Ord + Clone,
+          V: Eq + Clone
+{
+    /// Maps every key in `range` to `value`, overwriting what the map held
+    /// for any of them. Does nothing if `range` is empty.
+    pub fn insert(&mut self, range: ops::Range<K>, value: V) {
+        if range.start >= range.end {
+            return;
+        }
+        self.remove(range.clone());
+
+        let (mut start, mut end) = (range.start, range.end);
+        let before = match self.map.range(Unbounded, Excluded(&start)).next_back() {
+            Some((s, (e, v))) if *e == start && *v == value => Some(s.clone()),
+            _ => None,
+        };
+        if let Some(before) = before {
+            self.map.remove(&before);
+            start = before;
+        }
+        let after = match self.map.get(&end) {
+            Some((e, v)) if *v == value => Some(e.clone()),
+            _ => None,
+        };
+        if let Some(after) = after {
+            self.map.remove(&end);
+            end = after;
+        }
+        self.map.insert(start, (end, value));
+    }
+
+    /// Unmaps every key in `range`, splitting the ranges that extend beyond
+    /// it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::RangeMap;
+    ///
+    /// let mut map = RangeMap::new();
+    /// map.insert(0..10, 'a');
+    /// map.remove(3..5);
+    /// assert!(map.iter().eq(vec![(&0..&3, &'a'), (&5..&10, &'a')]));
+    /// ```
+    pub fn remove(&mut self, range: ops::Range<K>) {
+        if range.start >= range.end {
+            return;
+        }
+
+        // Trims the range that starts before `range` and reaches into it.
+        let before = match self.map.range(Unbounded, Excluded(&range.start)).next_back() {
+            Some((s, (e, _))) if *e > range.start => Some(s.clone()),
+            _ => None,
+        };
+        if let Some(before) = before {
+            let (end, value) = self.map.remove(&before).unwrap();
+            if end > range.end {
+                self.map.insert(range.end.clone(), (end, value.clone()));
+            }
+            self.map.insert(before, (range.start.clone(), value));
+        }
+
+        // Drops the ranges that start within `range`, keeping the part of the
+        // last one that reaches beyond it.
+        let starts: Vec<K> = self.map
+            .keys_range(Included(&range.start), Excluded(&range.end))
+            .cloned()
+            .collect();
+        for start in starts {
+            let (end, value) = self.map.remove(&start).unwrap();
+            if end > range.end {
+                self.map.insert(range.end.clone(), (end, value));
+            }
+        }
+    }
+}
+
+impl<K: Ord, V: PartialEq> PartialEq for RangeMap<K, V> {
+    fn eq(&self, other: &RangeMap<K, V>) -> bool {
+        self.map == other.map
+    }
+}
+
+impl<K: Ord, V: Eq> Eq for RangeMap<K, V> {}
+
+impl<K: Ord + Debug, V: Debug> Debug for RangeMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{")?;
+
+        for (i, (range, value)) in self.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{:?}..{:?}: {:?}", range.start, range.end, *value)?;
+        }
+
+        write!(f, "}}")
+    }
+}
+
+impl<K: Ord, V> Default for RangeMap<K, V> {
+    fn default() -> RangeMap<K, V> {
+        RangeMap::new()
+    }
+}
+
+impl<K, V> iter::FromIterator<(ops::Range<K>, V)> for RangeMap<K, V>
+    where K: Ord + Clone,
+          V: Eq + Clone
+{
+    fn from_iter<I: IntoIterator<Item = (ops::Range<K>, V)>>(iter: I) -> RangeMap<K, V> {
+        let mut map = RangeMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K, V> Extend<(ops::Range<K>, V)> for RangeMap<K, V>
+    where K: Ord + Clone,
+          V: Eq + Clone
+{
+    fn extend<I: IntoIterator<Item = (ops::Range<K>, V)>>(&mut self, iter: I) {
+        for (range, value) in iter {
+            self.insert(range, value);
+        }
+    }
+}
+
+impl<'a, K: Ord, V> IntoIterator for &'a RangeMap<K, V> {
+    type Item = (ops::Range<&'a K>, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+/// Lazy double-ended iterator over the ranges of a `RangeMap` and their
+/// values.
+pub struct Iter<'a, K: 'a, V: 'a>(map::Range<'a, K, (K, V), ()>);
+
+/// Lazy forward iterator over the parts of a range that a `RangeMap` does
+/// not cover.
+pub struct Gaps<'a, K: 'a, V: 'a> {
+    ranges: map::Range<'a, K, (K, V), ()>,
+    // The start of the next gap, or `None` once the last has been yielded.
+    cursor: Option<K>,
+    end: K,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (ops::Range<&'a K>, &'a V);
+
+    #[inline]
+    fn next(&mut self) -> Option<(ops::Range<&'a K>, &'a V)> {
+        self.0.next().map(|(start, (end, value))| (start..end, value))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for Iter<'a, K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<(ops::Range<&'a K>, &'a V)> {
+        self.0.next_back().map(|(start, (end, value))| (start..end, value))
+    }
+}
+
+impl<'a, K, V> FusedIterator for Iter<'a, K, V> {}
+
+impl<'a, K: Ord + Clone, V> Iterator for Gaps<'a, K, V> {
+    type Item = ops::Range<K>;
+
+    fn next(&mut self) -> Option<ops::Range<K>> {
+        let mut cursor = self.cursor.take()?;
+        for (start, (end, _)) in &mut self.ranges {
+            if *start > cursor {
+                self.cursor = Some(end.clone());
+                return Some(cursor..start.clone());
+            }
+            cursor = cmp::max(cursor, end.clone());
+        }
+        if cursor < self.end {
+            Some(cursor..self.end.clone())
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a, K: Ord + Clone, V> FusedIterator for Gaps<'a, K, V> {}
+
+#[cfg(test)]
+mod test {
+    use rand::{self, Rng};
+
+    use super::RangeMap;
+
+    #[test]
+    fn test_split_and_merge() {
+        let mut map = RangeMap::new();
+        map.insert(0..10, 'a');
+        map.insert(3..3, 'b');
+        map.insert(3..5, 'b');
+        assert_eq!(format!("{:?}", map), "{0..3: 'a', 3..5: 'b', 5..10: 'a'}");
+        map.insert(3..5, 'a');
+        assert_eq!(format!("{:?}", map), "{0..10: 'a'}");
+        map.insert(10..12, 'a');
+        map.insert(-2..0, 'a');
+        assert_eq!(format!("{:?}", map), "{-2..12: 'a'}");
+        map.insert(12..14, 'c');
+        map.remove(-5..0);
+        map.remove(11..13);
+        assert_eq!(format!("{:?}", map), "{0..11: 'a', 13..14: 'c'}");
+        map.insert(5..20, 'c');
+        assert_eq!(format!("{:?}", map), "{0..5: 'a', 5..20: 'c'}");
+        assert_eq!(map.get_range_value(&5), Some((&5..&20, &'c')));
+        assert!(map.contains(&0) && !map.contains(&20) && !map.contains(&-1));
+        assert!(map.iter().rev().map(|(_, v)| *v).eq("ca".chars()));
+    }
+
+    #[test]
+    fn test_gaps() {
+        let map: RangeMap<u32, ()> = vec![(2..4, ()), (6..8, ()), (8..9, ())].into_iter().collect();
+        assert_eq!(map.len(), 2);
+        assert!(map.gaps(0..20).eq(vec![0..2, 4..6, 9..20]));
+        assert!(map.gaps(3..7).eq(Some(4..6)));
+        assert!(map.gaps(6..9).eq(vec![]));
+        assert!(map.gaps(2..5).eq(Some(4..5)));
+        assert!(map.gaps(5..5).eq(vec![]));
+        assert!(RangeMap::<u32, ()>::new().gaps(1..3).eq(Some(1..3)));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // too slow
+    fn test_random() {
+        const N: usize = 64;
+        let mut rng = rand::weak_rng();
+        let mut map = RangeMap::new();
+        let mut model = [None; N];
+
+        for _ in 0..1000 {
+            let a = rng.gen_range(0, N + 1);
+            let b = rng.gen_range(0, N + 1);
+            let (lo, hi) = (a.min(b), a.max(b));
+            if rng.gen_weighted_bool(3) {
+                map.remove(lo..hi);
+                for slot in &mut model[lo..hi] {
+                    *slot = None;
+                }
+            } else {
+                let value = rng.gen_range(0, 3u8);
+                map.insert(lo..hi, value);
+                for slot in &mut model[lo..hi] {
+                    *slot = Some(value);
+                }
+            }
+
+            for (point, value) in model.iter().enumerate() {
+                assert_eq!(map.get(&point), value.as_ref());
+            }
+            let gaps: Vec<usize> = map.gaps(lo..hi).flatten().collect();
+            let model_gaps: Vec<usize> = (lo..hi).filter(|&p| model[p].is_none()).collect();
+            assert_eq!(gaps, model_gaps);
+
+            // The ranges are as few as possible: no two touching ones have
+            // equal values.
+            let ranges: Vec<_> = map.iter().collect();
+            for pair in ranges.windows(2) {
+                assert!(pair[0].0.end < pair[1].0.start || pair[0].1 != pair[1].1);
+            }
+        }
+    }
+}
@@ -0,0 +1,328 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A set of keys stored as disjoint half-open ranges.
+
+use core::cmp;
+use core::default::Default;
+use core::fmt::{self, Debug};
+use core::iter::{self, FusedIterator};
+use core::ops;
+
+use super::range_map::{self, Gaps, RangeMap};
+
+/// A set of keys stored as disjoint half-open ranges.
+///
+/// Ranges that overlap or touch are merged as they are inserted, so the set
+/// always holds as few ranges as possible. It is a `RangeMap` whose ranges
+/// carry no values.
+///
+/// # Examples
+///
+/// ```rust
+/// use stable_bst::RangeSet;
+///
+/// let mut received = RangeSet::new();
+/// received.insert_range(0..100);
+/// received.insert_range(200..300);
+/// received.insert_range(100..150);
+///
+/// assert!(received.iter().eq(vec![&0..&150, &200..&300]));
+/// assert!(received.covers(20..120));
+/// assert!(received.gaps(0..400).eq(vec![150..200, 300..400]));
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub struct RangeSet<K: Ord> {
+    map: RangeMap<K, ()>,
+}
+
+impl<K: Ord> RangeSet<K> {
+    /// Creates an empty `RangeSet`.
+    pub fn new() -> RangeSet<K> {
+        RangeSet { map: RangeMap::new() }
+    }
+
+    /// Returns the number of disjoint ranges in the set.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns true if the set contains no keys.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Clears the set, removing all ranges.
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+
+    /// Returns true if the set contains `point`.
+    pub fn contains(&self, point: &K) -> bool {
+        self.map.contains(point)
+    }
+
+    /// Returns true if the set contains every key in `range`. An empty
+    /// `range` is always covered.
+    pub fn covers(&self, range: ops::Range<K>) -> bool {
+        if range.start >= range.end {
+            return true;
+        }
+        match self.map.get_range_value(&range.start) {
+            Some((covering, _)) => *covering.end >= range.end,
+            None => false,
+        }
+    }
+
+    /// Returns an iterator over the ranges of the set, in ascending order.
+    pub fn iter(&self) -> Iter<'_, K> {
+        Iter(self.map.iter())
+    }
+
+    /// Returns an iterator over the parts of `range` that the set does not
+    /// contain, in ascending order.
+    pub fn gaps(&self, range: ops::Range<K>) -> Gaps<'_, K, ()>
+        where K: Clone
+    {
+        self.map.gaps(range)
+    }
+}
+
+impl<K: Ord + Clone> RangeSet<K> {
+    /// Adds every key in `range` to the set. Does nothing if `range` is
+    /// empty.
+    pub fn insert_range(&mut self, range: ops::Range<K>) {
+        self.map.insert(range, ());
+    }
+
+    /// Removes every key in `range` from the set, splitting the ranges that
+    /// extend beyond it.
+    pub fn remove_range(&mut self, range: ops::Range<K>) {
+        self.map.remove(range);
+    }
+
+    /// Returns the set of the keys in `bounds` that this set does not
+    /// contain.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::RangeSet;
+    ///
+    /// let set: RangeSet<u32> = vec![2..4, 6..8].into_iter().collect();
+    /// assert!(set.complement(0..7).iter().eq(vec![&0..&2, &4..&6]));
+    /// ```
+    pub fn complement(&self, bounds: ops::Range<K>) -> RangeSet<K> {
+        self.gaps(bounds).collect()
+    }
+
+    /// Returns the set of the keys in either set.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::RangeSet;
+    ///
+    /// let a: RangeSet<u32> = vec![0..4, 8..12].into_iter().collect();
+    /// let b: RangeSet<u32> = vec![2..6, 10..11].into_iter().collect();
+    /// assert!(a.union(&b).iter().eq(vec![&0..&6, &8..&12]));
+    /// assert!(a.intersection(&b).iter().eq(vec![&2..&4, &10..&11]));
+    /// assert!(a.difference(&b).iter().eq(vec![&0..&2, &8..&10, &11..&12]));
+    /// ```
+    pub fn union(&self, other: &RangeSet<K>) -> RangeSet<K> {
+        let (mut union, smaller) = if self.len() >= other.len() {
+            (self.clone(), other)
+        } else {
+            (other.clone(), self)
+        };
+        union.extend(smaller.iter().map(|r| r.start.clone()..r.end.clone()));
+        union
+    }
+
+    /// Returns the set of the keys in both sets.
+    pub fn intersection(&self, other: &RangeSet<K>) -> RangeSet<K> {
+        let mut intersection = RangeSet::new();
+        let (mut a, mut b) = (self.iter(), other.iter());
+        let (mut x, mut y) = (a.next(), b.next());
+        while let (Some(r), Some(s)) = (x.clone(), y.clone()) {
+            let start = cmp::max(r.start, s.start);
+            let end = cmp::min(r.end, s.end);
+            if start < end {
+                intersection.insert_range(start.clone()..end.clone());
+            }
+            if r.end < s.end {
+                x = a.next();
+            } else {
+                y = b.next();
+            }
+        }
+        intersection
+    }
+
+    /// Returns the set of the keys in this set but not in `other`.
+    pub fn difference(&self, other: &RangeSet<K>) -> RangeSet<K> {
+        match (self.iter().next(), self.iter().next_back()) {
+            (Some(first), Some(last)) => {
+                self.intersection(&other.complement(first.start.clone()..last.end.clone()))
+            }
+            _ => RangeSet::new(),
+        }
+    }
+}
+
+impl<K: Ord + Debug> Debug for RangeSet<K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{")?;
+
+        for (i, range) in self.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{:?}..{:?}", range.start, range.end)?;
+        }
+
+        write!(f, "}}")
+    }
+}
+
+impl<K: Ord> Default for RangeSet<K> {
+    fn default() -> RangeSet<K> {
+        RangeSet::new()
+    }
+}
+
+impl<K: Ord + Clone> iter::FromIterator<ops::Range<K>> for RangeSet<K> {
+    fn from_iter<I: IntoIterator<Item = ops::Range<K>>>(iter: I) -> RangeSet<K> {
+        let mut set = RangeSet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl<K: Ord + Clone> Extend<ops::Range<K>> for RangeSet<K> {
+    fn extend<I: IntoIterator<Item = ops::Range<K>>>(&mut self, iter: I) {
+        for range in iter {
+            self.insert_range(range);
+        }
+    }
+}
+
+impl<'a, K: Ord> IntoIterator for &'a RangeSet<K> {
+    type Item = ops::Range<&'a K>;
+    type IntoIter = Iter<'a, K>;
+    fn into_iter(self) -> Iter<'a, K> {
+        self.iter()
+    }
+}
+
+/// Lazy double-ended iterator over the ranges of a `RangeSet`.
+pub struct Iter<'a, K: 'a>(range_map::Iter<'a, K, ()>);
+
+impl<'a, K> Iterator for Iter<'a, K> {
+    type Item = ops::Range<&'a K>;
+
+    #[inline]
+    fn next(&mut self) -> Option<ops::Range<&'a K>> {
+        self.0.next().map(|(range, _)| range)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K> DoubleEndedIterator for Iter<'a, K> {
+    #[inline]
+    fn next_back(&mut self) -> Option<ops::Range<&'a K>> {
+        self.0.next_back().map(|(range, _)| range)
+    }
+}
+
+impl<'a, K> FusedIterator for Iter<'a, K> {}
+
+#[cfg(test)]
+mod test {
+    use rand::{self, Rng};
+
+    use super::RangeSet;
+
+    #[test]
+    fn test_merging() {
+        let mut set = RangeSet::new();
+        set.insert_range(5..8);
+        set.insert_range(0..2);
+        set.insert_range(2..3);
+        set.insert_range(7..10);
+        assert_eq!(format!("{:?}", set), "{0..3, 5..10}");
+        set.insert_range(3..5);
+        assert_eq!(format!("{:?}", set), "{0..10}");
+        set.remove_range(4..6);
+        assert_eq!(format!("{:?}", set), "{0..4, 6..10}");
+        assert!(set.contains(&0) && !set.contains(&4) && !set.contains(&10));
+        assert!(set.covers(6..10) && set.covers(5..5) && !set.covers(3..7) && !set.covers(9..11));
+        assert!(set.gaps(0..12).eq(vec![4..6, 10..12]));
+        assert!(set.complement(2..8).iter().eq(Some(&4..&6)));
+        set.clear();
+        assert!(set.is_empty() && set.difference(&set).is_empty());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // too slow
+    fn test_random_algebra() {
+        const N: usize = 48;
+        let mut rng = rand::weak_rng();
+
+        for _ in 0..200 {
+            let mut sets = vec![];
+            let mut models = vec![];
+            for _ in 0..2 {
+                let mut set = RangeSet::new();
+                let mut model = [false; N];
+                for _ in 0..rng.gen_range(0, 6) {
+                    let a = rng.gen_range(0, N + 1);
+                    let b = rng.gen_range(0, N + 1);
+                    let (lo, hi) = (a.min(b), a.max(b));
+                    let insert = rng.gen();
+                    if insert {
+                        set.insert_range(lo..hi);
+                    } else {
+                        set.remove_range(lo..hi);
+                    }
+                    for slot in &mut model[lo..hi] {
+                        *slot = insert;
+                    }
+                }
+                sets.push(set);
+                models.push(model);
+            }
+
+            let (a, b) = (&sets[0], &sets[1]);
+            let (x, y) = (&models[0], &models[1]);
+            let check = |set: RangeSet<usize>, f: &dyn Fn(usize) -> bool| {
+                for point in 0..N {
+                    assert_eq!(set.contains(&point), f(point));
+                }
+                let ranges: Vec<_> = set.iter().collect();
+                for pair in ranges.windows(2) {
+                    assert!(pair[0].end < pair[1].start);
+                }
+            };
+            check(a.union(b), &|p| x[p] || y[p]);
+            check(a.intersection(b), &|p| x[p] && y[p]);
+            check(a.difference(b), &|p| x[p] && !y[p]);
+            check(a.complement(4..N - 4), &|p| !x[p] && (4..N - 4).contains(&p));
+
+            let lo = rng.gen_range(0, N);
+            let hi = rng.gen_range(lo, N + 1);
+            assert_eq!(a.covers(lo..hi), (lo..hi).all(|p| x[p]));
+        }
+    }
+}
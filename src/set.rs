@@ -8,16 +8,25 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::cmp::Ordering::{self, Less, Equal, Greater};
-use std::default::Default;
-use std::fmt::{self, Debug};
-use std::iter::{self, Peekable, IntoIterator};
-use std::hash::{Hash, Hasher};
-use std::ops;
+use core::cmp::Ordering::{self, Less, Equal, Greater};
+use core::default::Default;
+use core::fmt::{self, Debug};
+use core::cmp;
+use core::iter::{self, FusedIterator, Peekable, IntoIterator};
+use core::hash::{Hash, Hasher};
+use core::ops::{self, RangeBounds};
+use alloc::collections::BTreeSet;
+use alloc::vec::{self, Vec};
 
 use compare::{Compare, Natural, natural};
 use super::map::{self, Forward, TreeMap};
-use super::Bound;
+use super::{Bound, HashCompare};
+
+#[cfg(feature = "rayon")]
+pub use self::par::{IntoParIter, ParIter, ParRange};
+
+#[cfg(feature = "rayon")]
+mod par;
 
 // FIXME(conventions): implement bounded iterators
 // FIXME(conventions): replace rev_iter(_mut) by making iter(_mut) DoubleEnded
@@ -79,36 +88,41 @@ pub struct TreeSet<T, C: Compare<T> = Natural<T>> {
     map: TreeMap<T, (), C>,
 }
 
-// FIXME: determine what `PartialEq` means for comparator-based `TreeSet`s
-impl<T: PartialEq + Ord> PartialEq for TreeSet<T> {
+// Sets are compared element by element with `self`'s comparator, like
+// `TreeMap`s.
+impl<T, C> PartialEq for TreeSet<T, C>
+    where C: Compare<T>
+{
     #[inline]
-    fn eq(&self, other: &TreeSet<T>) -> bool {
+    fn eq(&self, other: &TreeSet<T, C>) -> bool {
         self.map == other.map
     }
 }
 
-// FIXME: determine what `Eq` means for comparator-based `TreeSet`s
-impl<T: Eq + Ord> Eq for TreeSet<T> {}
+impl<T, C> Eq for TreeSet<T, C> where C: Compare<T> {}
 
-// FIXME: determine what `PartialOrd` means for comparator-based `TreeSet`s
-impl<T: Ord> PartialOrd for TreeSet<T> {
+impl<T, C> PartialOrd for TreeSet<T, C>
+    where C: Compare<T>
+{
     #[inline]
-    fn partial_cmp(&self, other: &TreeSet<T>) -> Option<Ordering> {
-        self.map.partial_cmp(&other.map)
+    fn partial_cmp(&self, other: &TreeSet<T, C>) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
-// FIXME: determine what `Ord` means for comparator-based `TreeSet`s
-impl<T: Ord> Ord for TreeSet<T> {
+impl<T, C> Ord for TreeSet<T, C>
+    where C: Compare<T>
+{
     #[inline]
-    fn cmp(&self, other: &TreeSet<T>) -> Ordering {
-        self.iter().cmp(other)
+    fn cmp(&self, other: &TreeSet<T, C>) -> Ordering {
+        self.map.cmp(&other.map)
     }
 }
 
 impl<T: Debug, C> Debug for TreeSet<T, C>
     where C: Compare<T>
 {
+    #[allow(deprecated)]
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         try!(write!(f, "{{"));
 
@@ -174,8 +188,8 @@ impl<T, C> TreeSet<T, C>
     /// }
     /// ```
     #[inline]
-    pub fn iter(&self) -> Iter<T> {
-        Iter { iter: self.map.iter() }
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { iter: self.map.iter().without_comparator() }
     }
 
     /// Creates a consuming iterator, that is, one that moves each value out of the
@@ -192,6 +206,7 @@ impl<T, C> TreeSet<T, C>
     /// assert_eq!(v, vec![1, 2, 3, 4, 5]);
     /// ```
     #[inline]
+    #[allow(clippy::should_implement_trait)]
     pub fn into_iter(self) -> IntoIter<T> {
         fn first<A, B>((a, _): (A, B)) -> A {
             a
@@ -224,14 +239,38 @@ impl<T, C> TreeSet<T, C>
     pub fn range<'a, Min: ?Sized, Max: ?Sized>(&'a self,
                                                min: Bound<&Min>,
                                                max: Bound<&Max>)
-                                               -> Range<'a, T>
+                                               -> Range<'a, T, C>
         where C: Compare<Min, T> + Compare<Max, T>
     {
         Range { range: self.map.range(min, max) }
     }
 
+    /// Like `range`, but takes the bounds as a standard range expression
+    /// such as `a..b`, `a..=b` or `..b`, or as any other `RangeBounds`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use stable_bst::TreeSet;
+    ///
+    /// let set: TreeSet<i32> = vec![3, 5, 8].into_iter().collect();
+    /// assert!(set.range_by(4..).cloned().eq(vec![5, 8]));
+    /// assert!(set.range_by(..=5).rev().cloned().eq(vec![5, 3]));
+    /// ```
+    pub fn range_by<Q: ?Sized, R>(&self, range: R) -> Range<'_, T, C>
+        where C: Compare<Q, T>,
+              R: RangeBounds<Q>
+    {
+        Range { range: self.map.range_by(range) }
+    }
+
     /// Visits the values representing the difference, in ascending order.
     ///
+    /// If `other`'s comparator is not compatible with this set's, `other` is
+    /// sorted first. Either way `C` must implement `CompatibleWith<D>`, so a
+    /// set ordered by `Natural<T>` cannot be combined with one ordered by a
+    /// closure, for example. See `CompatibleWith`.
+    ///
     /// # Examples
     ///
     /// ```rust
@@ -246,26 +285,42 @@ impl<T, C> TreeSet<T, C>
     /// }
     ///
     /// let diff: TreeSet<i32> = a.difference(&b).map(|&x| x).collect();
-    /// assert_eq!(diff, [1, 2].iter().map(|&x| x).collect());
+    /// assert_eq!(diff, [1, 2].iter().map(|&x| x).collect::<TreeSet<i32>>());
     ///
     /// // Note that difference is not symmetric,
     /// // and `b - a` means something else:
     /// let diff: TreeSet<i32> = b.difference(&a).map(|&x| x).collect();
-    /// assert_eq!(diff, [4, 5].iter().map(|&x| x).collect());
+    /// assert_eq!(diff, [4, 5].iter().map(|&x| x).collect::<TreeSet<i32>>());
     /// ```
-    pub fn difference<'a>(&'a self, other: &'a TreeSet<T, C>) -> Difference<'a, T, C>
-        where C: Eq
+    pub fn difference<'a, D>(&'a self, other: &'a TreeSet<T, D>) -> Difference<'a, T, C>
+        where C: CompatibleWith<D>,
+              D: Compare<T>
     {
-        assert!(self.comparator() == other.comparator());
         Difference {
             a: self.iter().peekable(),
-            b: other.iter().peekable(),
+            b: self.merge_source(other).peekable(),
             cmp: self.comparator(),
         }
     }
 
+    /// Like `difference`, but fails if the comparators do not agree instead of
+    /// sorting `other`.
+    pub fn try_difference<'a, D>(&'a self,
+                                 other: &'a TreeSet<T, D>)
+                                 -> Result<Difference<'a, T, C>, ComparatorMismatch>
+        where C: CompatibleWith<D>,
+              D: Compare<T>
+    {
+        self.check_compatible(other).map(|()| self.difference(other))
+    }
+
     /// Visits the values representing the symmetric difference, in ascending order.
     ///
+    /// If `other`'s comparator is not compatible with this set's, `other` is
+    /// sorted first. Either way `C` must implement `CompatibleWith<D>`, so a
+    /// set ordered by `Natural<T>` cannot be combined with one ordered by a
+    /// closure, for example. See `CompatibleWith`.
+    ///
     /// # Examples
     ///
     /// ```rust
@@ -283,23 +338,39 @@ impl<T, C> TreeSet<T, C>
     /// let diff2: TreeSet<i32> = b.symmetric_difference(&a).map(|&x| x).collect();
     ///
     /// assert_eq!(diff1, diff2);
-    /// assert_eq!(diff1, [1, 2, 4, 5].iter().map(|&x| x).collect());
+    /// assert_eq!(diff1, [1, 2, 4, 5].iter().map(|&x| x).collect::<TreeSet<i32>>());
     /// ```
-    pub fn symmetric_difference<'a>(&'a self,
-                                    other: &'a TreeSet<T, C>)
-                                    -> SymmetricDifference<'a, T, C>
-        where C: Eq
+    pub fn symmetric_difference<'a, D>(&'a self,
+                                       other: &'a TreeSet<T, D>)
+                                       -> SymmetricDifference<'a, T, C>
+        where C: CompatibleWith<D>,
+              D: Compare<T>
     {
-        assert!(self.comparator() == other.comparator());
         SymmetricDifference {
             a: self.iter().peekable(),
-            b: other.iter().peekable(),
+            b: self.merge_source(other).peekable(),
             cmp: self.comparator(),
         }
     }
 
+    /// Like `symmetric_difference`, but fails if the comparators do not agree
+    /// instead of sorting `other`.
+    pub fn try_symmetric_difference<'a, D>(&'a self,
+                                           other: &'a TreeSet<T, D>)
+                                           -> Result<SymmetricDifference<'a, T, C>, ComparatorMismatch>
+        where C: CompatibleWith<D>,
+              D: Compare<T>
+    {
+        self.check_compatible(other).map(|()| self.symmetric_difference(other))
+    }
+
     /// Visits the values representing the intersection, in ascending order.
     ///
+    /// If `other`'s comparator is not compatible with this set's, `other` is
+    /// sorted first. Either way `C` must implement `CompatibleWith<D>`, so a
+    /// set ordered by `Natural<T>` cannot be combined with one ordered by a
+    /// closure, for example. See `CompatibleWith`.
+    ///
     /// # Examples
     ///
     /// ```rust
@@ -314,21 +385,37 @@ impl<T, C> TreeSet<T, C>
     /// }
     ///
     /// let diff: TreeSet<i32> = a.intersection(&b).map(|&x| x).collect();
-    /// assert_eq!(diff, [2, 3].iter().map(|&x| x).collect());
+    /// assert_eq!(diff, [2, 3].iter().map(|&x| x).collect::<TreeSet<i32>>());
     /// ```
-    pub fn intersection<'a>(&'a self, other: &'a TreeSet<T, C>) -> Intersection<'a, T, C>
-        where C: Eq
+    pub fn intersection<'a, D>(&'a self, other: &'a TreeSet<T, D>) -> Intersection<'a, T, C>
+        where C: CompatibleWith<D>,
+              D: Compare<T>
     {
-        assert!(self.comparator() == other.comparator());
         Intersection {
             a: self.iter().peekable(),
-            b: other.iter().peekable(),
+            b: self.merge_source(other).peekable(),
             cmp: self.comparator(),
         }
     }
 
+    /// Like `intersection`, but fails if the comparators do not agree instead of
+    /// sorting `other`.
+    pub fn try_intersection<'a, D>(&'a self,
+                                   other: &'a TreeSet<T, D>)
+                                   -> Result<Intersection<'a, T, C>, ComparatorMismatch>
+        where C: CompatibleWith<D>,
+              D: Compare<T>
+    {
+        self.check_compatible(other).map(|()| self.intersection(other))
+    }
+
     /// Visits the values representing the union, in ascending order.
     ///
+    /// If `other`'s comparator is not compatible with this set's, `other` is
+    /// sorted first. Either way `C` must implement `CompatibleWith<D>`, so a
+    /// set ordered by `Natural<T>` cannot be combined with one ordered by a
+    /// closure, for example. See `CompatibleWith`.
+    ///
     /// # Examples
     ///
     /// ```rust
@@ -343,19 +430,30 @@ impl<T, C> TreeSet<T, C>
     /// }
     ///
     /// let diff: TreeSet<i32> = a.union(&b).map(|&x| x).collect();
-    /// assert_eq!(diff, [1, 2, 3, 4, 5].iter().map(|&x| x).collect());
+    /// assert_eq!(diff, [1, 2, 3, 4, 5].iter().map(|&x| x).collect::<TreeSet<i32>>());
     /// ```
-    pub fn union<'a>(&'a self, other: &'a TreeSet<T, C>) -> Union<'a, T, C>
-        where C: Eq
+    pub fn union<'a, D>(&'a self, other: &'a TreeSet<T, D>) -> Union<'a, T, C>
+        where C: CompatibleWith<D>,
+              D: Compare<T>
     {
-        assert!(self.comparator() == other.comparator());
         Union {
             a: self.iter().peekable(),
-            b: other.iter().peekable(),
+            b: self.merge_source(other).peekable(),
             cmp: self.comparator(),
         }
     }
 
+    /// Like `union`, but fails if the comparators do not agree instead of
+    /// sorting `other`.
+    pub fn try_union<'a, D>(&'a self,
+                            other: &'a TreeSet<T, D>)
+                            -> Result<Union<'a, T, C>, ComparatorMismatch>
+        where C: CompatibleWith<D>,
+              D: Compare<T>
+    {
+        self.check_compatible(other).map(|()| self.union(other))
+    }
+
     /// Return the number of elements in the set
     ///
     /// # Examples
@@ -431,6 +529,8 @@ impl<T, C> TreeSet<T, C>
     /// Returns `true` if the set has no elements in common with `other`.
     /// This is equivalent to checking for an empty intersection.
     ///
+    /// As with `intersection`, `C` must implement `CompatibleWith<D>`.
+    ///
     /// # Examples
     ///
     /// ```rust
@@ -445,14 +545,27 @@ impl<T, C> TreeSet<T, C>
     /// b.insert(1);
     /// assert_eq!(a.is_disjoint(&b), false);
     /// ```
-    pub fn is_disjoint(&self, other: &TreeSet<T, C>) -> bool
-        where C: Eq
+    pub fn is_disjoint<D>(&self, other: &TreeSet<T, D>) -> bool
+        where C: CompatibleWith<D>,
+              D: Compare<T>
     {
         self.intersection(other).next().is_none()
     }
 
+    /// Like `is_disjoint`, but fails if the comparators do not agree instead
+    /// of sorting `other`.
+    pub fn try_is_disjoint<D>(&self, other: &TreeSet<T, D>) -> Result<bool, ComparatorMismatch>
+        where C: CompatibleWith<D>,
+              D: Compare<T>
+    {
+        self.check_compatible(other).map(|()| self.is_disjoint(other))
+    }
+
     /// Returns `true` if the set is a subset of another.
     ///
+    /// `other` is sorted first if its comparator is not compatible with this
+    /// set's. Either way `C` must implement `CompatibleWith<D>`.
+    ///
     /// # Examples
     ///
     /// ```rust
@@ -467,12 +580,12 @@ impl<T, C> TreeSet<T, C>
     /// set.insert(4);
     /// assert_eq!(set.is_subset(&sup), false);
     /// ```
-    pub fn is_subset(&self, other: &TreeSet<T, C>) -> bool
-        where C: Eq
+    pub fn is_subset<D>(&self, other: &TreeSet<T, D>) -> bool
+        where C: CompatibleWith<D>,
+              D: Compare<T>
     {
-        assert!(self.comparator() == other.comparator());
         let mut x = self.iter();
-        let mut y = other.iter();
+        let mut y = self.merge_source(other);
         let mut a = x.next();
         let mut b = y.next();
         while a.is_some() {
@@ -496,6 +609,8 @@ impl<T, C> TreeSet<T, C>
 
     /// Returns `true` if the set is a superset of another.
     ///
+    /// As with `is_subset`, `C` must implement `CompatibleWith<D>`.
+    ///
     /// # Examples
     ///
     /// ```rust
@@ -513,10 +628,78 @@ impl<T, C> TreeSet<T, C>
     /// set.insert(2);
     /// assert_eq!(set.is_superset(&sub), true);
     /// ```
-    pub fn is_superset(&self, other: &TreeSet<T, C>) -> bool
-        where C: Eq
+    pub fn is_superset<D>(&self, other: &TreeSet<T, D>) -> bool
+        where C: CompatibleWith<D>,
+              D: Compare<T>
     {
-        other.is_subset(self)
+        let mut x = self.merge_source(other);
+        let mut y = self.iter();
+        let mut a = x.next();
+        let mut b = y.next();
+        while a.is_some() {
+            if b.is_none() {
+                return false;
+            }
+
+            let a1 = a.unwrap();
+            let b1 = b.unwrap();
+
+            match self.comparator().compare(b1, a1) {
+                Less => (),
+                Greater => return false,
+                Equal => a = x.next(),
+            }
+
+            b = y.next();
+        }
+        true
+    }
+
+    /// Like `is_subset`, but fails if the comparators do not agree instead of
+    /// sorting `other`.
+    pub fn try_is_subset<D>(&self, other: &TreeSet<T, D>) -> Result<bool, ComparatorMismatch>
+        where C: CompatibleWith<D>,
+              D: Compare<T>
+    {
+        self.check_compatible(other).map(|()| self.is_subset(other))
+    }
+
+    /// Like `is_superset`, but fails if the comparators do not agree instead
+    /// of sorting `other`.
+    pub fn try_is_superset<D>(&self, other: &TreeSet<T, D>) -> Result<bool, ComparatorMismatch>
+        where C: CompatibleWith<D>,
+              D: Compare<T>
+    {
+        self.check_compatible(other).map(|()| self.is_superset(other))
+    }
+
+    fn check_compatible<D>(&self, other: &TreeSet<T, D>) -> Result<(), ComparatorMismatch>
+        where C: CompatibleWith<D>,
+              D: Compare<T>
+    {
+        if self.comparator().compatible_with(other.comparator()) {
+            Ok(())
+        } else {
+            Err(ComparatorMismatch)
+        }
+    }
+
+    // Iterates over `other` in the order of `self`'s comparator: directly if
+    // the comparators agree, and otherwise by sorting its elements, dropping
+    // any that `self`'s comparator considers duplicates.
+    fn merge_source<'a, D>(&'a self, other: &'a TreeSet<T, D>) -> MergeSource<'a, T>
+        where C: CompatibleWith<D>,
+              D: Compare<T>
+    {
+        if self.comparator().compatible_with(other.comparator()) {
+            MergeSource::Tree(other.iter())
+        } else {
+            let cmp = self.comparator();
+            let mut sorted: Vec<&T> = other.iter().collect();
+            sorted.sort_by(|a, b| cmp.compare(a, b));
+            sorted.dedup_by(|a, b| cmp.compares_eq(a, b));
+            MergeSource::Sorted(sorted.into_iter())
+        }
     }
 
     /// Adds a value to the set. Returns `true` if the value was not already
@@ -566,44 +749,147 @@ impl<T, C> TreeSet<T, C>
 
 /// A lazy forward iterator over a set.
 pub struct Iter<'a, T: 'a> {
-    iter: map::Iter<'a, T, (), Forward>,
+    iter: map::Iter<'a, T, (), Forward, ()>,
 }
 
-pub struct Range<'a, T: 'a> {
-    range: map::Range<'a, T, ()>,
+/// A lazy double-ended iterator over a sub-range of a set.
+pub struct Range<'a, T: 'a, C: 'a = Natural<T>> {
+    range: map::Range<'a, T, (), C>,
 }
 
 /// A lazy forward iterator over a set that consumes the set while iterating.
+#[allow(clippy::type_complexity)]
 pub struct IntoIter<T>(iter::Map<map::IntoIter<T, ()>, fn((T, ())) -> T>);
 
 /// A lazy iterator producing elements in the set difference (in-order).
 pub struct Difference<'a, T: 'a, C: 'a> {
     a: Peekable<Iter<'a, T>>,
-    b: Peekable<Iter<'a, T>>,
+    b: Peekable<MergeSource<'a, T>>,
     cmp: &'a C,
 }
 
 /// A lazy iterator producing elements in the set symmetric difference (in-order).
 pub struct SymmetricDifference<'a, T: 'a, C: 'a> {
     a: Peekable<Iter<'a, T>>,
-    b: Peekable<Iter<'a, T>>,
+    b: Peekable<MergeSource<'a, T>>,
     cmp: &'a C,
 }
 
 /// A lazy iterator producing elements in the set intersection (in-order).
 pub struct Intersection<'a, T: 'a, C: 'a> {
     a: Peekable<Iter<'a, T>>,
-    b: Peekable<Iter<'a, T>>,
+    b: Peekable<MergeSource<'a, T>>,
     cmp: &'a C,
 }
 
 /// A lazy iterator producing elements in the set union (in-order).
 pub struct Union<'a, T: 'a, C: 'a> {
     a: Peekable<Iter<'a, T>>,
-    b: Peekable<Iter<'a, T>>,
+    b: Peekable<MergeSource<'a, T>>,
     cmp: &'a C,
 }
 
+// The second operand of a set operation, in the order of the first operand's
+// comparator.
+enum MergeSource<'a, T: 'a> {
+    Tree(Iter<'a, T>),
+    Sorted(vec::IntoIter<&'a T>),
+}
+
+impl<'a, T> Iterator for MergeSource<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        match *self {
+            MergeSource::Tree(ref mut iter) => iter.next(),
+            MergeSource::Sorted(ref mut iter) => iter.next(),
+        }
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match *self {
+            MergeSource::Tree(ref iter) => iter.size_hint(),
+            MergeSource::Sorted(ref iter) => iter.size_hint(),
+        }
+    }
+}
+
+impl<'a, T> ExactSizeIterator for MergeSource<'a, T> {}
+
+/// A comparator that orders values of type `T` the same way as comparators of
+/// type `D`, at least for some values of the two types.
+///
+/// Set operations such as `TreeSet::union` walk both sets in step when their
+/// comparators are compatible. Otherwise they sort the elements of the second
+/// set by the first set's comparator, which takes O(n log n) time and O(n)
+/// space, and the `try_` variants of the operations fail instead.
+///
+/// Comparators of the same type are compatible when they are equal.
+///
+/// The set operations need an impl even when they would end up sorting, so
+/// sets whose comparator types have none between them cannot be combined.
+/// That is the case for comparators that are not `Eq`, and for closures,
+/// which cannot be named in an impl. To combine such sets, wrap the closure
+/// in a comparator type that implements this trait, or first collect one
+/// set into a set ordered by the other's comparator.
+///
+/// # Examples
+///
+/// ```rust
+/// extern crate compare;
+/// extern crate stable_bst;
+///
+/// use std::cmp::Ordering;
+/// use compare::{Compare, Natural};
+/// use stable_bst::TreeSet;
+/// use stable_bst::set::CompatibleWith;
+///
+/// struct Ascending;
+///
+/// impl Compare<i32> for Ascending {
+///     fn compare(&self, l: &i32, r: &i32) -> Ordering {
+///         l.cmp(r)
+///     }
+/// }
+///
+/// impl CompatibleWith<Natural<i32>> for Ascending {
+///     fn compatible_with(&self, _: &Natural<i32>) -> bool {
+///         true
+///     }
+/// }
+///
+/// # fn main() {
+/// let mut a = TreeSet::with_comparator(Ascending);
+/// a.extend(vec![1, 2, 3]);
+/// let b: TreeSet<i32> = vec![2, 3, 4].into_iter().collect();
+///
+/// let union = a.try_union(&b).unwrap();
+/// assert!(union.cloned().eq(vec![1, 2, 3, 4]));
+/// # }
+/// ```
+pub trait CompatibleWith<D: ?Sized> {
+    /// Returns `true` if `self` orders values the same way as `other`.
+    fn compatible_with(&self, other: &D) -> bool;
+}
+
+impl<C: Eq> CompatibleWith<C> for C {
+    fn compatible_with(&self, other: &C) -> bool {
+        self == other
+    }
+}
+
+/// The error returned by the `try_` set operations when the two sets'
+/// comparators do not agree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ComparatorMismatch;
+
+impl fmt::Display for ComparatorMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the sets are ordered by incompatible comparators")
+    }
+}
+
+#[cfg(feature = "std")]
+impl ::std::error::Error for ComparatorMismatch {}
+
 /// Compare `x` and `y`, but return `short` if x is None and `long` if y is None
 fn cmp_opt<T, C: Compare<T>>(x: Option<&&T>,
                              y: Option<&&T>,
@@ -630,21 +916,71 @@ impl<'a, T> Iterator for Iter<'a, T> {
     }
 }
 
-impl<'a, T> Iterator for Range<'a, T> {
+impl<'a, T> ExactSizeIterator for Iter<'a, T> {}
+impl<'a, T> FusedIterator for Iter<'a, T> {}
+
+impl<'a, T, C> Range<'a, T, C> {
+    /// Returns the value that `next` would return, without advancing the
+    /// iterator.
+    pub fn peek(&mut self) -> Option<&'a T> {
+        self.range.peek_key()
+    }
+
+    /// Skips ahead to the first value that is not less than `value` under
+    /// the set's comparator. See `map::Range::seek`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::TreeSet;
+    ///
+    /// let set: TreeSet<i32> = (0..10).collect();
+    /// let mut range = set.range_by(2..);
+    /// range.seek(&7);
+    /// assert!(range.eq(vec![&7, &8, &9]));
+    /// ```
+    pub fn seek<Q: ?Sized>(&mut self, value: &Q)
+        where C: Compare<Q, T>
+    {
+        self.range.seek(value)
+    }
+
+    /// Like `seek`, but `f` compares the target with the value it is given,
+    /// as in `TreeMap::find_with`.
+    pub fn seek_with<F>(&mut self, f: F)
+        where F: FnMut(&T) -> Ordering
+    {
+        self.range.seek_with(f)
+    }
+
+    // Forgets the comparator, for the iterators that never seek.
+    pub(crate) fn without_comparator(self) -> Range<'a, T, ()> {
+        Range { range: self.range.without_comparator() }
+    }
+}
+
+impl<'a, T, C> Iterator for Range<'a, T, C> {
     type Item = &'a T;
     #[inline]
     fn next(&mut self) -> Option<&'a T> {
         self.range.next().map(|(value, _)| value)
     }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
 }
 
-impl<'a, T> DoubleEndedIterator for Range<'a, T> {
+impl<'a, T, C> DoubleEndedIterator for Range<'a, T, C> {
     #[inline]
     fn next_back(&mut self) -> Option<&'a T> {
         self.range.next_back().map(|(value, _)| value)
     }
 }
 
+impl<'a, T, C> ExactSizeIterator for Range<'a, T, C> {}
+impl<'a, T, C> FusedIterator for Range<'a, T, C> {}
+
 impl<T> Iterator for IntoIter<T> {
     type Item = T;
     #[inline]
@@ -657,6 +993,9 @@ impl<T> Iterator for IntoIter<T> {
     }
 }
 
+impl<T> ExactSizeIterator for IntoIter<T> {}
+impl<T> FusedIterator for IntoIter<T> {}
+
 impl<'a, T, C> Iterator for Difference<'a, T, C>
     where C: Compare<T>
 {
@@ -675,8 +1014,15 @@ impl<'a, T, C> Iterator for Difference<'a, T, C>
             }
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (a, b) = (self.a.len(), self.b.len());
+        (a.saturating_sub(b), Some(a))
+    }
 }
 
+impl<'a, T, C: Compare<T>> FusedIterator for Difference<'a, T, C> {}
+
 impl<'a, T, C> Iterator for SymmetricDifference<'a, T, C>
     where C: Compare<T>
 {
@@ -693,8 +1039,15 @@ impl<'a, T, C> Iterator for SymmetricDifference<'a, T, C>
             }
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (a, b) = (self.a.len(), self.b.len());
+        (cmp::max(a, b) - cmp::min(a, b), a.checked_add(b))
+    }
 }
 
+impl<'a, T, C: Compare<T>> FusedIterator for SymmetricDifference<'a, T, C> {}
+
 impl<'a, T, C> Iterator for Intersection<'a, T, C>
     where C: Compare<T>
 {
@@ -721,12 +1074,19 @@ impl<'a, T, C> Iterator for Intersection<'a, T, C>
             }
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(cmp::min(self.a.len(), self.b.len())))
+    }
 }
 
+impl<'a, T, C: Compare<T>> FusedIterator for Intersection<'a, T, C> {}
+
 impl<'a, T, C> Iterator for Union<'a, T, C>
     where C: Compare<T>
 {
     type Item = &'a T;
+    #[allow(clippy::never_loop)]
     fn next(&mut self) -> Option<&'a T> {
         loop {
             match cmp_opt(self.a.peek(), self.b.peek(), Greater, Less, self.cmp) {
@@ -739,8 +1099,16 @@ impl<'a, T, C> Iterator for Union<'a, T, C>
             }
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (a, b) = (self.a.len(), self.b.len());
+        (cmp::max(a, b), a.checked_add(b))
+    }
 }
 
+impl<'a, T, C: Compare<T>> FusedIterator for Union<'a, T, C> {}
+
+#[allow(clippy::needless_lifetimes)]
 impl<'a, 'b, T, C> ops::BitOr<&'b TreeSet<T, C>> for &'a TreeSet<T, C>
     where T: Clone,
           C: Compare<T> + Eq + Clone
@@ -769,6 +1137,7 @@ impl<'a, 'b, T, C> ops::BitOr<&'b TreeSet<T, C>> for &'a TreeSet<T, C>
     }
 }
 
+#[allow(clippy::needless_lifetimes)]
 impl<'a, 'b, T, C> ops::BitAnd<&'b TreeSet<T, C>> for &'a TreeSet<T, C>
     where T: Clone,
           C: Compare<T> + Eq + Clone
@@ -797,6 +1166,7 @@ impl<'a, 'b, T, C> ops::BitAnd<&'b TreeSet<T, C>> for &'a TreeSet<T, C>
     }
 }
 
+#[allow(clippy::needless_lifetimes)]
 impl<'a, 'b, T, C> ops::BitXor<&'b TreeSet<T, C>> for &'a TreeSet<T, C>
     where T: Clone,
           C: Compare<T> + Eq + Clone
@@ -825,6 +1195,7 @@ impl<'a, 'b, T, C> ops::BitXor<&'b TreeSet<T, C>> for &'a TreeSet<T, C>
     }
 }
 
+#[allow(clippy::needless_lifetimes)]
 impl<'a, 'b, T, C> ops::Sub<&'b TreeSet<T, C>> for &'a TreeSet<T, C>
     where T: Clone,
           C: Compare<T> + Eq + Clone
@@ -853,6 +1224,33 @@ impl<'a, 'b, T, C> ops::Sub<&'b TreeSet<T, C>> for &'a TreeSet<T, C>
     }
 }
 
+/// Builds the tree directly from the set's sorted elements, in O(n).
+impl<T: Ord> From<BTreeSet<T>> for TreeSet<T> {
+    fn from(set: BTreeSet<T>) -> TreeSet<T> {
+        TreeSet { map: TreeMap::from_sorted(set.len(), set.into_iter().map(|x| (x, ())), natural()) }
+    }
+}
+
+impl<T: Ord, C> From<TreeSet<T, C>> for BTreeSet<T>
+    where C: Compare<T>
+{
+    fn from(set: TreeSet<T, C>) -> BTreeSet<T> {
+        set.into_iter().collect()
+    }
+}
+
+impl<T: Ord> PartialEq<BTreeSet<T>> for TreeSet<T> {
+    fn eq(&self, other: &BTreeSet<T>) -> bool {
+        self.len() == other.len() && self.iter().eq(other)
+    }
+}
+
+impl<T: Ord> PartialEq<TreeSet<T>> for BTreeSet<T> {
+    fn eq(&self, other: &TreeSet<T>) -> bool {
+        other == self
+    }
+}
+
 impl<T, C> iter::FromIterator<T> for TreeSet<T, C>
     where C: Compare<T> + Default
 {
@@ -875,13 +1273,12 @@ impl<T, C> Extend<T> for TreeSet<T, C>
 }
 
 
-impl<T: Hash, C> Hash for TreeSet<T, C>
-    where C: Compare<T>
+/// Elements are hashed through the comparator, as for `TreeMap`.
+impl<T, C> Hash for TreeSet<T, C>
+    where C: HashCompare<T>
 {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        for elt in self.iter() {
-            elt.hash(state);
-        }
+        self.map.hash(state)
     }
 }
 
@@ -909,6 +1306,7 @@ impl<T, C> IntoIterator for TreeSet<T, C>
 impl<'a, K> ::ordered_iter::OrderedSetIterator for Iter<'a, K> {}
 
 #[cfg(test)]
+#[allow(deprecated, clippy::explicit_counter_loop, clippy::map_clone, clippy::partialeq_to_none)]
 mod test {
     use std::hash;
     use std::hash::Hasher;
@@ -1030,6 +1428,22 @@ mod test {
         assert_eq!(it.next(), None);
     }
 
+    #[test]
+    fn test_iterators_send_sync() {
+        use super::{Difference, IntoIter, Intersection, Iter, Range, SymmetricDifference, Union};
+        use compare::Natural;
+
+        fn send_sync<T: Send + Sync>() {}
+
+        send_sync::<Iter<String>>();
+        send_sync::<Range<String>>();
+        send_sync::<IntoIter<String>>();
+        send_sync::<Difference<String, Natural<String>>>();
+        send_sync::<SymmetricDifference<String, Natural<String>>>();
+        send_sync::<Intersection<String, Natural<String>>>();
+        send_sync::<Union<String, Natural<String>>>();
+    }
+
     #[test]
     fn test_clone_eq() {
         let mut m = TreeSet::new();
@@ -1056,6 +1470,31 @@ mod test {
         assert!(hash(&x) == hash(&y));
     }
 
+    #[test]
+    fn test_comparator_eq_ord_hash() {
+        use compare::{Compare, natural};
+        use super::super::by_key::by_key;
+
+        // Pairs that agree on their first field are the same element.
+        let cmp = by_key(|p: &(i32, char)| &p.0);
+        let mut x = TreeSet::with_comparator(cmp);
+        let mut y = TreeSet::with_comparator(cmp);
+        x.insert((1, 'a'));
+        x.insert((2, 'b'));
+        y.insert((2, 'z'));
+        y.insert((1, 'y'));
+        assert!(x == y);
+        assert!(hash(&x) == hash(&y));
+        assert_eq!(x.cmp(&y), ::std::cmp::Ordering::Equal);
+
+        let mut z = TreeSet::with_comparator(natural::<i32>().rev());
+        let mut w = TreeSet::with_comparator(natural::<i32>().rev());
+        z.extend(vec![1, 2]);
+        w.extend(vec![1, 3]);
+        assert!(z > w);
+        assert!(z != w);
+    }
+
     fn check<F>(a: &[i32], b: &[i32], expected: &[i32], f: F)
         where F: FnOnce(&TreeSet<i32>, &TreeSet<i32>) -> Vec<i32>
     {
@@ -1144,6 +1583,110 @@ mod test {
                     &[-2, 1, 3, 5, 9, 11, 13, 16, 19, 24]);
     }
 
+    #[test]
+    fn test_incompatible_comparators() {
+        use std::cmp::Ordering;
+        use compare::{Compare, Natural};
+        use super::{ComparatorMismatch, CompatibleWith};
+
+        // Orders ascending or descending, so that two comparators of the same
+        // type can disagree.
+        #[derive(PartialEq, Eq)]
+        struct Direction(bool);
+
+        impl Compare<i32> for Direction {
+            fn compare(&self, l: &i32, r: &i32) -> Ordering {
+                if self.0 { l.cmp(r) } else { r.cmp(l) }
+            }
+        }
+
+        let mut a = TreeSet::with_comparator(Direction(true));
+        let mut b = TreeSet::with_comparator(Direction(false));
+        a.extend(vec![1, 2, 3, 5]);
+        b.extend(vec![2, 3, 4]);
+
+        assert!(a.try_union(&b).is_err());
+        assert_eq!(a.try_is_subset(&b), Err(ComparatorMismatch));
+        assert_eq!(a.union(&b).cloned().collect::<Vec<_>>(), [1, 2, 3, 4, 5]);
+        assert_eq!(b.union(&a).cloned().collect::<Vec<_>>(), [5, 4, 3, 2, 1]);
+        assert_eq!(a.intersection(&b).cloned().collect::<Vec<_>>(), [2, 3]);
+        assert_eq!(a.difference(&b).cloned().collect::<Vec<_>>(), [1, 5]);
+        assert_eq!(a.symmetric_difference(&b).cloned().collect::<Vec<_>>(), [1, 4, 5]);
+        assert!(!a.is_disjoint(&b));
+        b.remove(&4);
+        assert!(b.is_subset(&a) && a.is_superset(&b));
+        assert!(!a.is_subset(&b) && !b.is_superset(&a));
+
+        // Different types: tens compares by `x / 10`, so two of `b`'s
+        // elements are the same element to it.
+        struct Tens;
+
+        impl Compare<i32> for Tens {
+            fn compare(&self, l: &i32, r: &i32) -> Ordering {
+                (l / 10).cmp(&(r / 10))
+            }
+        }
+
+        impl CompatibleWith<Natural<i32>> for Tens {
+            fn compatible_with(&self, _: &Natural<i32>) -> bool {
+                false
+            }
+        }
+
+        let mut tens = TreeSet::with_comparator(Tens);
+        tens.extend(vec![10, 20]);
+        let b: TreeSet<i32> = vec![21, 25, 30].into_iter().collect();
+        assert_eq!(tens.try_intersection(&b).err(), Some(ComparatorMismatch));
+        assert_eq!(tens.union(&b).cloned().collect::<Vec<_>>(), [10, 20, 30]);
+        let union = tens.union(&b);
+        // `b` is down to two elements once sorted.
+        assert_eq!(union.size_hint(), (2, Some(4)));
+        assert_eq!(tens.symmetric_difference(&b).cloned().collect::<Vec<_>>(), [10, 30]);
+        assert!(tens.try_is_disjoint(&b).is_err());
+    }
+
+    #[test]
+    fn test_size_hints() {
+        use super::super::Bound;
+
+        // Checks that the bounds of `iter` hold at every step.
+        fn check_hints<'a, I: Iterator<Item = &'a i32>>(mut iter: I) {
+            let mut hints = vec![iter.size_hint()];
+            while iter.next().is_some() {
+                hints.push(iter.size_hint());
+            }
+            let count = hints.len() - 1;
+            for (i, &(lo, hi)) in hints.iter().enumerate() {
+                assert!(lo <= count - i && count - i <= hi.unwrap());
+            }
+            assert!(iter.next().is_none());
+        }
+
+        let sets: Vec<TreeSet<i32>> = vec![TreeSet::new(),
+                                           (0..10).collect(),
+                                           (5..8).collect(),
+                                           (0..30).filter(|x| x % 3 == 0).collect(),
+                                           (20..40).collect()];
+        for a in sets.iter() {
+            assert_eq!(a.iter().len(), a.len());
+            assert_eq!(a.clone().into_iter().len(), a.len());
+            check_hints(a.range(Bound::Included(&5), Bound::Unbounded));
+            for b in sets.iter() {
+                check_hints(a.difference(b));
+                check_hints(a.symmetric_difference(b));
+                check_hints(a.intersection(b));
+                check_hints(a.union(b));
+            }
+        }
+
+        let a: TreeSet<i32> = (0..10).collect();
+        let b: TreeSet<i32> = (5..8).collect();
+        assert_eq!(a.difference(&b).size_hint(), (7, Some(10)));
+        assert_eq!(a.symmetric_difference(&b).size_hint(), (7, Some(13)));
+        assert_eq!(a.intersection(&b).size_hint(), (0, Some(3)));
+        assert_eq!(a.union(&b).size_hint(), (10, Some(13)));
+    }
+
     #[test]
     fn test_bit_or() {
         let a: TreeSet<i32> = vec![1, 3, 5, 9, 11, 16, 19, 24].into_iter().collect();
@@ -1230,6 +1773,7 @@ mod test {
     #[test]
     fn test_comparator_iterator() {
         use compare::{Compare, natural};
+        use super::super::Bound;
 
         let mut m = TreeSet::with_comparator(natural().rev());
 
@@ -1245,6 +1789,11 @@ mod test {
             assert_eq!(t, n);
         }
         assert_eq!(n, 0);
+
+        let mut range = m.range(Bound::Unbounded, Bound::Included(&1));
+        range.seek(&2);
+        assert_eq!(range.len(), 2);
+        assert_eq!(range.next(), Some(&2));
     }
 
     #[test]
@@ -0,0 +1,170 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Parallel iteration over a `TreeSet` with rayon, on top of the parallel
+// iterators of the underlying `TreeMap`.
+
+use compare::Compare;
+use rayon::iter::plumbing::UnindexedConsumer;
+use rayon::prelude::*;
+
+use super::TreeSet;
+use super::super::Bound;
+use super::super::map;
+
+impl<T, C> TreeSet<T, C>
+    where C: Compare<T>
+{
+    /// Constructs a parallel iterator over a sub-range of elements in the set,
+    /// with the same meaning of `min` and `max` as `range`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// extern crate rayon;
+    /// extern crate stable_bst;
+    ///
+    /// use rayon::prelude::*;
+    /// use stable_bst::TreeSet;
+    /// use stable_bst::Bound::{Included, Unbounded};
+    ///
+    /// # fn main() {
+    /// let set: TreeSet<u32> = (0..1000).collect();
+    /// assert_eq!(set.par_range(Included(&990), Unbounded).count(), 10);
+    /// # }
+    /// ```
+    pub fn par_range<'a, Min: ?Sized, Max: ?Sized>(&'a self,
+                                                   min: Bound<&Min>,
+                                                   max: Bound<&Max>)
+                                                   -> ParRange<'a, T>
+        where C: Compare<Min, T> + Compare<Max, T>
+    {
+        ParRange { range: self.map.par_range(min, max) }
+    }
+}
+
+/// A parallel iterator over the values of a `TreeSet`.
+pub struct ParIter<'a, T: 'a> {
+    iter: map::ParIter<'a, T, ()>,
+}
+
+/// A parallel iterator over a sub-range of a `TreeSet`.
+pub struct ParRange<'a, T: 'a> {
+    range: map::ParRange<'a, T, ()>,
+}
+
+/// A parallel iterator that consumes a `TreeSet`.
+pub struct IntoParIter<T> {
+    iter: map::IntoParIter<T, ()>,
+}
+
+impl<'a, T: Sync> ParallelIterator for ParIter<'a, T> {
+    type Item = &'a T;
+
+    fn drive_unindexed<Co>(self, consumer: Co) -> Co::Result
+        where Co: UnindexedConsumer<Self::Item>
+    {
+        self.iter.map(|(value, _)| value).drive_unindexed(consumer)
+    }
+}
+
+impl<'a, T: Sync> ParallelIterator for ParRange<'a, T> {
+    type Item = &'a T;
+
+    fn drive_unindexed<Co>(self, consumer: Co) -> Co::Result
+        where Co: UnindexedConsumer<Self::Item>
+    {
+        self.range.map(|(value, _)| value).drive_unindexed(consumer)
+    }
+}
+
+impl<T: Send> ParallelIterator for IntoParIter<T> {
+    type Item = T;
+
+    fn drive_unindexed<Co>(self, consumer: Co) -> Co::Result
+        where Co: UnindexedConsumer<Self::Item>
+    {
+        self.iter.map(|(value, _)| value).drive_unindexed(consumer)
+    }
+}
+
+impl<'a, T: Sync, C> IntoParallelIterator for &'a TreeSet<T, C>
+    where C: Compare<T>
+{
+    type Item = &'a T;
+    type Iter = ParIter<'a, T>;
+
+    fn into_par_iter(self) -> ParIter<'a, T> {
+        ParIter { iter: self.map.par_iter() }
+    }
+}
+
+impl<T: Send, C> IntoParallelIterator for TreeSet<T, C>
+    where C: Compare<T>
+{
+    type Item = T;
+    type Iter = IntoParIter<T>;
+
+    fn into_par_iter(self) -> IntoParIter<T> {
+        IntoParIter { iter: self.map.into_par_iter() }
+    }
+}
+
+/// Sorts the values in parallel and builds the tree from them in one pass, as
+/// for `TreeMap`.
+impl<T: Send, C> ParallelExtend<T> for TreeSet<T, C>
+    where C: Compare<T> + Sync
+{
+    fn par_extend<I>(&mut self, par_iter: I)
+        where I: IntoParallelIterator<Item = T>
+    {
+        self.map.par_extend(par_iter.into_par_iter().map(|value| (value, ())));
+    }
+}
+
+impl<T: Send, C> FromParallelIterator<T> for TreeSet<T, C>
+    where C: Compare<T> + Default + Sync
+{
+    fn from_par_iter<I>(par_iter: I) -> TreeSet<T, C>
+        where I: IntoParallelIterator<Item = T>
+    {
+        let mut set: TreeSet<T, C> = Default::default();
+        set.par_extend(par_iter);
+        set
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rayon::prelude::*;
+
+    use super::super::TreeSet;
+    use super::super::super::Bound;
+
+    #[test]
+    fn test_par_iter() {
+        let s: TreeSet<i32> = (0..10_000).collect();
+        let par: Vec<&i32> = s.par_iter().collect();
+        assert_eq!(par, s.iter().collect::<Vec<_>>());
+        assert_eq!(s.par_range(Bound::Excluded(&10), Bound::Included(&20)).count(), 10);
+        assert_eq!(s.into_par_iter().filter(|&x| x % 2 == 0).count(), 5000);
+    }
+
+    #[test]
+    fn test_from_par_iter() {
+        let s: TreeSet<i32> = (0..1000).into_par_iter().map(|i| i % 300).collect();
+        assert_eq!(s.len(), 300);
+        assert!(s.iter().cloned().eq(0..300));
+
+        let mut s = s;
+        s.par_extend((250..400).into_par_iter());
+        assert!(s.iter().cloned().eq(0..400));
+    }
+}
@@ -0,0 +1,658 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A concurrent ordered map that partitions its keys into `TreeMap` shards.
+
+use std::cmp::Ordering::{Less, Greater};
+use std::sync::{Arc, RwLock};
+use std::vec;
+
+use compare::{Compare, Natural, natural};
+
+use super::Bound;
+use super::TreeMap;
+
+/// An ordered map that can be shared between threads, made of `TreeMap`
+/// shards that each cover a contiguous range of keys.
+///
+/// Every shard sits behind its own `RwLock`, so threads working on keys in
+/// different shards do not contend with each other. The shards are delimited
+/// by split points: a shard holds the keys from its split point (inclusive) up
+/// to the next one (exclusive), and the first shard holds everything below the
+/// first split point.
+///
+/// Once a shard grows past the length set with `set_max_shard_len`, it is
+/// split in two at its median key, which adds a split point. Split points are
+/// never removed.
+///
+/// Every method that takes a lock panics if that lock has been poisoned by a
+/// thread that panicked while holding it.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::thread;
+/// use stable_bst::ShardedTreeMap;
+///
+/// let map = ShardedTreeMap::new(vec![1000, 2000, 3000]);
+///
+/// thread::scope(|s| {
+///     for t in 0..4 {
+///         let map = &map;
+///         s.spawn(move || {
+///             for i in t * 1000..(t + 1) * 1000 {
+///                 map.insert(i, i * 2);
+///             }
+///         });
+///     }
+/// });
+///
+/// assert_eq!(map.len(), 4000);
+/// assert_eq!(map.get(&2500), Some(5000));
+/// ```
+pub struct ShardedTreeMap<K, V, C: Compare<K> = Natural<K>> {
+    shards: RwLock<Vec<Shard<K, V, C>>>,
+    cmp: C,
+    max_shard_len: usize,
+}
+
+struct Shard<K, V, C: Compare<K>> {
+    // The smallest key the shard may hold; `None` for the first shard.
+    lower: Option<K>,
+    // Shared so that a shard can stay locked while it is added to the map.
+    map: Arc<RwLock<TreeMap<K, V, C>>>,
+}
+
+impl<K: Ord, V> ShardedTreeMap<K, V> {
+    /// Creates an empty `ShardedTreeMap` ordered according to the natural order
+    /// of its keys, with one shard more than there are split points.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the split points are not in strictly ascending order.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::ShardedTreeMap;
+    ///
+    /// let map: ShardedTreeMap<&str, i32> = ShardedTreeMap::new(vec!["m"]);
+    /// assert_eq!(map.shard_count(), 2);
+    /// ```
+    pub fn new(split_points: Vec<K>) -> ShardedTreeMap<K, V> {
+        ShardedTreeMap::with_comparator(split_points, natural())
+    }
+}
+
+impl<K, V, C> ShardedTreeMap<K, V, C>
+    where C: Compare<K> + Clone
+{
+    /// Creates an empty `ShardedTreeMap` ordered according to the given
+    /// comparator, with one shard more than there are split points.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the split points are not in strictly ascending order.
+    #[allow(clippy::redundant_field_names)]
+    pub fn with_comparator(split_points: Vec<K>, cmp: C) -> ShardedTreeMap<K, V, C> {
+        for pair in split_points.windows(2) {
+            assert!(cmp.compares_lt(&pair[0], &pair[1]),
+                    "split points must be strictly ascending");
+        }
+        let mut shards = vec![Shard {
+                                  lower: None,
+                                  map: Arc::new(RwLock::new(TreeMap::with_comparator(cmp.clone()))),
+                              }];
+        for key in split_points {
+            shards.push(Shard {
+                lower: Some(key),
+                map: Arc::new(RwLock::new(TreeMap::with_comparator(cmp.clone()))),
+            });
+        }
+        ShardedTreeMap {
+            shards: RwLock::new(shards),
+            cmp: cmp,
+            max_shard_len: usize::MAX,
+        }
+    }
+
+    /// Returns the comparator according to which the map is ordered.
+    pub fn comparator(&self) -> &C {
+        &self.cmp
+    }
+
+    /// Sets the number of entries past which a shard is split in two. The
+    /// default is `usize::MAX`, which never splits.
+    ///
+    /// Shards that are already too large are split by the next insertion.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::ShardedTreeMap;
+    ///
+    /// let mut map = ShardedTreeMap::new(vec![]);
+    /// map.set_max_shard_len(100);
+    /// for i in 0..1000 {
+    ///     map.insert(i, ());
+    /// }
+    /// assert!(map.shard_count() >= 10);
+    /// ```
+    pub fn set_max_shard_len(&mut self, len: usize) {
+        assert!(len > 0, "shards must be allowed to hold an entry");
+        self.max_shard_len = len;
+    }
+
+    /// Returns the number of shards the map currently has.
+    pub fn shard_count(&self) -> usize {
+        self.shards.read().unwrap().len()
+    }
+
+    /// Returns the current split points, in ascending order.
+    pub fn split_points(&self) -> Vec<K>
+        where K: Clone
+    {
+        self.shards.read().unwrap().iter().filter_map(|s| s.lower.clone()).collect()
+    }
+
+    /// Returns the number of entries in the map.
+    ///
+    /// The shards are counted one after the other, so insertions and removals
+    /// running at the same time may or may not be included.
+    pub fn len(&self) -> usize {
+        let shards = self.shards.read().unwrap();
+        let mut len = 0;
+        for (i, shard) in shards.iter().enumerate() {
+            let map = shard.map.read().unwrap();
+            // A shard that is being split still holds the entries that belong
+            // in the next one, until they are moved there.
+            len += match shards.get(i + 1) {
+                Some(next) => map.rank(next.lower.as_ref().unwrap()),
+                None => map.len(),
+            };
+        }
+        len
+    }
+
+    /// Returns true if the map contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.shards.read().unwrap().iter().all(|s| s.map.read().unwrap().is_empty())
+    }
+
+    /// Removes all entries from the map, keeping its split points.
+    pub fn clear(&self) {
+        for shard in self.shards.read().unwrap().iter() {
+            shard.map.write().unwrap().clear();
+        }
+    }
+
+    // Returns the index of the shard that holds `key`.
+    fn shard_index<Q: ?Sized>(&self, shards: &[Shard<K, V, C>], key: &Q) -> usize
+        where C: Compare<Q, K>
+    {
+        let (mut lo, mut hi) = (1, shards.len());
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match self.cmp.compare(key, shards[mid].lower.as_ref().unwrap()) {
+                Less => hi = mid,
+                _ => lo = mid + 1,
+            }
+        }
+        lo - 1
+    }
+
+    /// Returns a copy of the value corresponding to the key.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::ShardedTreeMap;
+    ///
+    /// let map = ShardedTreeMap::new(vec![10]);
+    /// map.insert(12, "a");
+    /// assert_eq!(map.get(&12), Some("a"));
+    /// assert_eq!(map.get(&2), None);
+    /// ```
+    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<V>
+        where C: Compare<Q, K>,
+              V: Clone
+    {
+        let shards = self.shards.read().unwrap();
+        let map = shards[self.shard_index(&shards, key)].map.read().unwrap();
+        map.get(key).cloned()
+    }
+
+    /// Returns true if the map contains a value for the specified key.
+    pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool
+        where C: Compare<Q, K>
+    {
+        let shards = self.shards.read().unwrap();
+        let map = shards[self.shard_index(&shards, key)].map.read().unwrap();
+        map.contains_key(key)
+    }
+
+    /// Inserts a key-value pair into the map, locking only the shard it
+    /// belongs in. If the key already had a value present in the map, that
+    /// value is returned. Otherwise, `None` is returned.
+    ///
+    /// If this makes the shard longer than the maximum shard length, the shard
+    /// is split once the insertion is done. The entries are moved under the
+    /// locks of the two shards involved, and the whole map is only locked to
+    /// add the new shard.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::ShardedTreeMap;
+    ///
+    /// let map = ShardedTreeMap::new(vec![10]);
+    /// assert_eq!(map.insert(37, "a"), None);
+    /// assert_eq!(map.insert(37, "b"), Some("a"));
+    /// assert_eq!(map.get(&37), Some("b"));
+    /// ```
+    pub fn insert(&self, key: K, value: V) -> Option<V>
+        where K: Clone
+    {
+        let (ret, too_long) = {
+            let shards = self.shards.read().unwrap();
+            let mut map = shards[self.shard_index(&shards, &key)].map.write().unwrap();
+            let ret = map.insert(key, value);
+            (ret, map.len() > self.max_shard_len)
+        };
+        if too_long {
+            self.split_long_shards();
+        }
+        ret
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key
+    /// was previously in the map.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::ShardedTreeMap;
+    ///
+    /// let map = ShardedTreeMap::new(vec![10]);
+    /// map.insert(1, "a");
+    /// assert_eq!(map.remove(&1), Some("a"));
+    /// assert_eq!(map.remove(&1), None);
+    /// ```
+    pub fn remove<Q: ?Sized>(&self, key: &Q) -> Option<V>
+        where C: Compare<Q, K>
+    {
+        let shards = self.shards.read().unwrap();
+        let mut map = shards[self.shard_index(&shards, key)].map.write().unwrap();
+        map.remove(key)
+    }
+
+    // Splits every shard that is longer than the maximum at its median key
+    // until none is. Other threads may have split or shrunk the shard that
+    // triggered this in the meantime, so all of them are checked again.
+    //
+    // The whole map is only locked to add the new, still empty shard, which
+    // is locked before it is added so that lookups of the keys it will hold
+    // wait until they have been moved over from the shard being split.
+    fn split_long_shards(&self)
+        where K: Clone
+    {
+        loop {
+            let (old, median) = {
+                let shards = self.shards.read().unwrap();
+                let long = shards.iter().filter_map(|shard| {
+                    let map = shard.map.read().unwrap();
+                    if map.len() <= self.max_shard_len {
+                        return None;
+                    }
+                    let median = map.select(map.len() / 2).unwrap().0.clone();
+                    Some((shard.map.clone(), median))
+                }).next();
+                match long {
+                    Some(long) => long,
+                    None => return,
+                }
+            };
+
+            let new = Arc::new(RwLock::new(TreeMap::with_comparator(self.cmp.clone())));
+            let mut upper = new.write().unwrap();
+            let next = {
+                let mut shards = self.shards.write().unwrap();
+                let i = self.shard_index(&shards, &median);
+                if !Arc::ptr_eq(&shards[i].map, &old) {
+                    // Another thread has split this shard since, and will
+                    // check the shards again once it is done.
+                    return;
+                }
+                shards.insert(i + 1,
+                              Shard {
+                                  lower: Some(median.clone()),
+                                  map: new.clone(),
+                              });
+                shards.get(i + 2).map(|shard| shard.lower.clone().unwrap())
+            };
+
+            // Another thread may also have added a shard for the keys of this
+            // one, above `median`, without having moved them yet. Those keys
+            // are its to move, so only take the keys below its split point.
+            let mut old = old.write().unwrap();
+            *upper = old.split_off(&median);
+            if let Some(next) = next {
+                let rest = upper.split_off(&next);
+                old.extend(rest);
+            }
+        }
+    }
+
+    /// Constructs an iterator over a sub-range of entries in the map, with the
+    /// same meaning of `min` and `max` as `TreeMap::range`. The entries are
+    /// copied out one shard at a time.
+    ///
+    /// Each shard is read under its own lock, so the entries that come from
+    /// one shard are a consistent snapshot of it, but changes made to later
+    /// shards while the iterator is in use may or may not be seen.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::ShardedTreeMap;
+    /// use stable_bst::Bound::{Included, Excluded};
+    ///
+    /// let map = ShardedTreeMap::new(vec![3, 6]);
+    /// for i in 0..10 {
+    ///     map.insert(i, i * 10);
+    /// }
+    ///
+    /// let entries: Vec<_> = map.range(Included(&2), Excluded(&7)).collect();
+    /// assert_eq!(entries, [(2, 20), (3, 30), (4, 40), (5, 50), (6, 60)]);
+    /// ```
+    #[allow(clippy::redundant_field_names)]
+    pub fn range<'a, Min: ?Sized, Max: ?Sized>(&'a self,
+                                               min: Bound<&'a Min>,
+                                               max: Bound<&'a Max>)
+                                               -> Range<'a, K, V, C, Min, Max>
+        where C: Compare<Min, K> + Compare<Max, K>,
+              K: Clone,
+              V: Clone
+    {
+        Range {
+            map: self,
+            min: min,
+            max: max,
+            next: Next::Start,
+            entries: Vec::new().into_iter(),
+        }
+    }
+
+    /// Constructs an iterator over all entries in the map, in ascending order
+    /// of their keys. See `range` for how it behaves alongside changes to the
+    /// map.
+    pub fn iter(&self) -> Range<'_, K, V, C, K, K>
+        where K: Clone,
+              V: Clone
+    {
+        self.range(Bound::Unbounded, Bound::Unbounded)
+    }
+}
+
+/// An iterator over copies of the entries in a sub-range of a
+/// `ShardedTreeMap`.
+pub struct Range<'a, K: 'a, V: 'a, C: 'a + Compare<K>, Min: 'a + ?Sized, Max: 'a + ?Sized> {
+    map: &'a ShardedTreeMap<K, V, C>,
+    min: Bound<&'a Min>,
+    max: Bound<&'a Max>,
+    next: Next<K>,
+    entries: vec::IntoIter<(K, V)>,
+}
+
+// Where a `Range` continues once it has handed out the entries it copied.
+enum Next<K> {
+    Start,
+    // The split point at which the next shard to read starts. Split points are
+    // never removed, so it still starts a shard after any splits.
+    Shard(K),
+    Done,
+}
+
+impl<'a, K, V, C, Min: ?Sized, Max: ?Sized> Range<'a, K, V, C, Min, Max>
+    where C: Compare<K> + Compare<Min, K> + Compare<Max, K> + Clone,
+          K: Clone,
+          V: Clone
+{
+    // Copies the entries in range out of the next shard and works out where to
+    // continue after it.
+    fn read_shard(&mut self) {
+        let cmp = &self.map.cmp;
+        let shards = self.map.shards.read().unwrap();
+        let index = match self.next {
+            Next::Start => {
+                match self.min {
+                    Bound::Unbounded => 0,
+                    Bound::Included(k) | Bound::Excluded(k) => self.map.shard_index(&shards, k),
+                }
+            }
+            Next::Shard(ref lower) => self.map.shard_index(&shards, lower),
+            Next::Done => return,
+        };
+        // A shard that is being split still holds the entries that belong in
+        // the next one, until they are moved there, so stop at its upper end.
+        let upper = shards.get(index + 1).map(|shard| shard.lower.as_ref().unwrap());
+        let below_upper = |k: &K| upper.is_none_or(|upper| cmp.compares_lt(k, upper));
+        let map = shards[index].map.read().unwrap();
+        let entries: Vec<_> = match self.next {
+            Next::Shard(ref lower) => {
+                map.range(Bound::Included(lower), self.max)
+                    .take_while(|&(k, _)| below_upper(k))
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect()
+            }
+            _ => {
+                map.range(self.min, self.max)
+                    .take_while(|&(k, _)| below_upper(k))
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect()
+            }
+        };
+        self.entries = entries.into_iter();
+
+        self.next = match shards.get(index + 1) {
+            None => Next::Done,
+            Some(shard) => {
+                let upper = shard.lower.as_ref().unwrap();
+                let past_max = match self.max {
+                    Bound::Unbounded => false,
+                    Bound::Included(k) => cmp.compare(k, upper) == Less,
+                    Bound::Excluded(k) => cmp.compare(k, upper) != Greater,
+                };
+                if past_max {
+                    Next::Done
+                } else {
+                    Next::Shard(upper.clone())
+                }
+            }
+        };
+    }
+}
+
+impl<'a, K, V, C, Min: ?Sized, Max: ?Sized> Iterator for Range<'a, K, V, C, Min, Max>
+    where C: Compare<K> + Compare<Min, K> + Compare<Max, K> + Clone,
+          K: Clone,
+          V: Clone
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                return Some(entry);
+            }
+            if let Next::Done = self.next {
+                return None;
+            }
+            self.read_shard();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    use super::ShardedTreeMap;
+    use super::super::Bound;
+    use super::super::TreeMap;
+
+    #[test]
+    fn test_basic() {
+        let map = ShardedTreeMap::new(vec![10, 20]);
+        assert!(map.is_empty());
+        for i in (0..30).rev() {
+            assert_eq!(map.insert(i, i), None);
+        }
+        assert_eq!(map.insert(15, 150), Some(15));
+        assert_eq!(map.len(), 30);
+        assert_eq!(map.get(&15), Some(150));
+        assert!(map.contains_key(&29));
+        assert!(!map.contains_key(&30));
+        assert_eq!(map.remove(&10), Some(10));
+        assert_eq!(map.remove(&10), None);
+        assert_eq!(map.get(&10), None);
+        assert_eq!(map.len(), 29);
+        assert_eq!(map.shard_count(), 3);
+
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.split_points(), [10, 20]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_unsorted_split_points() {
+        let _: ShardedTreeMap<i32, ()> = ShardedTreeMap::new(vec![1, 3, 3]);
+    }
+
+    #[test]
+    fn test_range() {
+        let map = ShardedTreeMap::new(vec![10, 20, 30, 40]);
+        let mut expected = TreeMap::new();
+        for i in 0..25 {
+            map.insert(i * 2, i);
+            expected.insert(i * 2, i);
+        }
+
+        let keys = [-1, 0, 9, 10, 11, 20, 29, 30, 48, 49, 50];
+        let mut bounds = vec![Bound::Unbounded];
+        for k in keys.iter() {
+            bounds.push(Bound::Included(k));
+            bounds.push(Bound::Excluded(k));
+        }
+        for &min in bounds.iter() {
+            for &max in bounds.iter() {
+                let got: Vec<_> = map.range(min, max).collect();
+                let want: Vec<_> = expected.range(min, max).map(|(&k, &v)| (k, v)).collect();
+                assert_eq!(got, want);
+            }
+        }
+        assert!(map.iter().eq(expected.into_iter()));
+    }
+
+    #[test]
+    fn test_split_shards() {
+        let mut map = ShardedTreeMap::new(vec![]);
+        map.set_max_shard_len(8);
+        for i in 0..100 {
+            map.insert(i, -i);
+        }
+        assert!(map.shard_count() >= 13);
+        let splits = map.split_points();
+        assert!(splits.windows(2).all(|w| w[0] < w[1]));
+        assert!(map.iter().eq((0..100).map(|i| (i, -i))));
+        for i in 0..100 {
+            assert_eq!(map.get(&i), Some(-i));
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // too slow
+    fn test_concurrent_writers() {
+        let mut map = ShardedTreeMap::new(vec![1000, 2000, 3000]);
+        map.set_max_shard_len(64);
+        let done = AtomicBool::new(false);
+
+        thread::scope(|s| {
+            let writers: Vec<_> = (0..4)
+                .map(|t| {
+                    let map = &map;
+                    s.spawn(move || {
+                        // Entries stay visible while their shard is split.
+                        for i in t * 1000..(t + 1) * 1000 {
+                            map.insert(i, i);
+                            assert_eq!(map.get(&(t * 1000)), Some(t * 1000));
+                        }
+                        for i in (t * 1000..(t + 1) * 1000).filter(|i| i % 3 == 0) {
+                            assert_eq!(map.remove(&i), Some(i));
+                        }
+                    })
+                })
+                .collect();
+
+            // Readers only ever see ascending keys, whatever the writers do.
+            s.spawn(|| {
+                while !done.load(Ordering::SeqCst) {
+                    let keys: Vec<_> = map.iter().map(|(k, _)| k).collect();
+                    assert!(keys.windows(2).all(|w| w[0] < w[1]));
+                    assert!(map.len() <= 4000);
+                }
+            });
+
+            for w in writers {
+                w.join().unwrap();
+            }
+            done.store(true, Ordering::SeqCst);
+        });
+
+        assert!(map.iter().map(|(k, _)| k).eq((0..4000).filter(|i| i % 3 != 0)));
+        assert!(map.shard_count() > 4);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // too slow
+    fn test_concurrent_splits_of_one_shard() {
+        // The threads interleave their keys, so that several of them split
+        // the same shard at once.
+        for _ in 0..20 {
+            let mut map = ShardedTreeMap::new(vec![]);
+            map.set_max_shard_len(4);
+            thread::scope(|s| {
+                for t in 0..4 {
+                    let map = &map;
+                    s.spawn(move || {
+                        for i in 0..500 {
+                            map.insert(i * 4 + t, t);
+                        }
+                    });
+                }
+            });
+
+            assert_eq!(map.len(), 2000);
+            assert!(map.iter().map(|(k, _)| k).eq(0..2000));
+            for k in 0..2000 {
+                assert_eq!(map.get(&k), Some(k % 4));
+                // Lands on the existing entry rather than adding a duplicate.
+                assert_eq!(map.insert(k, k % 4), Some(k % 4));
+            }
+            assert_eq!(map.len(), 2000);
+        }
+    }
+}
@@ -0,0 +1,360 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A window over the most recent samples of a stream, with running quantiles.
+
+use core::fmt::{self, Debug};
+use core::iter::FusedIterator;
+
+use super::map::{self, TreeMap};
+
+/// A window over the most recent samples of a stream that answers order
+/// statistics, such as the running median, in O(log n).
+///
+/// The samples are kept in a `TreeMap` ordered by value and then by arrival,
+/// so that duplicate samples are told apart, and the map's per-subtree counts
+/// find the sample at any rank. A second map from arrival to sample finds the
+/// oldest samples to evict. Pushing a sample beyond the window's capacity
+/// evicts the oldest one, and samples can also be evicted by count or by
+/// value.
+///
+/// # Examples
+///
+/// ```rust
+/// use stable_bst::SlidingWindow;
+///
+/// let mut window = SlidingWindow::new(3);
+/// window.push(5);
+/// window.push(1);
+/// window.push(9);
+/// assert_eq!(window.median(), Some(&5));
+///
+/// // The window is full, so the 5 is evicted.
+/// assert_eq!(window.push(2), Some(5));
+/// assert_eq!(window.median(), Some(&2));
+/// assert_eq!(window.quantile(1.0), Some(&9));
+/// assert_eq!(window.rank_of(&9), 2);
+/// ```
+#[derive(Clone)]
+pub struct SlidingWindow<T: Ord> {
+    samples: TreeMap<(T, u64), ()>,
+    arrivals: TreeMap<u64, T>,
+    next_arrival: u64,
+    capacity: usize,
+}
+
+impl<T: Ord + Clone> SlidingWindow<T> {
+    /// Creates an empty `SlidingWindow` that holds up to `capacity` samples.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    #[allow(clippy::redundant_field_names)]
+    pub fn new(capacity: usize) -> SlidingWindow<T> {
+        assert!(capacity > 0, "a window needs room for at least one sample");
+        SlidingWindow {
+            samples: TreeMap::new(),
+            arrivals: TreeMap::new(),
+            next_arrival: 0,
+            capacity: capacity,
+        }
+    }
+
+    /// Returns the number of samples the window holds before pushing evicts
+    /// the oldest.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of samples in the window.
+    pub fn len(&self) -> usize {
+        self.arrivals.len()
+    }
+
+    /// Returns true if the window contains no samples.
+    pub fn is_empty(&self) -> bool {
+        self.arrivals.is_empty()
+    }
+
+    /// Clears the window, removing all samples.
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.arrivals.clear();
+    }
+
+    /// Adds `sample` to the window. If the window was full, evicts the oldest
+    /// sample and returns it.
+    pub fn push(&mut self, sample: T) -> Option<T> {
+        let evicted = if self.len() == self.capacity {
+            self.pop_oldest()
+        } else {
+            None
+        };
+        self.samples.insert((sample.clone(), self.next_arrival), ());
+        self.arrivals.insert(self.next_arrival, sample);
+        self.next_arrival += 1;
+        evicted
+    }
+
+    /// Returns the oldest sample in the window.
+    pub fn oldest(&self) -> Option<&T> {
+        self.arrivals.first_key_value().map(|(_, sample)| sample)
+    }
+
+    /// Removes the oldest sample from the window, and returns it.
+    pub fn pop_oldest(&mut self) -> Option<T> {
+        let arrival = *self.arrivals.first_key_value()?.0;
+        let sample = self.arrivals.remove(&arrival).unwrap();
+        self.samples.remove(&(sample.clone(), arrival));
+        Some(sample)
+    }
+
+    /// Removes the `n` oldest samples from the window, or all of them if it
+    /// holds fewer, and returns how many were removed.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::SlidingWindow;
+    ///
+    /// let mut window = SlidingWindow::new(10);
+    /// window.extend(vec![4, 8, 15, 16, 23]);
+    /// assert_eq!(window.evict_oldest(2), 2);
+    /// assert_eq!(window.oldest(), Some(&15));
+    /// assert_eq!(window.evict_oldest(5), 3);
+    /// assert!(window.is_empty());
+    /// ```
+    pub fn evict_oldest(&mut self, n: usize) -> usize {
+        let n = n.min(self.len());
+        for _ in 0..n {
+            self.pop_oldest();
+        }
+        n
+    }
+
+    /// Removes the oldest of the samples equal to `sample` from the window,
+    /// and returns true if there was one.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::SlidingWindow;
+    ///
+    /// let mut window = SlidingWindow::new(10);
+    /// window.extend(vec![3, 7, 3]);
+    /// assert!(window.remove(&3));
+    /// assert_eq!(window.oldest(), Some(&7));
+    /// assert_eq!(window.count(&3), 1);
+    /// assert!(!window.remove(&5));
+    /// ```
+    pub fn remove(&mut self, sample: &T) -> bool {
+        let arrival = match self.samples.select(self.rank_of(sample)) {
+            Some((&(ref found, arrival), _)) if found == sample => arrival,
+            _ => return false,
+        };
+        self.samples.remove(&(sample.clone(), arrival));
+        self.arrivals.remove(&arrival);
+        true
+    }
+
+    /// Returns the number of samples in the window that are less than
+    /// `sample`.
+    pub fn rank_of(&self, sample: &T) -> usize {
+        // Arrivals start at 0, so this key sorts before every sample equal to
+        // `sample`.
+        self.samples.rank(&(sample.clone(), 0))
+    }
+
+    /// Returns the number of samples in the window that are equal to
+    /// `sample`.
+    pub fn count(&self, sample: &T) -> usize {
+        self.samples.rank(&(sample.clone(), u64::MAX)) - self.rank_of(sample)
+    }
+
+    /// Returns the `q`-quantile of the samples in the window: the sample at
+    /// index `floor(q * (len - 1))` in ascending order, or `None` if the
+    /// window is empty. A `q` of 0 gives the smallest sample and a `q` of 1
+    /// the largest.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `q` is not between 0 and 1.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use stable_bst::SlidingWindow;
+    ///
+    /// let mut window = SlidingWindow::new(100);
+    /// window.extend(1..101);
+    /// assert_eq!(window.quantile(0.0), Some(&1));
+    /// assert_eq!(window.quantile(0.9), Some(&90));
+    /// assert_eq!(window.quantile(0.99), Some(&99));
+    /// ```
+    pub fn quantile(&self, q: f64) -> Option<&T> {
+        assert!((0.0..=1.0).contains(&q), "quantile {} is not between 0 and 1", q);
+        let last = self.len().checked_sub(1)?;
+        let index = (q * last as f64) as usize;
+        self.samples.select(index.min(last)).map(|((sample, _), _)| sample)
+    }
+
+    /// Returns the median of the samples in the window, the lower of the two
+    /// middle samples if it holds an even number, or `None` if it is empty.
+    pub fn median(&self) -> Option<&T> {
+        let last = self.len().checked_sub(1)?;
+        self.samples.select(last / 2).map(|((sample, _), _)| sample)
+    }
+
+    /// Returns an iterator over the samples in the window in ascending order.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter(self.samples.keys())
+    }
+}
+
+impl<T: Ord> PartialEq for SlidingWindow<T> {
+    fn eq(&self, other: &SlidingWindow<T>) -> bool {
+        self.capacity == other.capacity && self.arrivals.values().eq(other.arrivals.values())
+    }
+}
+
+impl<T: Ord> Eq for SlidingWindow<T> {}
+
+impl<T: Ord + Debug> Debug for SlidingWindow<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
+
+        for (i, sample) in self.arrivals.values().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{:?}", *sample)?;
+        }
+
+        write!(f, "]")
+    }
+}
+
+impl<T: Ord + Clone> Extend<T> for SlidingWindow<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for sample in iter {
+            self.push(sample);
+        }
+    }
+}
+
+impl<'a, T: Ord + Clone> IntoIterator for &'a SlidingWindow<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+/// Lazy forward iterator over the samples of a `SlidingWindow` in ascending
+/// order.
+pub struct Iter<'a, T: 'a>(map::Keys<'a, (T, u64), ()>);
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    #[inline]
+    fn next(&mut self) -> Option<&'a T> {
+        self.0.next().map(|(sample, _)| sample)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, T> ExactSizeIterator for Iter<'a, T> {}
+impl<'a, T> FusedIterator for Iter<'a, T> {}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use rand::{self, Rng};
+
+    use super::SlidingWindow;
+
+    #[test]
+    fn test_duplicates() {
+        let mut window = SlidingWindow::new(4);
+        window.extend(vec![2, 2, 1, 2]);
+        assert_eq!(format!("{:?}", window), "[2, 2, 1, 2]");
+        assert!(window.iter().eq(&[1, 2, 2, 2]));
+        assert_eq!((window.rank_of(&2), window.count(&2)), (1, 3));
+        assert_eq!(window.median(), Some(&2));
+
+        assert_eq!(window.push(0), Some(2));
+        assert!(window.remove(&2));
+        assert_eq!(format!("{:?}", window), "[1, 2, 0]");
+        assert_eq!(window.median(), Some(&1));
+        assert_eq!(window.rank_of(&3), 3);
+
+        window.clear();
+        assert_eq!((window.median(), window.quantile(0.5)), (None, None));
+        assert_eq!(window.pop_oldest(), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_quantile_out_of_range() {
+        let mut window = SlidingWindow::new(1);
+        window.push(1);
+        window.quantile(1.5);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // too slow
+    fn test_random() {
+        let mut rng = rand::weak_rng();
+        let mut window = SlidingWindow::new(30);
+        let mut model: VecDeque<u32> = VecDeque::new();
+
+        for _ in 0..3000 {
+            let sample = rng.gen_range(0, 20u32);
+            match rng.gen_range(0, 8) {
+                0 => {
+                    let n = rng.gen_range(0, 4);
+                    assert_eq!(window.evict_oldest(n), n.min(model.len()));
+                    for _ in 0..n {
+                        model.pop_front();
+                    }
+                }
+                1 => {
+                    let position = model.iter().position(|&x| x == sample);
+                    assert_eq!(window.remove(&sample), position.is_some());
+                    if let Some(position) = position {
+                        model.remove(position);
+                    }
+                }
+                _ => {
+                    let evicted = if model.len() == 30 { model.pop_front() } else { None };
+                    model.push_back(sample);
+                    assert_eq!(window.push(sample), evicted);
+                }
+            }
+
+            let mut sorted: Vec<u32> = model.iter().cloned().collect();
+            sorted.sort();
+            assert!(window.iter().eq(&sorted));
+            assert_eq!(window.oldest(), model.front());
+            assert_eq!(window.median(), sorted.get(sorted.len().wrapping_sub(1) / 2));
+            assert_eq!(window.rank_of(&sample), sorted.iter().filter(|&&x| x < sample).count());
+            for &q in &[0.0, 0.25, 0.9, 1.0] {
+                let index = (q * (sorted.len() as f64 - 1.0)) as usize;
+                assert_eq!(window.quantile(q), sorted.get(index));
+            }
+        }
+    }
+}
@@ -0,0 +1,245 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A trait for writing code once against both `TreeMap` and the standard
+//! library's `BTreeMap`.
+
+use alloc::collections::btree_map::{self, BTreeMap};
+use core::ops;
+
+use compare::Compare;
+
+use super::Bound;
+use super::map::{self, TreeMap};
+
+/// The operations that ordered maps have in common.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::collections::BTreeMap;
+/// use stable_bst::{SortedMap, TreeMap};
+/// use stable_bst::Bound::{Included, Unbounded};
+///
+/// // Sums the values of the keys from `min` up.
+/// fn tail_sum<M: SortedMap<u32, u32>>(map: &M, min: u32) -> u32 {
+///     map.range(Included(&min), Unbounded).map(|(_, v)| v).sum()
+/// }
+///
+/// let tree: TreeMap<u32, u32> = (0..10).map(|i| (i, i)).collect();
+/// let btree: BTreeMap<u32, u32> = (0..10).map(|i| (i, i)).collect();
+/// assert_eq!(tail_sum(&tree, 7), 24);
+/// assert_eq!(tail_sum(&btree, 7), 24);
+/// ```
+pub trait SortedMap<K, V> {
+    /// The iterator returned by `range`.
+    type Range<'a>: DoubleEndedIterator<Item = (&'a K, &'a V)>
+        where Self: 'a,
+              K: 'a,
+              V: 'a;
+
+    /// Returns the number of entries in the map.
+    fn len(&self) -> usize;
+
+    /// Returns `true` if the map contains no entries.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns a reference to the value for `key`.
+    fn get(&self, key: &K) -> Option<&V>;
+
+    /// Returns `true` if the map contains a value for `key`.
+    fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Inserts a key-value pair, returning the value previously stored for the
+    /// key.
+    fn insert(&mut self, key: K, value: V) -> Option<V>;
+
+    /// Removes `key`, returning its value.
+    fn remove(&mut self, key: &K) -> Option<V>;
+
+    /// Returns a double-ended iterator over the entries with keys between
+    /// `min` and `max`, in ascending order. An empty range, such as one whose
+    /// `min` is above its `max`, yields nothing.
+    fn range<'a>(&'a self, min: Bound<&K>, max: Bound<&K>) -> Self::Range<'a>;
+
+    /// Returns the entry with the smallest key.
+    fn first(&self) -> Option<(&K, &V)>;
+
+    /// Returns the entry with the largest key.
+    fn last(&self) -> Option<(&K, &V)>;
+}
+
+impl<K, V, C> SortedMap<K, V> for TreeMap<K, V, C>
+    where C: Compare<K>
+{
+    type Range<'a> = map::Range<'a, K, V, C>
+        where Self: 'a,
+              K: 'a,
+              V: 'a;
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn get(&self, key: &K) -> Option<&V> {
+        self.get(key)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.insert(key, value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        self.remove(key)
+    }
+
+    fn range<'a>(&'a self, min: Bound<&K>, max: Bound<&K>) -> map::Range<'a, K, V, C> {
+        self.range(min, max)
+    }
+
+    fn first(&self) -> Option<(&K, &V)> {
+        self.first_key_value()
+    }
+
+    fn last(&self) -> Option<(&K, &V)> {
+        self.last_key_value()
+    }
+}
+
+impl<K: Ord, V> SortedMap<K, V> for BTreeMap<K, V> {
+    type Range<'a> = btree_map::Range<'a, K, V>
+        where Self: 'a,
+              K: 'a,
+              V: 'a;
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn get(&self, key: &K) -> Option<&V> {
+        self.get(key)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.insert(key, value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        self.remove(key)
+    }
+
+    fn range<'a>(&'a self, min: Bound<&K>, max: Bound<&K>) -> btree_map::Range<'a, K, V> {
+        // `BTreeMap::range` panics on some empty ranges, so those are replaced
+        // by one that it accepts.
+        let empty = match (&min, &max) {
+            (&Bound::Included(lo), &Bound::Included(hi)) |
+            (&Bound::Included(lo), &Bound::Excluded(hi)) |
+            (&Bound::Excluded(lo), &Bound::Included(hi)) => lo > hi,
+            (&Bound::Excluded(lo), &Bound::Excluded(hi)) => lo >= hi,
+            _ => false,
+        };
+        match min {
+            Bound::Included(lo) | Bound::Excluded(lo) if empty => {
+                self.range((ops::Bound::Excluded(lo), ops::Bound::Included(lo)))
+            }
+            _ => self.range((ops::Bound::from(min), ops::Bound::from(max))),
+        }
+    }
+
+    fn first(&self) -> Option<(&K, &V)> {
+        self.first_key_value()
+    }
+
+    fn last(&self) -> Option<(&K, &V)> {
+        self.last_key_value()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use compare::Compare;
+
+    use super::SortedMap;
+    use super::super::Bound::{self, Excluded, Included, Unbounded};
+    use super::super::{TreeMap, TreeSet};
+
+    // Runs the same operations against any `SortedMap`, returning a log of
+    // their results.
+    fn exercise<M: SortedMap<i32, char>>(map: &mut M) -> Vec<String> {
+        let mut log = vec![];
+        for (i, c) in "hello world".chars().enumerate() {
+            log.push(format!("{:?}", map.insert(i as i32 % 7, c)));
+        }
+        log.push(format!("{:?} {:?} {}", map.first(), map.last(), map.len()));
+        log.push(format!("{:?} {:?}", map.remove(&3), map.remove(&3)));
+        log.push(format!("{:?} {}", map.get(&4), map.contains_key(&3)));
+        let bounds: Vec<Bound<i32>> = vec![Included(1), Excluded(1), Included(5), Excluded(5), Unbounded];
+        for &min in &bounds {
+            for &max in &bounds {
+                let (min, max) = (by_ref(&min), by_ref(&max));
+                let forward: Vec<_> = map.range(min, max).collect();
+                let backward: Vec<_> = map.range(min, max).rev().collect();
+                log.push(format!("{:?} {:?}", forward, backward));
+            }
+        }
+        log
+    }
+
+    fn by_ref<T>(bound: &Bound<T>) -> Bound<&T> {
+        match *bound {
+            Included(ref x) => Included(x),
+            Excluded(ref x) => Excluded(x),
+            Unbounded => Unbounded,
+        }
+    }
+
+    #[test]
+    fn test_same_behavior() {
+        let mut tree = TreeMap::new();
+        let mut btree = BTreeMap::new();
+        assert!(SortedMap::is_empty(&tree) && SortedMap::is_empty(&btree));
+        assert_eq!(exercise(&mut tree), exercise(&mut btree));
+        assert!(tree == btree);
+        assert!(btree == tree);
+    }
+
+    #[test]
+    fn test_conversions() {
+        let btree: BTreeMap<i32, i32> = (0..100).map(|i| (i, -i)).collect();
+        let tree = TreeMap::from(btree.clone());
+        assert_eq!(tree.check_invariants(), Ok(()));
+        assert!(tree == btree);
+        assert_eq!(BTreeMap::from(tree), btree);
+
+        let mut tree = TreeMap::from(btree.clone());
+        tree.insert(100, 0);
+        assert!(tree != btree);
+        tree.remove(&100);
+        *tree.get_mut(&5).unwrap() = 0;
+        assert!(btree != tree);
+
+        // A reversed map converts to a map in ascending order.
+        let mut rev = TreeMap::with_comparator(::compare::natural::<i32>().rev());
+        rev.extend(vec![(1, 'a'), (2, 'b')]);
+        assert!(BTreeMap::from(rev).into_iter().eq(vec![(1, 'a'), (2, 'b')]));
+
+        let bset: BTreeSet<i32> = (0..10).collect();
+        let set = TreeSet::from(bset.clone());
+        assert!(set == bset);
+        assert!(bset == set);
+        assert_eq!(BTreeSet::from(set), bset);
+    }
+}
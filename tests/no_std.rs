@@ -0,0 +1,45 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Uses the crate the way a `no_std` user would, through `core` and `alloc`
+// only. Run it with `cargo test --no-default-features` to check that the
+// crate still builds without `std`; building for a target that has no `std`
+// at all is done by CI (see .travis.yml).
+
+#![no_std]
+
+extern crate alloc;
+extern crate compare;
+extern crate stable_bst;
+
+use alloc::vec::Vec;
+use compare::{Compare, natural};
+use stable_bst::{Bound, TreeMap, TreeSet};
+
+#[test]
+fn test_map() {
+    let mut map = TreeMap::new();
+    for i in 0..100u32 {
+        map.insert(i, i * i);
+    }
+    assert_eq!(map.get(&7), Some(&49));
+    assert_eq!(map.remove(&7), Some(49));
+    let range: Vec<_> = map.range(Bound::Excluded(&5), Bound::Included(&9)).map(|(&k, _)| k).collect();
+    assert_eq!(range, [6, 8, 9]);
+    assert_eq!(map.into_iter().count(), 99);
+}
+
+#[test]
+fn test_set_with_comparator() {
+    let mut set = TreeSet::with_comparator(natural().rev());
+    set.extend(0..10u8);
+    assert!(set.iter().cloned().eq((0..10).rev()));
+    assert!(set.comparator().compares_lt(&9, &0));
+}